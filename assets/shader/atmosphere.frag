@@ -11,8 +11,25 @@ layout(location = 0) in VertexData {
     float tang_handedness;
 } vertex;
 
+layout(location = 6) flat in vec4 shell; // center and radius
+layout(location = 7) flat in vec4 atmosphere_color; // color and density
+layout(location = 8) flat in float planet_radius;
+
 layout(location = 0) out vec4 out_color;
 
+// distances along the ray to both intersections with a sphere, negative when missed
+vec2 ray_sphere(vec3 origin, vec3 direction, vec3 sphere_center, float radius) {
+    vec3 offset = origin - sphere_center;
+    float b = dot(offset, direction);
+    float c = dot(offset, offset) - radius * radius;
+    float discriminant = b * b - c;
+    if (discriminant < 0.0) {
+        return vec2(-1.0);
+    }
+    float root = sqrt(discriminant);
+    return vec2(-b - root, -b + root);
+}
+
 vec3 fresnel(float HdotV, vec3 fresnel_base) {
     return fresnel_base + (1.0 - fresnel_base) * pow(1.0 - HdotV, 5.0);
 }
@@ -49,7 +66,10 @@ vec3 compute_light(vec3 attenuation,
 }
 
 void main(){
-    vec3 albedo = vec3(0.123,0.34,0.8);
+    vec3 center = shell.xyz;
+    float atmosphere_radius = shell.w;
+    float density = atmosphere_color.a;
+    vec3 albedo = atmosphere_color.rgb;
     float metallic = 0;
     float roughness = 1;
 
@@ -61,9 +81,20 @@ void main(){
     vec3 vertex_bitangent = normalize(cross(vertex_normal, vertex_tangent) * vertex.tang_handedness);
     /*mat3 vertex_basis = mat3(vertex_tangent, vertex_bitangent, vertex_normal);*/
     //vec3 normal = normalize(vertex_basis);
-    vec3 normal = normalize(vertex.normal);
-
-    vec3 view_direction = normalize(camera_position - vertex.position);
+    // follow the view ray through the shell, from where it enters (or the camera when inside)
+    // to where it leaves or hits the planet, this gives the sky, the horizon haze and the
+    // aerial perspective over the surface from the same parameters
+    vec3 ray_direction = normalize(vertex.position - camera_position);
+    vec2 shell = ray_sphere(camera_position, ray_direction, center, atmosphere_radius);
+    vec2 ground = ray_sphere(camera_position, ray_direction, center, planet_radius);
+    float ray_start = max(shell.x, 0.0);
+    float ray_end = ground.x > 0.0 ? min(shell.y, ground.x) : shell.y;
+    float path_length = max(ray_end - ray_start, 0.0);
+
+    // shade the air at the middle of the path, lit as if it were a surface facing up
+    vec3 sample_position = camera_position + ray_direction * (ray_start + path_length * 0.5);
+    vec3 normal = normalize(sample_position - center);
+    vec3 view_direction = -ray_direction;
     vec3 lighted = vec3(0.0);
     for (int i = 0; i < point_light_count; i++) {
         vec3 light_direction = normalize(plight[i].position - vertex.position);
@@ -152,9 +183,11 @@ void main(){
     }
 
     vec3 ambient = ambient_color * albedo * 1.0;
-    vec3 color = ambient + lighted;
+    vec3 shaded = ambient + lighted;
 
-    float alpha = min((1-dot(normal, view_direction))*0.45,1.0);
+    // density is the optical depth when looking straight through the shell
+    float shell_thickness = max(atmosphere_radius - planet_radius, 0.0001);
+    float alpha = 1.0 - exp(-density * path_length / shell_thickness);
 
-    out_color = vec4(color, alpha);
+    out_color = vec4(shaded, alpha);
 }
\ No newline at end of file
@@ -1,4 +1,4 @@
-// this is just the normal Amethyst pos_norm_tang_tex.vert shader
+// the normal Amethyst pos_norm_tang_tex.vert shader with per instance atmosphere parameters
 #version 450
 
 layout(std140, set = 0, binding = 0) uniform Projview {
@@ -12,7 +12,8 @@ layout(location = 1) in vec3 normal;
 layout(location = 2) in vec4 tangent;
 layout(location = 3) in vec2 tex_coord;
 layout(location = 4) in mat4 model; // instance rate
-layout(location = 8) in vec4 tint; // instance rate
+layout(location = 8) in vec4 atmosphere; // instance rate, color and density
+layout(location = 9) in vec4 ground; // instance rate, planet radius and 1 when drawn at the far plane
 
 layout(location = 0) out VertexData {
     vec3 position;
@@ -23,6 +24,10 @@ layout(location = 0) out VertexData {
     vec4 color;
 } vertex;
 
+layout(location = 6) flat out vec4 shell; // center and radius
+layout(location = 7) flat out vec4 atmosphere_color; // color and density
+layout(location = 8) flat out float planet_radius;
+
 void main() {
     vec4 vertex_position = model * vec4(position, 1.0);
     vertex.position = vertex_position.xyz;
@@ -30,6 +35,13 @@ void main() {
     vertex.tangent = mat3(model) * tangent.xyz;
     vertex.tang_handedness = tangent.w;
     vertex.tex_coord = tex_coord;
-    vertex.color = tint;
+    vertex.color = vec4(atmosphere.rgb, 1.0);
+    shell = vec4(model[3].xyz, length(model[0].xyz));
+    atmosphere_color = atmosphere;
+    planet_radius = ground.x;
     gl_Position = proj_view * vertex_position;
+    // the shell around the camera goes behind everything, the depth test keeps it off the scene
+    if (ground.y > 0.5) {
+        gl_Position.z = gl_Position.w;
+    }
 }
\ No newline at end of file
@@ -0,0 +1,85 @@
+#version 450
+
+#include "header/srgb.frag"
+
+layout(std140, set = 0, binding = 0) uniform DebugViewUniformArgs {
+    uniform mat4 inverse_projection;
+    uniform float screen_width;
+    uniform float screen_height;
+    uniform int mode;
+    uniform float exposure;
+    uniform float znear;
+    uniform float zfar;
+    uniform bool encode_srgb;
+};
+
+// hdr, depth and tonemapped images
+layout(set = 0, binding = 1) uniform sampler2D images[3];
+
+layout(location = 0) in VertexData {
+    vec3 position;
+    vec2 tex_coord;
+} vertex;
+
+layout(location = 0) out vec4 out_color;
+
+#define EDGE_THRESHOLD_MIN 0.0312
+#define EDGE_THRESHOLD_MAX 0.125
+
+// blue through green to red over -8..+8 EV around middle grey
+vec3 false_color(float ev) {
+    float t = clamp((ev + 8.0) / 16.0, 0.0, 1.0);
+    vec3 cold = mix(vec3(0.0, 0.0, 1.0), vec3(0.0, 1.0, 0.0), clamp(t * 2.0, 0.0, 1.0));
+    vec3 hot = mix(vec3(1.0, 1.0, 0.0), vec3(1.0, 0.0, 0.0), clamp(t * 2.0 - 1.0, 0.0, 1.0));
+    return t < 0.5 ? cold : hot;
+}
+
+// view space position of the pixel, from the depth buffer
+vec3 view_position(vec2 tex_coord) {
+    float depth = texture(images[1], tex_coord).r;
+    vec4 position = inverse_projection * vec4(tex_coord * 2.0 - 1.0, depth, 1.0);
+    return position.xyz / position.w;
+}
+
+float luma(vec3 rgb) {
+    return sqrt(dot(rgb, vec3(0.299, 0.587, 0.114)));
+}
+
+// the views are display colours, except the tonemapped image which is linear
+vec3 debug_color(){
+    if(mode == 1){
+        vec3 hdr = texture(images[0], vertex.tex_coord).rgb * exposure;
+        float luminance = dot(hdr, vec3(0.2126, 0.7152, 0.0722));
+        float ev = log2(max(luminance, 0.00001) / 0.18);
+        return false_color(ev);
+    } else if(mode == 2){
+        // log scale so both the cockpit and the sun stay readable
+        float distance = -view_position(vertex.tex_coord).z;
+        float value = log(max(distance, znear) / znear) / log(zfar / znear);
+        return vec3(1.0 - clamp(value, 0.0, 1.0));
+    } else if(mode == 3){
+        vec3 position = view_position(vertex.tex_coord);
+        vec3 normal = normalize(cross(dFdx(position), dFdy(position)));
+        return normal * 0.5 + 0.5;
+    } else if(mode == 4){
+        return srgb_encode(texture(images[2], vertex.tex_coord).rgb);
+    } else {
+        // same edge test as the fxaa pass
+        vec3 center = texture(images[2], vertex.tex_coord).rgb;
+        float luma_center = luma(center);
+        float luma_down = luma(textureLodOffset(images[2], vertex.tex_coord, 0.0, ivec2( 0,-1)).rgb);
+        float luma_up = luma(textureLodOffset(images[2], vertex.tex_coord, 0.0, ivec2( 0, 1)).rgb);
+        float luma_left = luma(textureLodOffset(images[2], vertex.tex_coord, 0.0, ivec2(-1, 0)).rgb);
+        float luma_right = luma(textureLodOffset(images[2], vertex.tex_coord, 0.0, ivec2( 1, 0)).rgb);
+        float luma_min = min(luma_center, min(min(luma_down, luma_up), min(luma_left, luma_right)));
+        float luma_max = max(luma_center, max(max(luma_down, luma_up), max(luma_left, luma_right)));
+        bool edge = luma_max - luma_min >= max(EDGE_THRESHOLD_MIN, luma_max * EDGE_THRESHOLD_MAX);
+        return edge ? vec3(1.0, 0.0, 0.0) : vec3(luma_center * 0.5);
+    }
+}
+
+void main(){
+    // the surface encodes itself when it's srgb
+    vec3 display = debug_color();
+    out_color = vec4(encode_srgb ? display : srgb_decode(display), 1.0);
+}
@@ -0,0 +1,18 @@
+#ifndef SRGB_FRAG
+#define SRGB_FRAG
+
+// the piecewise srgb transfer function, same as render::color in rust
+
+vec3 srgb_encode(vec3 linear) {
+    vec3 low = linear * 12.92;
+    vec3 high = 1.055 * pow(max(linear, vec3(0.0)), vec3(1.0 / 2.4)) - 0.055;
+    return mix(high, low, lessThanEqual(linear, vec3(0.0031308)));
+}
+
+vec3 srgb_decode(vec3 encoded) {
+    vec3 low = encoded / 12.92;
+    vec3 high = pow(max((encoded + 0.055) / 1.055, vec3(0.0)), vec3(2.4));
+    return mix(high, low, lessThanEqual(encoded, vec3(0.04045)));
+}
+
+#endif
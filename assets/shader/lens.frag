@@ -0,0 +1,67 @@
+#version 450
+
+#include "header/srgb.frag"
+
+layout(std140, set = 0, binding = 0) uniform LensUniformArgs {
+    uniform float screen_width;
+    uniform float screen_height;
+    uniform bool enabled;
+    uniform float vignette_intensity;
+    uniform float vignette_smoothness;
+    uniform float chromatic_aberration;
+    uniform float grain_intensity;
+    uniform float grain_size;
+    uniform float time;
+    uniform bool encode_srgb;
+};
+
+layout(set = 0, binding = 1) uniform sampler2D color;
+
+layout(location = 0) in VertexData {
+    vec3 position;
+    vec2 tex_coord;
+} vertex;
+
+layout(location = 0) out vec4 out_color;
+
+float hash(vec2 p) {
+    vec3 p3 = fract(vec3(p.xyx) * 0.1031);
+    p3 += dot(p3, p3.yzx + 33.33);
+    return fract((p3.x + p3.y) * p3.z);
+}
+
+// vignette, chromatic aberration and grain, center is the unshifted colour of the pixel
+vec3 lens(vec3 center){
+    // offset from the center, corrected for the aspect ratio so the vignette stays round
+    vec2 from_center = vertex.tex_coord - 0.5;
+    vec2 aspect = vec2(screen_width / screen_height, 1.0);
+    float radius = length(from_center * aspect) / length(0.5 * aspect);
+
+    // chromatic aberration, red and blue pulled apart along the radius
+    vec2 shift = from_center * chromatic_aberration * radius * 2.0;
+    vec3 result = vec3(
+        texture(color, vertex.tex_coord - shift).r,
+        center.g,
+        texture(color, vertex.tex_coord + shift).b
+    );
+
+    // vignette
+    float vignette = smoothstep(1.0, 1.0 - vignette_smoothness, radius);
+    result *= mix(1.0, vignette, vignette_intensity);
+
+    // film grain, a new pattern every frame, strongest in the midtones like real film
+    vec2 grain_cell = floor(vertex.tex_coord * vec2(screen_width, screen_height) / max(grain_size, 1.0));
+    float noise = hash(grain_cell + fract(time) * 1000.0) - 0.5;
+    float luma = dot(result, vec3(0.2126, 0.7152, 0.0722));
+    result += noise * grain_intensity * (1.0 - abs(luma * 2.0 - 1.0));
+    return result;
+}
+
+void main(){
+    // linear in, the transfer function for the surface is applied at the end
+    vec3 result = texture(color, vertex.tex_coord).rgb;
+    if(enabled){
+        result = lens(result);
+    }
+    out_color = vec4(encode_srgb ? srgb_encode(result) : result, 1.0);
+}
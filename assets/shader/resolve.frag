@@ -0,0 +1,31 @@
+#version 450
+
+layout(std140, set = 0, binding = 0) uniform ResolveUniformArgs {
+    uniform ivec2 grid;
+};
+
+layout(set = 0, binding = 1) uniform sampler2D color;
+
+layout(location = 0) in VertexData {
+    vec3 position;
+    vec2 tex_coord;
+} vertex;
+
+layout(location = 0) out vec4 out_color;
+
+void main(){
+    // each pixel averages a grid of scene samples, weighted by inverse luminance so a single very
+    // bright sample, like the sun behind an edge, doesn't turn the whole pixel white after tonemapping
+    ivec2 origin = ivec2(gl_FragCoord.xy) * grid;
+    vec3 sum = vec3(0.0);
+    float weights = 0.0;
+    for(int y = 0; y < grid.y; y++){
+        for(int x = 0; x < grid.x; x++){
+            vec3 sample_color = texelFetch(color, origin + ivec2(x, y), 0).rgb;
+            float weight = 1.0 / (1.0 + dot(sample_color, vec3(0.2126, 0.7152, 0.0722)));
+            sum += sample_color * weight;
+            weights += weight;
+        }
+    }
+    out_color = vec4(sum / weights, 1.0);
+}
@@ -0,0 +1,79 @@
+#version 450
+
+layout(std140, set = 0, binding = 0) uniform TaaUniformArgs {
+    uniform mat4 reprojection;
+    uniform vec2 jitter;
+    uniform float screen_width;
+    uniform float screen_height;
+    uniform float feedback;
+    uniform bool reset;
+};
+
+// current hdr, depth and history images
+layout(set = 0, binding = 1) uniform sampler2D images[3];
+
+layout(location = 0) in VertexData {
+    vec3 position;
+    vec2 tex_coord;
+} vertex;
+
+layout(location = 0) out vec4 out_color;
+
+// clamping in ycocg keeps the neighbourhood box tight around the luminance
+vec3 rgb_to_ycocg(vec3 rgb) {
+    return vec3(
+        dot(rgb, vec3(0.25, 0.5, 0.25)),
+        dot(rgb, vec3(0.5, 0.0, -0.5)),
+        dot(rgb, vec3(-0.25, 0.5, -0.25))
+    );
+}
+
+vec3 ycocg_to_rgb(vec3 ycocg) {
+    return vec3(
+        ycocg.x + ycocg.y - ycocg.z,
+        ycocg.x + ycocg.z,
+        ycocg.x - ycocg.y - ycocg.z
+    );
+}
+
+// blending weight that keeps bright pixels from dominating, like the sun on the ocean
+float luma_weight(vec3 ycocg) {
+    return 1.0 / (1.0 + ycocg.x);
+}
+
+void main(){
+    // the scene was rendered with a jittered projection, sample where this pixel would have been
+    vec2 uv = vertex.tex_coord + jitter * 0.5;
+    vec3 current = rgb_to_ycocg(texture(images[0], uv).rgb);
+
+    // neighbourhood bounds of the current frame
+    vec2 texel = vec2(1.0 / screen_width, 1.0 / screen_height);
+    vec3 color_min = current;
+    vec3 color_max = current;
+    for(int x = -1; x <= 1; x++){
+        for(int y = -1; y <= 1; y++){
+            vec3 neighbour = rgb_to_ycocg(texture(images[0], uv + vec2(x, y) * texel).rgb);
+            color_min = min(color_min, neighbour);
+            color_max = max(color_max, neighbour);
+        }
+    }
+
+    // where this pixel was last frame, from the depth and the camera motion
+    float depth = texture(images[1], vertex.tex_coord).r;
+    vec4 previous = reprojection * vec4(vertex.tex_coord * 2.0 - 1.0, depth, 1.0);
+    vec2 previous_uv = previous.xy / previous.w * 0.5 + 0.5;
+
+    if(reset || any(lessThan(previous_uv, vec2(0.0))) || any(greaterThan(previous_uv, vec2(1.0)))){
+        out_color = vec4(ycocg_to_rgb(current), 1.0);
+        return;
+    }
+
+    // history clamped to what's plausible given the current frame, removes most ghosting
+    vec3 history = rgb_to_ycocg(texture(images[2], previous_uv).rgb);
+    history = clamp(history, color_min, color_max);
+
+    float current_weight = (1.0 - feedback) * luma_weight(current);
+    float history_weight = feedback * luma_weight(history);
+    vec3 result = (current * current_weight + history * history_weight) / (current_weight + history_weight);
+    out_color = vec4(ycocg_to_rgb(result), 1.0);
+}
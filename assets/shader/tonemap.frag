@@ -1,11 +1,27 @@
 #version 450
 
+#include "header/srgb.frag"
+
 layout(std140, set = 0, binding = 0) uniform TonemapUniformArgs {
     uniform bool enabled;
     uniform float exposure;
+    uniform bool grading;
+    uniform float lut_strength;
+    uniform vec3 lut_domain_min;
+    uniform float lut_size;
+    uniform vec3 lut_domain_max;
+    uniform float contrast;
+    uniform vec3 white_balance;
+    uniform float saturation;
+    uniform vec3 lift;
+    uniform bool dither;
+    uniform vec3 gamma;
+    uniform float frame;
+    uniform vec3 gain;
 };
 
 layout(set = 0, binding = 1) uniform sampler2D color;
+layout(set = 0, binding = 2) uniform sampler3D lut;
 
 layout(location = 0) in VertexData {
     vec3 position;
@@ -14,17 +30,53 @@ layout(location = 0) in VertexData {
 
 layout(location = 0) out vec4 out_color;
 
+// white balance, contrast, saturation and lift/gamma/gain on the tonemapped linear colour
+vec3 grade(vec3 color) {
+    color *= white_balance;
+    // contrast pivots around middle grey
+    color = (color - 0.18) * contrast + 0.18;
+    float luma = dot(color, vec3(0.2126, 0.7152, 0.0722));
+    color = mix(vec3(luma), color, saturation);
+    color = gain * (color + lift * (1.0 - color));
+    return pow(max(color, vec3(0.0)), 1.0 / gamma);
+}
+
+// the lut is applied to the srgb encoded colour, like grading tools author them
+vec3 apply_lut(vec3 color) {
+    vec3 coord = clamp((color - lut_domain_min) / (lut_domain_max - lut_domain_min), 0.0, 1.0);
+    // sample at texel centers so the ends of the range map to the first and last entries
+    coord = coord * ((lut_size - 1.0) / lut_size) + 0.5 / lut_size;
+    return mix(color, texture(lut, coord).rgb, lut_strength);
+}
+
+// triangular noise of one 8 bit step, hides banding in slow gradients like the sky
+vec3 dither_noise(vec2 pixel) {
+    vec2 seed = pixel + vec2(frame * 5.588238);
+    float a = fract(52.9829189 * fract(dot(seed, vec2(0.06711056, 0.00583715))));
+    float b = fract(52.9829189 * fract(dot(seed + vec2(47.0, 17.0), vec2(0.06711056, 0.00583715))));
+    return vec3(a + b - 1.0) / 255.0;
+}
+
 void main(){
     if(!enabled){
         out_color = texture(color, vertex.tex_coord);
         return;
     }
-    
-    const float gamma = 2.2;
+
     vec3 hdr = texture(color, vertex.tex_coord).rgb;
     // exposure tone mapping
     vec3 mapped = vec3(1.0) - exp(-hdr * exposure);
-    // gamma correction
-    mapped = pow(mapped, vec3(1.0 / gamma));
-    out_color = vec4(mapped, 1.0);
-}
\ No newline at end of file
+    if(grading){
+        mapped = grade(mapped);
+    }
+    // the lut and the dither work on the encoded colour, the output is an srgb image which
+    // encodes on write, so it's decoded back to linear for the following passes
+    vec3 encoded = srgb_encode(mapped);
+    if(grading){
+        encoded = apply_lut(encoded);
+    }
+    if(dither){
+        encoded += dither_noise(gl_FragCoord.xy);
+    }
+    out_color = vec4(srgb_decode(encoded), 1.0);
+}
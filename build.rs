@@ -0,0 +1,50 @@
+// compiles the shaders in assets/shader to spir-v, the tests of the passes check their uniform blocks
+// against the rust structs
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+#[path = "src/render/shader_compile.rs"]
+mod shader_compile;
+
+use shader_compile::compile;
+
+fn main() {
+    let shader_dir = PathBuf::from("assets/shader");
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    println!("cargo:rerun-if-changed={}", shader_dir.display());
+    println!("cargo:rerun-if-changed={}", shader_dir.join("header").display());
+
+    let mut errors = Vec::new();
+
+    let mut shaders = fs::read_dir(&shader_dir)
+        .expect("Failed to read assets/shader")
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| matches!(p.extension().and_then(|e| e.to_str()), Some("vert") | Some("frag")))
+        .collect::<Vec<_>>();
+    shaders.sort();
+
+    for path in shaders {
+        println!("cargo:rerun-if-changed={}", path.display());
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let included = |include: &Path| println!("cargo:rerun-if-changed={}", include.display());
+        match compile(&path, &included) {
+            Ok(spirv) => {
+                fs::write(out_dir.join(format!("{}.spv", name)), &spirv)
+                    .expect("Failed to write compiled shader");
+            },
+            Err(error) => errors.push(error),
+        }
+    }
+
+    if !errors.is_empty() {
+        for error in &errors {
+            eprintln!("error: {}", error);
+        }
+        process::exit(1);
+    }
+}
@@ -0,0 +1,696 @@
+use amethyst::{
+    config::Config,
+    core::{
+        shrev::{EventChannel, ReaderId},
+        HiddenPropagate,
+    },
+    derive::SystemDesc,
+    ecs::prelude::{ Read, ReadExpect, ReadStorage, System, SystemData, Write, WriteStorage },
+    input::{Axis, Bindings, Button, InputEvent, InputHandler, StringBindings},
+    ui::{UiEvent, UiEventType, UiFinder, UiText, UiTransform},
+    winit::{MouseButton, VirtualKeyCode},
+    Error,
+};
+use std::{fs, io, path::{Path, PathBuf}};
+use crate::menu::MenuState;
+
+// the bindings from assets, before the user overrides are layered on top
+pub struct DefaultBindings {
+    pub bindings: Bindings<StringBindings>,
+}
+
+// loads the default bindings and layers the user overrides on top, returns both
+pub fn load(defaults_path: &Path, overrides_path: &Path) -> Result<(Bindings<StringBindings>, Bindings<StringBindings>), Error> {
+    let defaults = Bindings::<StringBindings>::load(defaults_path)?;
+    if !overrides_path.exists() {
+        return Ok((defaults.clone(), defaults));
+    }
+    let overrides = Bindings::<StringBindings>::load(overrides_path)?;
+    let layered = layered(&defaults, &overrides);
+    Ok((defaults, layered))
+}
+
+// every action and axis in the overrides replaces the one with the same name
+pub fn layered(defaults: &Bindings<StringBindings>, overrides: &Bindings<StringBindings>) -> Bindings<StringBindings> {
+    let mut bindings = defaults.clone();
+    for action in overrides.actions() {
+        let combos = overrides.action_bindings(action).map(|combo| combo.to_vec()).collect::<Vec<_>>();
+        if let Err(error) = set_action(&mut bindings, action, combos) {
+            log::warn!("Ignoring the binding override for {}: {}", action, error);
+        }
+    }
+    for axis in overrides.axes() {
+        if let Some(override_axis) = overrides.axis(axis) {
+            if let Err(error) = set_axis(&mut bindings, axis, override_axis.clone()) {
+                log::warn!("Ignoring the binding override for {}: {}", axis, error);
+            }
+        }
+    }
+    bindings
+}
+
+// the actions and axes that differ from the defaults
+pub fn overrides(defaults: &Bindings<StringBindings>, bindings: &Bindings<StringBindings>) -> Bindings<StringBindings> {
+    let mut overrides = Bindings::<StringBindings>::default();
+    for action in bindings.actions() {
+        let current = bindings.action_bindings(action).collect::<Vec<_>>();
+        if current != defaults.action_bindings(action).collect::<Vec<_>>() {
+            for binding in current {
+                let _ = overrides.insert_action_binding(action.clone(), binding.iter().cloned());
+            }
+        }
+    }
+    for axis in bindings.axes() {
+        if bindings.axis(axis) != defaults.axis(axis) {
+            if let Some(current) = bindings.axis(axis) {
+                let _ = overrides.insert_axis(axis.clone(), current.clone());
+            }
+        }
+    }
+    overrides
+}
+
+fn clear_action(bindings: &mut Bindings<StringBindings>, action: &str) {
+    let existing = bindings.action_bindings(action).map(|b| b.to_vec()).collect::<Vec<_>>();
+    for binding in existing {
+        bindings.remove_action_binding(action, &binding);
+    }
+}
+
+// replaces the combos of an action, leaves the ones it had when any of the new ones can't be bound
+fn set_action(bindings: &mut Bindings<StringBindings>, action: &str, combos: Vec<Vec<Button>>) -> Result<(), String> {
+    let previous = bindings.action_bindings(action).map(|combo| combo.to_vec()).collect::<Vec<_>>();
+    clear_action(bindings, action);
+    for combo in combos {
+        if let Err(error) = bindings.insert_action_binding(action.to_string(), combo) {
+            clear_action(bindings, action);
+            for combo in previous {
+                let _ = bindings.insert_action_binding(action.to_string(), combo);
+            }
+            return Err(format!("{:?}", error));
+        }
+    }
+    Ok(())
+}
+
+// replaces the binding of an axis, leaves the one it had when the new one can't be bound
+fn set_axis(bindings: &mut Bindings<StringBindings>, axis: &str, binding: Axis) -> Result<(), String> {
+    // the axis is taken out first so its own buttons don't count as conflicts
+    let previous = bindings.remove_axis(axis);
+    match bindings.insert_axis(axis.to_string(), binding) {
+        Ok(_) => Ok(()),
+        Err(error) => {
+            if let Some(previous) = previous {
+                let _ = bindings.insert_axis(axis.to_string(), previous);
+            }
+            Err(format!("{:?}", error))
+        },
+    }
+}
+
+// what an action or axis is used for, the help panel is grouped by these
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Context {
+    General,
+    Flight,
+    Camera,
+    Debug,
+}
+
+impl Context {
+    pub const ALL: [Context; 4] = [Context::General, Context::Flight, Context::Camera, Context::Debug];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Context::General => "General",
+            Context::Flight => "Flight",
+            Context::Camera => "Camera",
+            Context::Debug => "Debug",
+        }
+    }
+}
+
+// every action and axis in input.ron, add new ones here so they show up in the help panel
+pub const DESCRIPTIONS: [(&str, Context, &str); 27] = [
+    ("help", Context::General, "toggle help panel"),
+    ("settings", Context::General, "open settings"),
+    ("hud", Context::Flight, "toggle flight hud"),
+    ("map", Context::Flight, "toggle the orbital map"),
+    ("warp_up", Context::Flight, "speed up time"),
+    ("warp_down", Context::Flight, "slow down time"),
+    ("throttle", Context::Flight, "throttle"),
+    ("move_x", Context::Flight, "move sideways"),
+    ("move_y", Context::Flight, "move up and down"),
+    ("move_z", Context::Flight, "move forwards and backwards"),
+    ("boost", Context::Flight, "move faster"),
+    ("zoom", Context::Camera, "zoom"),
+    ("camera_mode", Context::Camera, "switch between orbit, free-fly and chase"),
+    ("focus_next", Context::Camera, "focus the next body"),
+    ("focus_pick", Context::Camera, "focus the body under the cursor"),
+    ("cursor", Context::Camera, "free the cursor to point at things"),
+    ("camera_yaw", Context::Camera, "orbit sideways"),
+    ("camera_pitch", Context::Camera, "orbit up and down"),
+    ("debug_view", Context::Debug, "cycle debug views"),
+    ("lens", Context::Debug, "toggle lens effects"),
+    ("grading", Context::Debug, "toggle colour grading"),
+    ("tonemap", Context::Debug, "toggle tonemapping"),
+    ("exposure_decr", Context::Debug, "decrease exposure"),
+    ("exposure_incr", Context::Debug, "increase exposure"),
+    ("anti_aliasing", Context::Debug, "cycle anti-aliasing"),
+    ("debuglines", Context::Debug, "toggle debug lines"),
+    ("fps", Context::Debug, "toggle framerate"),
+];
+
+pub fn description(name: &str) -> Option<(Context, &'static str)> {
+    DESCRIPTIONS.iter().find(|(n, _, _)| *n == name).map(|(_, context, description)| (*context, *description))
+}
+
+// help panel text for the current bindings, anything without a description is listed by name
+pub fn help_text(bindings: &Bindings<StringBindings>) -> String {
+    let mut text = "Shortcuts:".to_string();
+    let mut undescribed = Vec::new();
+    for context in Context::ALL.iter() {
+        let mut lines = Vec::new();
+        for (name, _, description) in DESCRIPTIONS.iter().filter(|(_, c, _)| c == context) {
+            let bound = match bindings.axis(*name) {
+                Some(axis) => axis_name(axis),
+                None => binding_name(bindings, &Target::Action(name.to_string())),
+            };
+            if bound != "unbound" {
+                lines.push(format!(" {} - {}", bound, description));
+            }
+        }
+        if !lines.is_empty() {
+            text.push_str(&format!("\n{}:\n{}", context.name(), lines.join("\n")));
+        }
+    }
+    for name in bindings.actions().chain(bindings.axes()) {
+        if description(name).is_none() {
+            undescribed.push(name.clone());
+        }
+    }
+    if !undescribed.is_empty() {
+        undescribed.sort();
+        text.push_str(&format!("\nOther:\n {}", undescribed.join("\n ")));
+    }
+    text
+}
+
+// something that can be rebound, emulated axes are rebound one direction at a time
+#[derive(Clone, Debug, PartialEq)]
+enum Target {
+    Action(String),
+    AxisButton(String, bool),
+    Axis(String),
+}
+
+impl Target {
+    // the description when there is one
+    fn name(&self) -> String {
+        let name = |id: &str| description(id).map_or_else(|| id.to_string(), |(_, description)| description.to_string());
+        match self {
+            Target::Action(action) => name(action),
+            Target::AxisButton(axis, true) => format!("{} +", name(axis)),
+            Target::AxisButton(axis, false) => format!("{} -", name(axis)),
+            Target::Axis(axis) => name(axis),
+        }
+    }
+}
+
+// everything in the bindings, actions first, sorted by name so the list doesn't jump around
+fn targets(bindings: &Bindings<StringBindings>) -> Vec<Target> {
+    let mut actions = bindings.actions().cloned().collect::<Vec<_>>();
+    actions.sort();
+    let mut axes = bindings.axes().cloned().collect::<Vec<_>>();
+    axes.sort();
+
+    let mut targets = actions.into_iter().map(Target::Action).collect::<Vec<_>>();
+    for axis in axes {
+        let binding = match bindings.axis(&axis) {
+            Some(binding) => binding,
+            None => continue,
+        };
+        if emulated(binding).is_some() {
+            targets.push(Target::AxisButton(axis.clone(), true));
+            targets.push(Target::AxisButton(axis.clone(), false));
+        }
+        if controller(binding).is_some() || emulated(binding).is_none() {
+            targets.push(Target::Axis(axis));
+        }
+    }
+    targets
+}
+
+// the button pair of an axis, axes bound to both keys and a stick are a multiple axis
+pub fn emulated(axis: &Axis) -> Option<(&Button, &Button)> {
+    match axis {
+        Axis::Emulated { pos, neg } => Some((pos, neg)),
+        Axis::Multiple(axes) => axes.iter().find_map(emulated),
+        _ => None,
+    }
+}
+
+fn emulated_mut(axis: &mut Axis) -> Option<(&mut Button, &mut Button)> {
+    match axis {
+        Axis::Emulated { pos, neg } => Some((pos, neg)),
+        Axis::Multiple(axes) => axes.iter_mut().find_map(emulated_mut),
+        _ => None,
+    }
+}
+
+fn controller(axis: &Axis) -> Option<&Axis> {
+    match axis {
+        Axis::Controller { .. } => Some(axis),
+        Axis::Multiple(axes) => axes.iter().find_map(controller),
+        _ => None,
+    }
+}
+
+fn controller_mut(axis: &mut Axis) -> Option<&mut Axis> {
+    match axis {
+        Axis::Controller { .. } => Some(axis),
+        Axis::Multiple(axes) => axes.iter_mut().find_map(controller_mut),
+        _ => None,
+    }
+}
+
+pub fn button_name(button: &Button) -> String {
+    match button {
+        Button::Key(key) => format!("{:?}", key),
+        Button::ScanCode(code) => format!("Scan {}", code),
+        Button::Mouse(button) => format!("Mouse {:?}", button),
+        Button::MouseWheel(direction) => format!("Wheel {:?}", direction),
+        Button::Controller(id, button) => format!("Pad {} {:?}", id, button),
+    }
+}
+
+pub fn axis_name(axis: &Axis) -> String {
+    match axis {
+        Axis::Emulated { pos, neg } => format!("{} / {}", button_name(pos), button_name(neg)),
+        Axis::Controller { controller_id, axis, invert, .. } => {
+            format!("Pad {} {:?}{}", controller_id, axis, if *invert { " inverted" } else { "" })
+        },
+        Axis::Mouse { axis, .. } => format!("Mouse {:?}", axis),
+        Axis::MouseWheel { horizontal } => format!("Wheel {}", if *horizontal { "horizontal" } else { "vertical" }),
+        Axis::Multiple(axes) => axes.iter().map(axis_name).collect::<Vec<_>>().join(", "),
+    }
+}
+
+fn binding_name(bindings: &Bindings<StringBindings>, target: &Target) -> String {
+    match target {
+        Target::Action(action) => {
+            let combos = bindings.action_bindings(action)
+                .map(|combo| combo.iter().map(button_name).collect::<Vec<_>>().join(" + "))
+                .collect::<Vec<_>>();
+            if combos.is_empty() { "unbound".to_string() } else { combos.join(", ") }
+        },
+        Target::AxisButton(axis, positive) => match bindings.axis(axis).and_then(emulated) {
+            Some((pos, neg)) => button_name(if *positive { pos } else { neg }),
+            _ => "unbound".to_string(),
+        },
+        Target::Axis(axis) => match bindings.axis(axis) {
+            Some(binding) => axis_name(controller(binding).unwrap_or(binding)),
+            None => "unbound".to_string(),
+        },
+    }
+}
+
+// name of whatever else the button is bound to
+fn conflict(bindings: &Bindings<StringBindings>, button: &Button, target: &Target) -> Option<String> {
+    for action in bindings.actions() {
+        if *target == Target::Action(action.clone()) {
+            continue;
+        }
+        if bindings.action_bindings(action).any(|combo| combo.contains(button)) {
+            return Some(action.clone());
+        }
+    }
+    for axis in bindings.axes() {
+        if let Some((pos, neg)) = bindings.axis(axis).and_then(emulated) {
+            for (positive, bound) in [(true, pos), (false, neg)].iter() {
+                if *bound == button && *target != Target::AxisButton(axis.clone(), *positive) {
+                    return Some(Target::AxisButton(axis.clone(), *positive).name());
+                }
+            }
+        }
+    }
+    None
+}
+
+fn rebind(bindings: &mut Bindings<StringBindings>, target: &Target, button: Button) -> Result<(), String> {
+    match target {
+        Target::Action(action) => set_action(bindings, action, vec![vec![button]]),
+        Target::AxisButton(axis, positive) => {
+            let mut binding = match bindings.axis(axis) {
+                Some(binding) => binding.clone(),
+                None => return Err(format!("{} isn't bound", axis)),
+            };
+            match emulated_mut(&mut binding) {
+                Some((pos, _)) if *positive => *pos = button,
+                Some((_, neg)) => *neg = button,
+                None => return Err(format!("{} isn't a button axis", axis)),
+            }
+            set_axis(bindings, axis, binding)
+        },
+        Target::Axis(axis) => Err(format!("{} is rebound by moving a stick", axis)),
+    }
+}
+
+// stick movement for a controller axis, keeps the dead zone of the current binding
+fn rebind_stick(bindings: &mut Bindings<StringBindings>, target: &Target, event: &InputEvent<StringBindings>) -> Option<Result<(), String>> {
+    let (axis, mut binding) = match target {
+        Target::Axis(axis) => (axis, bindings.axis(axis)?.clone()),
+        _ => return None,
+    };
+    match controller_mut(&mut binding) {
+        Some(Axis::Controller { controller_id, axis: bound, invert, .. }) => match event {
+            // half way so resting sticks don't get picked up
+            InputEvent::ControllerAxisMoved { which, axis: moved, value } if value.abs() > 0.5 => {
+                *controller_id = *which;
+                *bound = *moved;
+                *invert = *value < 0.0;
+            },
+            _ => return None,
+        },
+        _ => return Some(Err(format!("{} can't be rebound here", axis))),
+    }
+    Some(set_axis(bindings, axis, binding))
+}
+
+pub const ROWS: usize = 12;
+
+// controls menu opened from the settings menu, lists every binding and rebinds the clicked one
+#[derive(SystemDesc)]
+#[system_desc(name(ControlsMenuSystemDesc))]
+pub struct ControlsMenuSystem {
+    overrides_path: PathBuf,
+    #[system_desc(event_channel_reader)]
+    input_reader: ReaderId<InputEvent<StringBindings>>,
+    #[system_desc(event_channel_reader)]
+    ui_reader: ReaderId<UiEvent>,
+    // what's waiting for an input
+    #[system_desc(skip)]
+    capturing: Option<Target>,
+    #[system_desc(skip)]
+    page: usize,
+    #[system_desc(skip)]
+    status: String,
+    #[system_desc(skip)]
+    dirty: bool,
+}
+
+impl ControlsMenuSystem {
+    pub fn new(
+        overrides_path: PathBuf,
+        input_reader: ReaderId<InputEvent<StringBindings>>,
+        ui_reader: ReaderId<UiEvent>,
+    ) -> Self {
+        Self { overrides_path, input_reader, ui_reader, capturing:None, page:0, status:String::new(), dirty:true }
+    }
+
+    fn save(&mut self, defaults: &Bindings<StringBindings>, bindings: &Bindings<StringBindings>) {
+        if let Err(error) = overrides(defaults, bindings).write(&self.overrides_path) {
+            log::error!("Failed to save the input bindings: {}", error);
+        }
+    }
+}
+
+impl<'s> System<'s> for ControlsMenuSystem {
+    type SystemData = (
+        Read<'s, EventChannel<InputEvent<StringBindings>>>,
+        Read<'s, EventChannel<UiEvent>>,
+        ReadStorage<'s, UiTransform>,
+        UiFinder<'s>,
+        WriteStorage<'s, UiText>,
+        WriteStorage<'s, HiddenPropagate>,
+        Write<'s, InputHandler<StringBindings>>,
+        ReadExpect<'s, DefaultBindings>,
+        Write<'s, MenuState>,
+    );
+
+    fn run(&mut self, (input_events, ui_events, transforms, ui_finder, mut ui_texts, mut hidden, mut input, defaults, mut menu): Self::SystemData) {
+        let container = match ui_finder.find("controls_container") {
+            Some(container) => container,
+            None => return,
+        };
+        let settings = ui_finder.find("settings_container");
+
+        for event in input_events.read(&mut self.input_reader) {
+            // the settings action toggles the settings menu, this one goes away with it
+            if let InputEvent::ActionPressed(action) = event {
+                if action == "settings" && hidden.get(container).is_none() {
+                    hidden.insert(container, HiddenPropagate::new()).expect("Failed to create HiddenPropagate component");
+                    self.capturing = None;
+                }
+            }
+
+            let target = match &self.capturing {
+                Some(target) => target.clone(),
+                None => continue,
+            };
+            let button = match event {
+                // the left button clicks the menu and escape backs out of it, so they cancel instead
+                InputEvent::ButtonPressed(Button::Mouse(MouseButton::Left)) | InputEvent::ButtonPressed(Button::Key(VirtualKeyCode::Escape)) => {
+                    self.capturing = None;
+                    self.status = String::new();
+                    self.dirty = true;
+                    continue;
+                },
+                InputEvent::ButtonPressed(button) => button.clone(),
+                InputEvent::MouseWheelMoved(direction) => Button::MouseWheel(*direction),
+                _ => {
+                    if let Some(result) = rebind_stick(&mut input.bindings, &target, event) {
+                        self.status = result.err().unwrap_or_default();
+                        self.capturing = None;
+                        self.dirty = true;
+                        self.save(&defaults.bindings, &input.bindings);
+                    }
+                    continue;
+                },
+            };
+
+            self.dirty = true;
+            if let Some(other) = conflict(&input.bindings, &button, &target) {
+                self.status = format!("{} is already bound to {}", button_name(&button), other);
+                continue;
+            }
+            match rebind(&mut input.bindings, &target, button) {
+                Ok(()) => self.status = String::new(),
+                Err(error) => self.status = error,
+            }
+            self.capturing = None;
+            self.save(&defaults.bindings, &input.bindings);
+        }
+
+        let targets = targets(&input.bindings);
+        let pages = (targets.len() + ROWS - 1) / ROWS;
+        for event in ui_events.read(&mut self.ui_reader) {
+            if event.event_type != UiEventType::Click {
+                continue;
+            }
+            let id = match transforms.get(event.target) {
+                Some(transform) => transform.id.as_str(),
+                None => continue,
+            };
+            match id {
+                "settings_controls" => {
+                    hidden.remove(container);
+                    if let Some(settings) = settings {
+                        hidden.insert(settings, HiddenPropagate::new()).expect("Failed to create HiddenPropagate component");
+                    }
+                },
+                "controls_back" => {
+                    hidden.insert(container, HiddenPropagate::new()).expect("Failed to create HiddenPropagate component");
+                    if let Some(settings) = settings {
+                        hidden.remove(settings);
+                    }
+                    self.capturing = None;
+                },
+                "controls_reset" => {
+                    input.bindings = defaults.bindings.clone();
+                    if let Err(error) = fs::remove_file(&self.overrides_path) {
+                        if error.kind() != io::ErrorKind::NotFound {
+                            log::error!("Failed to remove the input binding overrides: {}", error);
+                        }
+                    }
+                    self.capturing = None;
+                    self.status = "Reset to the defaults".to_string();
+                },
+                "controls_previous_page" => self.page = self.page.saturating_sub(1),
+                "controls_next_page" => self.page = (self.page + 1).min(pages.saturating_sub(1)),
+                row if row.starts_with("controls_row_") => {
+                    let index = row["controls_row_".len()..].parse::<usize>().ok().map(|i| self.page * ROWS + i);
+                    if let Some(target) = index.and_then(|i| targets.get(i)) {
+                        self.status = match target {
+                            Target::Axis(_) => format!("Move a stick for {}, click or press Escape to cancel", target.name()),
+                            _ => format!("Press a key or button for {}, click or press Escape to cancel", target.name()),
+                        };
+                        self.capturing = Some(target.clone());
+                    }
+                },
+                _ => continue,
+            }
+            self.dirty = true;
+        }
+        // gamepad presses rebind instead of moving the menu focus
+        menu.capturing = self.capturing.is_some();
+
+        if !self.dirty {
+            return;
+        }
+        let rows = targets.iter().skip(self.page * ROWS).map(Some).chain(std::iter::repeat(None)).take(ROWS);
+        for (index, target) in rows.enumerate() {
+            let text = match target {
+                Some(target) if Some(target) == self.capturing.as_ref() => format!("{}: ...", target.name()),
+                Some(target) => format!("{}: {}", target.name(), binding_name(&input.bindings, target)),
+                None => String::new(),
+            };
+            if let Some(ui) = ui_finder.find(&format!("controls_row_{}", index)).and_then(|e| ui_texts.get_mut(e)) {
+                ui.text = text;
+            }
+        }
+        if let Some(ui) = ui_finder.find("controls_status").and_then(|e| ui_texts.get_mut(e)) {
+            ui.text = self.status.clone();
+        }
+        if let Some(ui) = ui_finder.find("controls_page").and_then(|e| ui_texts.get_mut(e)) {
+            ui.text = format!("{}/{}", self.page + 1, pages.max(1));
+        }
+        self.dirty = false;
+    }
+}
+
+// fills the help panel from the live bindings, so it follows input.ron and the controls menu
+#[derive(SystemDesc)]
+#[system_desc(name(HelpSystemDesc))]
+pub struct HelpSystem;
+
+impl<'s> System<'s> for HelpSystem {
+    type SystemData = (
+        UiFinder<'s>,
+        WriteStorage<'s, UiText>,
+        ReadStorage<'s, HiddenPropagate>,
+        Read<'s, InputHandler<StringBindings>>,
+    );
+
+    fn run(&mut self, (ui_finder, mut ui_texts, hidden, input): Self::SystemData) {
+        // only while the panel is open
+        match ui_finder.find("help_container") {
+            Some(container) if hidden.get(container).is_none() => (),
+            _ => return,
+        }
+        if let Some(ui) = ui_finder.find("help_text").and_then(|e| ui_texts.get_mut(e)) {
+            let text = help_text(&input.bindings);
+            if ui.text != text {
+                ui.text = text;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst::winit::VirtualKeyCode;
+
+    fn key(key: VirtualKeyCode) -> Button {
+        Button::Key(key)
+    }
+
+    fn defaults() -> Bindings<StringBindings> {
+        let mut bindings = Bindings::<StringBindings>::default();
+        bindings.insert_action_binding("help".to_string(), vec![key(VirtualKeyCode::F1)]).unwrap();
+        bindings.insert_action_binding("boost".to_string(), vec![key(VirtualKeyCode::LShift)]).unwrap();
+        bindings.insert_axis("move_x".to_string(), Axis::Emulated { pos:key(VirtualKeyCode::D), neg:key(VirtualKeyCode::A) }).unwrap();
+        bindings.insert_axis("zoom".to_string(), Axis::MouseWheel { horizontal:false }).unwrap();
+        bindings
+    }
+
+    fn combos(bindings: &Bindings<StringBindings>, action: &str) -> Vec<Vec<Button>> {
+        bindings.action_bindings(action).map(|combo| combo.to_vec()).collect()
+    }
+
+    fn assert_same(a: &Bindings<StringBindings>, b: &Bindings<StringBindings>) {
+        for action in a.actions().chain(b.actions()) {
+            assert_eq!(combos(a, action), combos(b, action), "{}", action);
+        }
+        for axis in a.axes().chain(b.axes()) {
+            assert_eq!(a.axis(axis), b.axis(axis), "{}", axis);
+        }
+    }
+
+    #[test]
+    fn layered_replaces_only_what_is_overridden() {
+        let mut overrides = Bindings::<StringBindings>::default();
+        overrides.insert_action_binding("help".to_string(), vec![key(VirtualKeyCode::F2)]).unwrap();
+        overrides.insert_axis("move_x".to_string(), Axis::Emulated { pos:key(VirtualKeyCode::Right), neg:key(VirtualKeyCode::Left) }).unwrap();
+        let bindings = layered(&defaults(), &overrides);
+        assert_eq!(combos(&bindings, "help"), vec![vec![key(VirtualKeyCode::F2)]]);
+        assert_eq!(combos(&bindings, "boost"), vec![vec![key(VirtualKeyCode::LShift)]]);
+        assert_eq!(bindings.axis("move_x"), overrides.axis("move_x"));
+        assert_eq!(bindings.axis("zoom"), defaults().axis("zoom"));
+    }
+
+    #[test]
+    fn layered_keeps_the_default_when_an_override_fails() {
+        let mut overrides = Bindings::<StringBindings>::default();
+        // bound to an axis button in the defaults
+        overrides.insert_action_binding("boost".to_string(), vec![key(VirtualKeyCode::D)]).unwrap();
+        // bound to an action in the defaults
+        overrides.insert_axis("move_x".to_string(), Axis::Emulated { pos:key(VirtualKeyCode::F1), neg:key(VirtualKeyCode::A) }).unwrap();
+        assert_same(&layered(&defaults(), &overrides), &defaults());
+    }
+
+    #[test]
+    fn overrides_round_trip() {
+        let defaults = defaults();
+        let mut bindings = defaults.clone();
+        rebind(&mut bindings, &Target::Action("help".to_string()), key(VirtualKeyCode::F2)).unwrap();
+        rebind(&mut bindings, &Target::AxisButton("move_x".to_string(), false), key(VirtualKeyCode::Left)).unwrap();
+        let overrides = overrides(&defaults, &bindings);
+        // only what changed is saved
+        assert_eq!(combos(&overrides, "help"), vec![vec![key(VirtualKeyCode::F2)]]);
+        assert!(combos(&overrides, "boost").is_empty());
+        assert!(overrides.axis("move_x").is_some());
+        assert!(overrides.axis("zoom").is_none());
+        assert_same(&layered(&defaults, &overrides), &bindings);
+        // nothing changed, nothing saved
+        let unchanged = super::overrides(&defaults, &defaults);
+        assert_eq!(unchanged.actions().count() + unchanged.axes().count(), 0);
+    }
+
+    #[test]
+    fn conflict_names_the_other_binding() {
+        let bindings = defaults();
+        let help = Target::Action("help".to_string());
+        let right = Target::AxisButton("move_x".to_string(), true);
+        assert_eq!(conflict(&bindings, &key(VirtualKeyCode::F1), &Target::Action("boost".to_string())), Some("help".to_string()));
+        assert_eq!(conflict(&bindings, &key(VirtualKeyCode::D), &help), Some(right.name()));
+        // its own button, or a free one
+        assert_eq!(conflict(&bindings, &key(VirtualKeyCode::F1), &help), None);
+        assert_eq!(conflict(&bindings, &key(VirtualKeyCode::D), &right), None);
+        assert_eq!(conflict(&bindings, &key(VirtualKeyCode::F9), &help), None);
+    }
+
+    #[test]
+    fn rebinds_actions_and_axis_buttons() {
+        let mut bindings = defaults();
+        rebind(&mut bindings, &Target::Action("help".to_string()), key(VirtualKeyCode::F2)).unwrap();
+        assert_eq!(combos(&bindings, "help"), vec![vec![key(VirtualKeyCode::F2)]]);
+        rebind(&mut bindings, &Target::AxisButton("move_x".to_string(), true), key(VirtualKeyCode::Right)).unwrap();
+        rebind(&mut bindings, &Target::AxisButton("move_x".to_string(), false), key(VirtualKeyCode::Left)).unwrap();
+        assert_eq!(bindings.axis("move_x"), Some(&Axis::Emulated { pos:key(VirtualKeyCode::Right), neg:key(VirtualKeyCode::Left) }));
+    }
+
+    #[test]
+    fn failed_rebinds_leave_the_binding() {
+        let mut bindings = defaults();
+        // taken by an axis button and an action
+        assert!(rebind(&mut bindings, &Target::Action("help".to_string()), key(VirtualKeyCode::D)).is_err());
+        assert!(rebind(&mut bindings, &Target::AxisButton("move_x".to_string(), true), key(VirtualKeyCode::F1)).is_err());
+        // not a button axis, not bound, or rebound with a stick
+        assert!(rebind(&mut bindings, &Target::AxisButton("zoom".to_string(), true), key(VirtualKeyCode::F9)).is_err());
+        assert!(rebind(&mut bindings, &Target::AxisButton("move_y".to_string(), true), key(VirtualKeyCode::F9)).is_err());
+        assert!(rebind(&mut bindings, &Target::Axis("zoom".to_string()), key(VirtualKeyCode::F9)).is_err());
+        assert_same(&bindings, &defaults());
+    }
+}
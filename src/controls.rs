@@ -1,62 +1,345 @@
 use amethyst::{
-    ecs::prelude::{ Join, Component, System, SystemData, WriteStorage, Read },
+    ecs::prelude::{ Join, Component, Entity, Entities, System, SystemData, WriteStorage, ReadStorage, Read, Write },
     derive::{SystemDesc},
-    core::{timing::Time, transform::Transform, shrev::{EventChannel, ReaderId}},
-    input::{StringBindings, InputEvent, ScrollDirection},
+    core::{math::Vector3, timing::Time, transform::{Transform, Parent}, shrev::{EventChannel, ReaderId}},
+    input::{StringBindings, InputEvent, InputHandler},
+    renderer::camera::Camera,
     utils::{
         tag::{Tag},
     },
-    controls::ArcBallControlTag,
+    controls::{ArcBallControlTag, FlyControlTag},
 };
+use crate::flight::Ship;
+use crate::focus::Focus;
+use crate::gamepad::GamepadSettings;
+use crate::map::MapView;
+use crate::menu::MenuState;
+use crate::origin::{scene_position, WorldPosition};
+use crate::planet::Planet;
+use crate::render::atmosphere::Atmosphere;
+use crate::render::depth::ClipPlanes;
 
+// how the camera moves, switched with the camera_mode action
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CameraMode {
+    // orbits the focused body
+    ArcBall,
+    // flies with the move axes
+    FreeFly,
+    // orbits the ship, the move axes fly the ship
+    Chase,
+}
+
+impl Default for CameraMode {
+    fn default() -> Self {
+        CameraMode::ArcBall
+    }
+}
+
+impl CameraMode {
+    pub fn next(self) -> Self {
+        match self {
+            CameraMode::ArcBall => CameraMode::FreeFly,
+            CameraMode::FreeFly => CameraMode::Chase,
+            CameraMode::Chase => CameraMode::ArcBall,
+        }
+    }
+}
+
+// fraction of the distance to the nearest surface flown per second
+const FLY_SPEED: f32 = 0.5;
+// slowest speed in units per second, so landing on a surface doesn't stop you
+const MIN_FLY_SPEED: f32 = 0.0005;
+const BOOST: f32 = 10.0;
+
+// each wheel step zooms by this factor, keys and sticks zoom by ZOOM_RATE per second
+pub const ZOOM_STEP: f32 = 1.15;
+pub const ZOOM_RATE: f32 = 4.0;
+// how quickly the arcball distance follows the zoom, per second in log space
+pub const ZOOM_SMOOTHING: f32 = 4.0;
+
+// closest and furthest the arcball may be from a body, the surface and atmosphere have to stay
+// outside the near plane and the rest of the scene inside the far plane. the extent is how far the
+// scene reaches from the body, so from the furthest zoom it's at most that plus the extent away
+pub fn zoom_limits(radius: f32, atmosphere_radius: f32, extent: f32, znear: f32, zfar: f32) -> (f32, f32) {
+    let min = radius.max(atmosphere_radius) + 2.0 * znear;
+    let max = extent.min(zfar - extent).max(min);
+    (min, max)
+}
+
+// orbits and zooms the arcball with the look and zoom axes
 #[derive(SystemDesc)]
 #[system_desc(name(CameraControlSystemDesc))]
-pub struct CameraControlSystem {
+pub struct CameraControlSystem;
+
+impl<'s> System<'s> for CameraControlSystem {
+    type SystemData = (
+        Entities<'s>,
+        WriteStorage<'s, ArcBallControlTag>,
+        ReadStorage<'s, FlyControlTag>,
+        ReadStorage<'s, Camera>,
+        ReadStorage<'s, Atmosphere>,
+        ReadStorage<'s, Parent>,
+        ReadStorage<'s, WorldPosition>,
+        WriteStorage<'s, Transform>,
+        Write<'s, Focus>,
+        Read<'s, MapView>,
+        Read<'s, InputHandler<StringBindings>>,
+        Read<'s, GamepadSettings>,
+        Read<'s, ClipPlanes>,
+        Read<'s, Time>,
+    );
+
+    fn run(&mut self, (entities, mut arcball_control, fly_control, cameras, atmospheres, parents, positions, mut transforms, mut focus, map, input, gamepad, clip_planes, time):Self::SystemData) {
+        // the map has its own camera, this one is left as it was
+        if map.open {
+            return;
+        }
+        // camera controls run in real time, they'd be unusable under time warp otherwise
+        let seconds = time.delta_real_seconds();
+
+        // orbit with the look axes, rotating the camera the same way the mouse does so the arcball follows
+        let speed = gamepad.look_speed * seconds;
+        let yaw = gamepad.response(input.axis_value("camera_yaw").unwrap_or(0.0) as f32) * speed;
+        let mut pitch = gamepad.response(input.axis_value("camera_pitch").unwrap_or(0.0) as f32) * speed;
+        if gamepad.invert_look {
+            pitch = -pitch;
+        }
+        if yaw != 0.0 || pitch != 0.0 {
+            for (_, transform) in (&fly_control, &mut transforms).join() {
+                transform.append_rotation_x_axis(-pitch);
+                transform.prepend_rotation_y_axis(-yaw);
+            }
+        }
+
+        let (target, current) = match focus.target {
+            Some(target) if arcball_control.join().next().is_some() => (target, focus.distance),
+            _ => return,
+        };
+
+        // the wheel moves in steps, everything else bound to zoom is held down
+        let zoom = gamepad.response(input.axis_value("zoom").unwrap_or(0.0) as f32);
+        let steps = if input.mouse_wheel_value(false) != 0.0 {
+            zoom
+        } else {
+            zoom * ZOOM_RATE * seconds
+        };
+        let mut updated_value = current * ZOOM_STEP.powf(steps);
+
+        // limits from the focused body and the scene around it
+        if let Some(target_transform) = transforms.get(target) {
+            let radius = target_transform.scale().x;
+            let atmosphere_radius = (&atmospheres, &parents, &transforms).join()
+                .filter(|(_, parent, _)| parent.entity == target)
+                .map(|(_, _, transform)| radius * transform.scale().x)
+                .fold(0.0, f32::max);
+            let centre = scene_position(target_transform);
+            let extent = (&positions, &transforms).join()
+                .map(|(_, transform)| (scene_position(transform) - centre).norm() + transform.scale().x)
+                .fold(0.0, f32::max);
+            // the planes the camera was authored with, the near plane it renders with moves out with the zoom
+            let (znear, zfar) = (&entities, &cameras).join()
+                .next()
+                .and_then(|(entity, _)| clip_planes.authored(entity))
+                .unwrap_or((0.1, 100000.0));
+            let (min, max) = zoom_limits(radius, atmosphere_radius, extent, znear, zfar);
+            updated_value = updated_value.max(min).min(max);
+        }
+        focus.distance = updated_value;
+
+        // the focus system eases the distance while the camera moves over to a new target
+        if focus.transition.is_some() {
+            return;
+        }
+        // make arcball value approach our value, in log space so it takes as long at any distance
+        let blend = (ZOOM_SMOOTHING * seconds).min(1.0);
+        for item in (&mut arcball_control).join() {
+            let distance = item.distance.max(std::f32::EPSILON);
+            item.distance = (distance.ln() + (updated_value.ln() - distance.ln()) * blend).exp();
+        }
+    }
+}
+
+// switches camera modes, the camera keeps its position and orientation wherever it can
+#[derive(SystemDesc)]
+#[system_desc(name(CameraModeSystemDesc))]
+pub struct CameraModeSystem {
     #[system_desc(event_channel_reader)]
     event_reader: ReaderId<InputEvent<StringBindings>>,
+    // what was in focus before chasing the ship
     #[system_desc(skip)]
-    target_distance:Option<f32>
+    orbit_target: Option<Entity>,
 }
 
-impl CameraControlSystem {
+impl CameraModeSystem {
     pub fn new(event_reader: ReaderId<InputEvent<StringBindings>>) -> Self {
-        Self { event_reader:event_reader, target_distance:None }
+        Self { event_reader:event_reader, orbit_target:None }
     }
 }
 
-impl<'s> System<'s> for CameraControlSystem {
+impl<'s> System<'s> for CameraModeSystem {
     type SystemData = (
         Read<'s, EventChannel<InputEvent<StringBindings>>>,
+        Write<'s, CameraMode>,
+        Write<'s, Focus>,
+        Read<'s, MapView>,
+        Entities<'s>,
+        ReadStorage<'s, Camera>,
+        ReadStorage<'s, Tag<Ship>>,
         WriteStorage<'s, ArcBallControlTag>,
-        Read<'s, Time>,
     );
 
-    fn run(&mut self, (events, mut arcball_control, time):Self::SystemData) {
-        if let Some(current) = self.target_distance {
-            // loop events
-            let mut updated_value = current;
-            for event in events.read(&mut self.event_reader) {
-                if let InputEvent::MouseWheelMoved(direction) = event {            
-                    if direction.eq(&ScrollDirection::ScrollUp) {
-                        updated_value += 1.0;
-                    } else if direction.eq(&ScrollDirection::ScrollDown) {
-                        updated_value -= 1.0;
+    fn run(&mut self, (events, mut mode, mut focus, map, entities, cameras, ships, mut arcball_control):Self::SystemData) {
+        let switches = events
+            .read(&mut self.event_reader)
+            .filter(|event| matches!(event, InputEvent::ActionPressed(action) if action == "camera_mode"))
+            .count();
+        if map.open {
+            return;
+        }
+        let camera = match (&entities, &cameras).join().next() {
+            Some((camera, _)) => camera,
+            None => return,
+        };
+        let ship = (&entities, &ships).join().next().map(|(ship, _)| ship);
+
+        // the focus system puts the arcball back on the pivot when leaving free-fly
+        for _ in 0..switches {
+            *mode = match (mode.next(), ship) {
+                // nothing to chase
+                (CameraMode::Chase, None) => CameraMode::ArcBall,
+                (next, _) => next,
+            };
+
+            match *mode {
+                CameraMode::FreeFly => {
+                    arcball_control.remove(camera);
+                },
+                CameraMode::Chase => {
+                    if focus.target != ship {
+                        self.orbit_target = focus.target;
                     }
-                }
-            }
-            if updated_value < 3.0 {
-                updated_value = 3.0
-            }
-            self.target_distance = Some(updated_value);
-            // make arcball value approach our value
-            for item in (&mut arcball_control).join() {
-                item.distance = item.distance+(updated_value-item.distance)*4.0*time.delta_seconds();
-            }
-        // init if not set yet
-        } else {
-            for item in arcball_control.join() {
-                self.target_distance = Some(item.distance);
+                    focus.target = ship;
+                },
+                CameraMode::ArcBall => {
+                    if let Some(target) = self.orbit_target.take() {
+                        focus.target = Some(target);
+                    }
+                },
             }
+            log::info!("Camera mode: {:?}", *mode);
+        }
+    }
+}
+
+// moves the camera in free-fly mode and the ship in chase mode, relative to where the camera looks
+#[derive(SystemDesc)]
+#[system_desc(name(FlySystemDesc))]
+pub struct FlySystem;
+
+impl<'s> System<'s> for FlySystem {
+    type SystemData = (
+        Read<'s, CameraMode>,
+        Read<'s, MapView>,
+        Read<'s, MenuState>,
+        Read<'s, InputHandler<StringBindings>>,
+        Read<'s, GamepadSettings>,
+        Read<'s, Time>,
+        Entities<'s>,
+        ReadStorage<'s, Camera>,
+        ReadStorage<'s, Planet>,
+        ReadStorage<'s, Tag<Ship>>,
+        WriteStorage<'s, WorldPosition>,
+        WriteStorage<'s, Transform>,
+    );
+
+    fn run(&mut self, (mode, map, menu, input, gamepad, time, entities, cameras, planets, ships, mut positions, mut transforms):Self::SystemData) {
+        // an open menu moves its focus with the up and down buttons
+        if *mode == CameraMode::ArcBall || map.open || menu.open {
+            return;
+        }
+        let axis = |name: &str| gamepad.response(input.axis_value(name).unwrap_or(0.0) as f32);
+        let direction = Vector3::new(axis("move_x"), axis("move_y"), axis("move_z"));
+        // the flight camera, the map camera is created after it
+        let camera = match (&entities, &cameras).join().next() {
+            Some((camera, _)) => camera,
+            None => return,
+        };
+        let (rotation, camera_position) = match transforms.get(camera) {
+            Some(transform) => (*transform.rotation(), scene_position(transform)),
+            None => return,
+        };
+        let ship = (&ships, &transforms).join().next().map(|(_, transform)| scene_position(transform));
+        let position = match (*mode, ship) {
+            (CameraMode::Chase, Some(ship)) => ship,
+            _ => camera_position,
+        };
+
+        // cover the distance to the nearest surface in a couple of seconds, however far out it is
+        let altitude = (&planets, &transforms).join()
+            .map(|(_, transform)| (scene_position(transform) - position).norm() - transform.scale().x)
+            .fold(std::f32::INFINITY, f32::min);
+        let boost = if input.action_is_down("boost").unwrap_or(false) { BOOST } else { 1.0 };
+        let speed = if altitude.is_finite() { (altitude * FLY_SPEED).max(MIN_FLY_SPEED) } else { FLY_SPEED };
+        // the camera flies in real time, flying it by hand under time warp would be hopeless otherwise. the ship
+        // is part of the simulation and moves in simulation time like the bodies and its engine
+        let seconds = match *mode {
+            CameraMode::Chase => time.delta_seconds(),
+            _ => time.delta_real_seconds(),
+        };
+        let movement = rotation * direction * speed * boost * seconds;
+
+        match *mode {
+            CameraMode::Chase => {
+                // the ship points where the camera looks
+                for (_, position, transform) in (&ships, &mut positions, &mut transforms).join() {
+                    position.position += movement.map(|v| v as f64);
+                    transform.set_rotation(rotation);
+                }
+            },
+            _ => {
+                if direction == Vector3::zeros() {
+                    return;
+                }
+                if let Some(transform) = transforms.get_mut(camera) {
+                    let translation = transform.translation() + movement;
+                    transform.set_translation(translation);
+                }
+            },
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // centres and radii of the bodies in assets/scene.ron
+    const BODIES: [([f32; 3], f32); 4] = [
+        ([0.0, 0.0, 0.0], 1.0),
+        ([23481.0, 0.0, 0.0], 109.166),
+        ([0.0, 0.0, 60.3], 0.273),
+        ([23481.0, 0.0, 35691.0], 0.532),
+    ];
+
+    #[test]
+    fn whole_scene_stays_inside_the_far_plane_at_the_furthest_zoom() {
+        let (znear, zfar) = (0.1, 100000.0);
+        for (centre, radius) in BODIES.iter() {
+            let centre = Vector3::from(*centre);
+            let extent = BODIES.iter()
+                .map(|(position, radius)| (Vector3::from(*position) - centre).norm() + radius)
+                .fold(0.0, f32::max);
+            let (min, max) = zoom_limits(*radius, radius * 1.01, extent, znear, zfar);
+            assert!(min > radius * 1.01 + znear);
+            assert!(max >= min);
+            assert!(max + extent <= zfar, "{} + {} is past the far plane", max, extent);
+        }
+    }
+
+    #[test]
+    fn zoom_limits_never_cross() {
+        let (min, max) = zoom_limits(1.0, 1.006, 99999.5, 0.1, 100000.0);
+        assert_eq!(min, max);
+    }
+}
@@ -19,7 +19,10 @@ use amethyst::{
     ui::{UiFinder, UiText},
 };
 use crate::render::fxaa::FxaaSettings;
-use crate::render::tonemap::TonemapSettings;
+use crate::render::ssaa::AntiAliasingSettings;
+use crate::render::tonemap::{TonemapSettings, ColorGradingSettings};
+use crate::render::lens::LensSettings;
+use crate::render::debug_view::DebugViewSettings;
 
 #[derive(SystemDesc)]
 #[system_desc(name(DebugSystemDesc))]
@@ -53,9 +56,13 @@ impl<'s> System<'s> for DebugSystem {
         WriteStorage<'s, Tag<FpsDisplay>>,
         Write<'s, FxaaSettings>,
         Write<'s, TonemapSettings>,
+        Write<'s, ColorGradingSettings>,
+        Write<'s, LensSettings>,
+        Write<'s, DebugViewSettings>,
+        Write<'s, AntiAliasingSettings>,
     );
 
-    fn run(&mut self, (events, mut hidden, mut debuglines, entities, fps_counter, time, ui_finder, mut ui_texts, mut fps_tags, mut fxaa_settings, mut tonemap_settings): Self::SystemData) {
+    fn run(&mut self, (events, mut hidden, mut debuglines, entities, fps_counter, time, ui_finder, mut ui_texts, mut fps_tags, mut fxaa_settings, mut tonemap_settings, mut grading_settings, mut lens_settings, mut debug_view, mut anti_aliasing): Self::SystemData) {
         // set fps display if it's available
         if let Some(result) = (&*entities, &fps_tags).join().next() {
             if time.frame_number() % 20 == 0 {
@@ -81,6 +88,14 @@ impl<'s> System<'s> for DebugSystem {
                             }
                         }
                     },
+                    "hud" => {
+                        if let Some(entity) = ui_finder.find("hud_container") {
+                            match hidden.get(entity) {
+                                Some(_) => { hidden.remove(entity); },
+                                None => { hidden.insert(entity, HiddenPropagate::new()).expect("Failed to create HiddenPropagate component"); },
+                            }
+                        }
+                    },
                     "fps" => {
                         if let Some(entity) = ui_finder.find("fps_text") {
                             match hidden.get(entity) {
@@ -101,12 +116,24 @@ impl<'s> System<'s> for DebugSystem {
                             entities.build_entity().with(create_debug_lines(), &mut debuglines).build();
                         }
                     },
-                    "fxaa" => {
-                        fxaa_settings.enabled = !fxaa_settings.enabled;
+                    "anti_aliasing" => {
+                        anti_aliasing.mode = anti_aliasing.mode.next();
+                        fxaa_settings.enabled = anti_aliasing.mode.fxaa();
+                        log::info!("Anti-aliasing: {:?}", anti_aliasing.mode);
                     },
                     "tonemap" => {
                         tonemap_settings.enabled = !tonemap_settings.enabled;
                     },
+                    "debug_view" => {
+                        debug_view.view = debug_view.view.next();
+                        log::info!("Debug view: {:?}", debug_view.view);
+                    },
+                    "lens" => {
+                        lens_settings.enabled = !lens_settings.enabled;
+                    },
+                    "grading" => {
+                        grading_settings.enabled = !grading_settings.enabled;
+                    },
                     "exposure_incr" => {
                         tonemap_settings.exposure += 0.1;
                     },
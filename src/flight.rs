@@ -0,0 +1,483 @@
+use amethyst::{
+    ecs::prelude::{ Join, Component, NullStorage, System, SystemData, ReadStorage, WriteStorage, Read, Write },
+    derive::{SystemDesc},
+    core::{math::{Vector3}, timing::Time, transform::Transform, shrev::{EventChannel, ReaderId}},
+    input::{InputEvent, InputHandler, StringBindings},
+    renderer::camera::Camera,
+    utils::tag::Tag,
+};
+use std::f64::consts::PI;
+use crate::origin::{FloatingOrigin, WorldPosition};
+use crate::planet::Planet;
+use crate::gamepad::GamepadSettings;
+use crate::menu::MenuState;
+
+// the scene is modelled in earth radii
+pub const METERS_PER_UNIT: f64 = 6_371_000.0;
+pub const METERS_PER_AU: f64 = 149_597_870_700.0;
+
+// the craft, drawn far larger than life so it's visible outside the near plane
+#[derive(Clone, Default)]
+pub struct Ship;
+impl Component for Ship {
+    type Storage = NullStorage<Self>;
+}
+
+// fuel and engine state of the ship
+pub struct Vessel {
+    // 0..1
+    pub throttle: f32,
+    // kilograms
+    pub fuel: f32,
+    pub fuel_capacity: f32,
+    // acceleration and fuel use at full throttle, in m/s² and kg/s
+    pub thrust: f32,
+    pub fuel_flow: f32,
+    // velocity the engine built up, in meters per second of simulation time
+    pub velocity: Vector3<f64>,
+}
+
+impl Default for Vessel {
+    fn default() -> Self {
+        Self { throttle:0.0, fuel:1000.0, fuel_capacity:1000.0, thrust:20.0, fuel_flow:5.0, velocity:Vector3::zeros() }
+    }
+}
+
+impl Vessel {
+    // burns fuel at the throttle for some seconds and returns the speed gained, less once the fuel runs out
+    pub fn burn(&mut self, seconds: f32) -> f32 {
+        let flow = self.fuel_flow * self.throttle;
+        if flow <= 0.0 || self.fuel <= 0.0 || seconds <= 0.0 {
+            return 0.0;
+        }
+        let burning = seconds.min(self.fuel / flow);
+        self.fuel = (self.fuel - flow * burning).max(0.0);
+        self.thrust * self.throttle * burning
+    }
+}
+
+// keplerian orbit around a body, distances are from its centre in meters, times in seconds
+#[derive(Clone, Debug, Default)]
+pub struct Orbit {
+    pub semi_major_axis: f64,
+    pub eccentricity: f64,
+    pub periapsis: f64,
+    // none when the orbit is open
+    pub apoapsis: Option<f64>,
+    // none when the apsis won't be reached, or is undefined on a circular orbit
+    pub time_to_periapsis: Option<f64>,
+    pub time_to_apoapsis: Option<f64>,
+}
+
+impl Orbit {
+    // orbit from a position and velocity relative to a body with gravitational parameter mu
+    pub fn from_state(mu: f64, position: &Vector3<f64>, velocity: &Vector3<f64>) -> Option<Self> {
+        let distance = position.norm();
+        if mu <= 0.0 || distance <= 0.0 {
+            return None;
+        }
+        let energy = velocity.norm_squared() / 2.0 - mu / distance;
+        // a parabola has no semi-major axis
+        if energy.abs() < std::f64::EPSILON {
+            return None;
+        }
+        let momentum = position.cross(velocity).norm_squared();
+        let eccentricity = (1.0 + 2.0 * energy * momentum / (mu * mu)).max(0.0).sqrt();
+        let semi_major_axis = -mu / (2.0 * energy);
+        let periapsis = semi_major_axis * (1.0 - eccentricity);
+        let radial = position.dot(velocity);
+
+        // bound orbits, including falling straight down
+        if energy < 0.0 {
+            let motion = (mu / semi_major_axis.powi(3)).sqrt();
+            let (time_to_periapsis, time_to_apoapsis) = if eccentricity > 1e-6 {
+                // mean anomaly from the eccentric anomaly
+                let e_cos = 1.0 - distance / semi_major_axis;
+                let e_sin = radial / (mu * semi_major_axis).sqrt();
+                let mean = e_sin.atan2(e_cos) - e_sin;
+                (
+                    Some((2.0 * PI - mean).rem_euclid(2.0 * PI) / motion),
+                    Some((PI - mean).rem_euclid(2.0 * PI) / motion),
+                )
+            } else {
+                (None, None)
+            };
+            Some(Self {
+                semi_major_axis,
+                eccentricity,
+                periapsis,
+                apoapsis: Some(semi_major_axis * (1.0 + eccentricity)),
+                time_to_periapsis,
+                time_to_apoapsis,
+            })
+        } else {
+            // mean anomaly from the hyperbolic anomaly, negative while inbound
+            let e_sinh = radial / (-mu * semi_major_axis).sqrt();
+            let mean = e_sinh - (e_sinh / eccentricity).asinh();
+            let motion = (mu / (-semi_major_axis).powi(3)).sqrt();
+            Some(Self {
+                semi_major_axis,
+                eccentricity,
+                periapsis,
+                apoapsis: None,
+                time_to_periapsis: if mean < 0.0 { Some(-mean / motion) } else { None },
+                time_to_apoapsis: None,
+            })
+        }
+    }
+}
+
+// where the craft is relative to the body it's under the influence of, in meters and seconds
+#[derive(Clone, Debug, Default)]
+pub struct FlightState {
+    pub body: Option<String>,
+    pub radius: f64,
+    pub altitude: f64,
+    pub orbital_speed: f64,
+    pub surface_speed: f64,
+    pub vertical_speed: f64,
+    pub escape_velocity: f64,
+    pub orbit: Option<Orbit>,
+    // state vectors relative to the body and its gravitational parameter, for plotting the trajectory
+    pub position: Vector3<f64>,
+    pub velocity: Vector3<f64>,
+    pub mu: f64,
+}
+
+// derives the flight state from the ship's motion through world space, or the camera's without a ship
+#[derive(SystemDesc)]
+#[system_desc(name(FlightSystemDesc))]
+pub struct FlightSystem {
+    // last craft position relative to the reference body, and which body that was
+    #[system_desc(skip)]
+    previous: Option<(String, Vector3<f64>)>,
+}
+
+impl FlightSystem {
+    pub fn new() -> Self {
+        Self { previous:None }
+    }
+}
+
+impl<'s> System<'s> for FlightSystem {
+    type SystemData = (
+        Write<'s, FlightState>,
+        Read<'s, FloatingOrigin>,
+        Read<'s, Time>,
+        ReadStorage<'s, Camera>,
+        ReadStorage<'s, Tag<Ship>>,
+        ReadStorage<'s, Planet>,
+        ReadStorage<'s, WorldPosition>,
+        ReadStorage<'s, Transform>,
+    );
+
+    fn run(&mut self, (mut state, origin, time, cameras, ships, planets, positions, transforms) : Self::SystemData) {
+        let ship = (&ships, &positions).join().next().map(|(_, position)| position.position);
+        let camera = (&cameras, &transforms).join().next().map(|(_, transform)| origin.to_world(transform.translation()));
+        let craft = match ship.or(camera) {
+            Some(position) => position * METERS_PER_UNIT,
+            None => return,
+        };
+
+        // the reference body is the one pulling hardest, a cheap stand-in for spheres of influence
+        let body = (&planets, &positions, &transforms).join()
+            .map(|(planet, position, transform)| {
+                let radius = transform.scale().x as f64 * METERS_PER_UNIT;
+                let relative = craft - position.position * METERS_PER_UNIT;
+                (planet, radius, relative)
+            })
+            .filter(|(planet, _, relative)| planet.gravity > 0.0 && relative.norm() > 0.0)
+            .max_by(|a, b| {
+                let a = a.0.mu(a.1) / a.2.norm_squared();
+                let b = b.0.mu(b.1) / b.2.norm_squared();
+                a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+            });
+        let (planet, radius, relative) = match body {
+            Some(body) => body,
+            None => {
+                *state = FlightState::default();
+                self.previous = None;
+                return;
+            },
+        };
+
+        // velocity by finite difference, only meaningful while the reference body stays the same. the bodies,
+        // the engine and the flown ship all move in simulation time, so it's per simulated second
+        let delta = time.delta_seconds() as f64;
+        let velocity = match &self.previous {
+            Some((name, previous)) if *name == planet.name && delta > 0.0 => (relative - previous) / delta,
+            _ => Vector3::zeros(),
+        };
+        self.previous = Some((planet.name.clone(), relative));
+
+        let distance = relative.norm();
+        let mu = planet.mu(radius);
+        let rotation = Vector3::y() * planet.rotation_speed as f64;
+        *state = FlightState {
+            body: Some(planet.name.clone()),
+            radius,
+            altitude: distance - radius,
+            orbital_speed: velocity.norm(),
+            surface_speed: (velocity - rotation.cross(&relative)).norm(),
+            vertical_speed: velocity.dot(&relative) / distance,
+            escape_velocity: (2.0 * mu / distance).sqrt(),
+            orbit: Orbit::from_state(mu, &relative, &velocity),
+            position: relative,
+            velocity,
+            mu,
+        };
+    }
+}
+
+// throttle per second at full deflection of the throttle axis
+const THROTTLE_RATE: f32 = 0.5;
+
+// moves the throttle with the throttle axis
+#[derive(SystemDesc)]
+#[system_desc(name(ThrottleSystemDesc))]
+pub struct ThrottleSystem;
+
+impl<'s> System<'s> for ThrottleSystem {
+    type SystemData = (
+        Write<'s, Vessel>,
+        Read<'s, InputHandler<StringBindings>>,
+        Read<'s, GamepadSettings>,
+        Read<'s, Time>,
+    );
+
+    fn run(&mut self, (mut vessel, input, gamepad, time) : Self::SystemData) {
+        let change = gamepad.response(input.axis_value("throttle").unwrap_or(0.0) as f32);
+        // in real time, the throttle shouldn't slam open under time warp
+        vessel.throttle = (vessel.throttle + change * THROTTLE_RATE * time.delta_real_seconds()).max(0.0).min(1.0);
+    }
+}
+
+// burns fuel at the throttle and pushes the ship where it points, the ship coasts with what it gained
+#[derive(SystemDesc)]
+#[system_desc(name(EngineSystemDesc))]
+pub struct EngineSystem;
+
+impl<'s> System<'s> for EngineSystem {
+    type SystemData = (
+        Write<'s, Vessel>,
+        Read<'s, Time>,
+        ReadStorage<'s, Tag<Ship>>,
+        ReadStorage<'s, Transform>,
+        WriteStorage<'s, WorldPosition>,
+    );
+
+    fn run(&mut self, (mut vessel, time, ships, transforms, mut positions) : Self::SystemData) {
+        // in simulation time like the bodies, so the burn speeds up under time warp
+        let seconds = time.delta_seconds();
+        for (_, transform, position) in (&ships, &transforms, &mut positions).join() {
+            let forward = (transform.rotation() * -Vector3::z()).map(|v| v as f64);
+            let gained = vessel.burn(seconds) as f64;
+            vessel.velocity += forward * gained;
+            position.position += vessel.velocity * seconds as f64 / METERS_PER_UNIT;
+        }
+    }
+}
+
+// simulation speed factors, stepped through with the warp actions
+pub const WARP_LEVELS: [f32; 8] = [1.0, 2.0, 5.0, 10.0, 100.0, 1000.0, 10_000.0, 100_000.0];
+
+#[derive(Default)]
+pub struct TimeWarp {
+    // index into WARP_LEVELS
+    pub level: usize,
+}
+
+impl TimeWarp {
+    pub fn factor(&self) -> f32 {
+        WARP_LEVELS[self.level]
+    }
+}
+
+// changes the time warp with the warp actions, everything running on the scaled time speeds up with it
+#[derive(SystemDesc)]
+#[system_desc(name(TimeWarpSystemDesc))]
+pub struct TimeWarpSystem {
+    #[system_desc(event_channel_reader)]
+    event_reader: ReaderId<InputEvent<StringBindings>>,
+}
+
+impl TimeWarpSystem {
+    pub fn new(event_reader: ReaderId<InputEvent<StringBindings>>) -> Self {
+        Self { event_reader }
+    }
+}
+
+impl<'s> System<'s> for TimeWarpSystem {
+    type SystemData = (
+        Read<'s, EventChannel<InputEvent<StringBindings>>>,
+        Write<'s, TimeWarp>,
+        Write<'s, Time>,
+        Read<'s, MenuState>,
+    );
+
+    fn run(&mut self, (events, mut warp, mut time, menu) : Self::SystemData) {
+        for event in events.read(&mut self.event_reader) {
+            match event {
+                // an open menu borrows the warp buttons to step its settings
+                InputEvent::ActionPressed(action) if action == "warp_up" && !menu.open => {
+                    warp.level = (warp.level + 1).min(WARP_LEVELS.len() - 1);
+                },
+                InputEvent::ActionPressed(action) if action == "warp_down" && !menu.open => {
+                    warp.level = warp.level.saturating_sub(1);
+                },
+                _ => continue,
+            }
+            log::info!("Time warp: {}x", warp.factor());
+        }
+        if time.time_scale() != warp.factor() {
+            time.set_time_scale(warp.factor());
+        }
+    }
+}
+
+pub fn format_distance(meters: f64) -> String {
+    let magnitude = meters.abs();
+    if magnitude < 1e3 {
+        format!("{:.0} m", meters)
+    } else if magnitude < 1e6 {
+        format!("{:.2} km", meters / 1e3)
+    } else if magnitude < 0.01 * METERS_PER_AU {
+        format!("{:.2} Mm", meters / 1e6)
+    } else {
+        format!("{:.3} AU", meters / METERS_PER_AU)
+    }
+}
+
+pub fn format_speed(meters_per_second: f64) -> String {
+    if meters_per_second.abs() < 1e4 {
+        format!("{:.1} m/s", meters_per_second)
+    } else {
+        format!("{:.2} km/s", meters_per_second / 1e3)
+    }
+}
+
+pub fn format_duration(seconds: f64) -> String {
+    let total = seconds.max(0.0).round() as u64;
+    let (days, hours, minutes, seconds) = (total / 86400, total / 3600 % 24, total / 60 % 60, total % 60);
+    if days > 0 {
+        format!("{}d {:02}h {:02}m", days, hours, minutes)
+    } else if hours > 0 {
+        format!("{}h {:02}m {:02}s", hours, minutes, seconds)
+    } else {
+        format!("{}m {:02}s", minutes, seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // earth in meters and m³/s²
+    const RADIUS: f64 = 6_371_000.0;
+    const MU: f64 = 3.986e14;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() <= 1e-6 * b.abs().max(1.0), "{} != {}", a, b);
+    }
+
+    #[test]
+    fn circular_orbit() {
+        let distance = RADIUS + 400_000.0;
+        let speed = (MU / distance).sqrt();
+        let orbit = Orbit::from_state(MU, &Vector3::new(distance, 0.0, 0.0), &Vector3::new(0.0, 0.0, speed)).unwrap();
+        assert_close(orbit.semi_major_axis, distance);
+        assert!(orbit.eccentricity < 1e-6);
+        assert_close(orbit.periapsis, distance);
+        assert_close(orbit.apoapsis.unwrap(), distance);
+        // neither apsis is defined on a circle
+        assert!(orbit.time_to_periapsis.is_none() && orbit.time_to_apoapsis.is_none());
+    }
+
+    #[test]
+    fn elliptic_orbit_from_periapsis() {
+        let distance = RADIUS + 400_000.0;
+        let speed = (MU / distance).sqrt() * 1.1;
+        let orbit = Orbit::from_state(MU, &Vector3::new(distance, 0.0, 0.0), &Vector3::new(0.0, 0.0, speed)).unwrap();
+        assert_close(orbit.periapsis, distance);
+        assert!(orbit.apoapsis.unwrap() > distance);
+        // half a period to the apoapsis, and a whole one back round to the periapsis
+        let period = 2.0 * PI * (orbit.semi_major_axis.powi(3) / MU).sqrt();
+        assert_close(orbit.time_to_apoapsis.unwrap(), period / 2.0);
+        let to_periapsis = orbit.time_to_periapsis.unwrap();
+        assert!(to_periapsis < 1e-6 || (to_periapsis - period).abs() < 1e-3);
+    }
+
+    #[test]
+    fn escape_orbit() {
+        let distance = RADIUS + 400_000.0;
+        let escape = (2.0 * MU / distance).sqrt();
+        // past escape velocity, heading out from the periapsis
+        let orbit = Orbit::from_state(MU, &Vector3::new(distance, 0.0, 0.0), &Vector3::new(0.0, 0.0, escape * 1.2)).unwrap();
+        assert!(orbit.eccentricity > 1.0);
+        assert!(orbit.semi_major_axis < 0.0);
+        assert_close(orbit.periapsis, distance);
+        assert!(orbit.apoapsis.is_none() && orbit.time_to_apoapsis.is_none());
+        assert!(orbit.time_to_periapsis.is_none());
+        // still inbound, the periapsis is ahead
+        let velocity = Vector3::new(-escape, 0.0, escape);
+        let orbit = Orbit::from_state(MU, &Vector3::new(distance, 0.0, 0.0), &velocity).unwrap();
+        assert!(orbit.apoapsis.is_none());
+        assert!(orbit.time_to_periapsis.unwrap() > 0.0);
+        assert!(orbit.periapsis < distance);
+    }
+
+    #[test]
+    fn no_orbit_without_a_body() {
+        assert!(Orbit::from_state(0.0, &Vector3::x(), &Vector3::z()).is_none());
+        assert!(Orbit::from_state(MU, &Vector3::zeros(), &Vector3::z()).is_none());
+    }
+
+    #[test]
+    fn distances_switch_units_at_the_boundaries() {
+        assert_eq!(format_distance(0.0), "0 m");
+        assert_eq!(format_distance(999.0), "999 m");
+        assert_eq!(format_distance(1000.0), "1.00 km");
+        assert_eq!(format_distance(-1500.0), "-1.50 km");
+        assert_eq!(format_distance(999_990.0), "999.99 km");
+        assert_eq!(format_distance(1e6), "1.00 Mm");
+        assert_eq!(format_distance(0.01 * METERS_PER_AU - 1e6), "1494.98 Mm");
+        assert_eq!(format_distance(0.01 * METERS_PER_AU), "0.010 AU");
+        assert_eq!(format_distance(METERS_PER_AU), "1.000 AU");
+    }
+
+    #[test]
+    fn speeds_switch_to_km_per_second() {
+        assert_eq!(format_speed(0.0), "0.0 m/s");
+        assert_eq!(format_speed(-12.34), "-12.3 m/s");
+        assert_eq!(format_speed(9999.9), "9999.9 m/s");
+        assert_eq!(format_speed(10_000.0), "10.00 km/s");
+        assert_eq!(format_speed(-29_780.0), "-29.78 km/s");
+    }
+
+    #[test]
+    fn durations_drop_seconds_past_a_day() {
+        assert_eq!(format_duration(0.0), "0m 00s");
+        assert_eq!(format_duration(-5.0), "0m 00s");
+        assert_eq!(format_duration(59.4), "0m 59s");
+        assert_eq!(format_duration(59.6), "1m 00s");
+        assert_eq!(format_duration(3599.0), "59m 59s");
+        assert_eq!(format_duration(3600.0), "1h 00m 00s");
+        assert_eq!(format_duration(86399.0), "23h 59m 59s");
+        assert_eq!(format_duration(86400.0 + 3600.0 + 60.0), "1d 01h 01m");
+    }
+
+    #[test]
+    fn burning_uses_fuel_until_it_runs_out() {
+        let mut vessel = Vessel::default();
+        assert_eq!(vessel.burn(1.0), 0.0);
+        assert_eq!(vessel.fuel, vessel.fuel_capacity);
+        vessel.throttle = 0.5;
+        assert_eq!(vessel.burn(2.0), vessel.thrust);
+        assert_eq!(vessel.fuel, vessel.fuel_capacity - vessel.fuel_flow);
+        // only the fuel that's left burns
+        vessel.fuel = vessel.fuel_flow;
+        assert_eq!(vessel.burn(10.0), vessel.thrust);
+        assert_eq!(vessel.fuel, 0.0);
+        assert_eq!(vessel.burn(1.0), 0.0);
+    }
+}
@@ -0,0 +1,343 @@
+use amethyst::{
+    controls::ArcBallControlTag,
+    core::{
+        math::{UnitQuaternion, Vector3},
+        shrev::{EventChannel, ReaderId},
+        timing::Time,
+        transform::Transform,
+    },
+    derive::SystemDesc,
+    ecs::prelude::{ Join, Entity, Entities, Read, ReadStorage, System, SystemData, Write, WriteStorage },
+    input::{InputEvent, StringBindings},
+    renderer::camera::Camera,
+    utils::tag::Tag,
+};
+use serde::{Deserialize, Serialize};
+use crate::controls::CameraMode;
+use crate::flight::Ship;
+use crate::map::MapView;
+use crate::menu::MenuState;
+use crate::origin::{FloatingOrigin, WorldPosition};
+use crate::picking::Picking;
+use crate::planet::Planet;
+use crate::render::sun::Sun;
+
+// arcball distance after a focus switch, in radii of the new target
+const FOCUS_DISTANCE: f32 = 4.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Easing {
+    Linear,
+    SmoothStep,
+    QuadOut,
+    CubicInOut,
+}
+
+impl Easing {
+    // eased progress for a linear progress, both 0..1
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.max(0.0).min(1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::SmoothStep => t * t * (3.0 - 2.0 * t),
+            Easing::QuadOut => t * (2.0 - t),
+            Easing::CubicInOut => if t < 0.5 { 4.0 * t * t * t } else { 1.0 - (2.0 - 2.0 * t).powi(3) / 2.0 },
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Transition {
+    // seconds, 0 jumps straight to the new value
+    pub duration: f32,
+    pub curve: Easing,
+}
+
+impl Transition {
+    pub fn progress(&self, elapsed: f32) -> f32 {
+        if self.duration <= 0.0 {
+            1.0
+        } else {
+            self.curve.apply(elapsed / self.duration)
+        }
+    }
+}
+
+// how the camera moves over to a new focus target, loaded from config/focus.ron
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FocusSettings {
+    pub position: Transition,
+    pub distance: Transition,
+    pub up: Transition,
+}
+
+impl Default for FocusSettings {
+    fn default() -> Self {
+        Self {
+            position: Transition { duration:1.5, curve:Easing::CubicInOut },
+            distance: Transition { duration:1.5, curve:Easing::CubicInOut },
+            up: Transition { duration:1.0, curve:Easing::SmoothStep },
+        }
+    }
+}
+
+impl FocusSettings {
+    pub fn duration(&self) -> f32 {
+        self.position.duration.max(self.distance.duration).max(self.up.duration)
+    }
+}
+
+// the body the camera orbits and follows
+#[derive(Default)]
+pub struct Focus {
+    pub target: Option<Entity>,
+    // what the arcball actually orbits, it's moved from the old target over to the new one
+    pub pivot: Option<Entity>,
+    // arcball distance to settle at, the zoom changes this
+    pub distance: f32,
+    // seconds since the target changed, none once the camera has arrived
+    pub transition: Option<f32>,
+}
+
+// where the camera was when the focus changed
+struct Departure {
+    position: Vector3<f64>,
+    distance: f32,
+    up: Vector3<f32>,
+}
+
+// switches the focus with the focus actions and eases the camera over to it
+#[derive(SystemDesc)]
+#[system_desc(name(FocusSystemDesc))]
+pub struct FocusSystem {
+    #[system_desc(event_channel_reader)]
+    event_reader: ReaderId<InputEvent<StringBindings>>,
+    // the target the current transition is heading to, the camera mode system changes the focus too
+    #[system_desc(skip)]
+    current: Option<Entity>,
+    #[system_desc(skip)]
+    departure: Option<Departure>,
+}
+
+impl FocusSystem {
+    pub fn new(event_reader: ReaderId<InputEvent<StringBindings>>) -> Self {
+        Self { event_reader, current:None, departure:None }
+    }
+}
+
+impl<'s> System<'s> for FocusSystem {
+    type SystemData = (
+        Read<'s, EventChannel<InputEvent<StringBindings>>>,
+        Write<'s, Focus>,
+        Write<'s, CameraMode>,
+        Read<'s, FocusSettings>,
+        Read<'s, FloatingOrigin>,
+        Read<'s, Picking>,
+        Read<'s, MapView>,
+        Read<'s, Time>,
+        Read<'s, MenuState>,
+        Entities<'s>,
+        ReadStorage<'s, Camera>,
+        ReadStorage<'s, Planet>,
+        ReadStorage<'s, Tag<Sun>>,
+        ReadStorage<'s, Tag<Ship>>,
+        WriteStorage<'s, ArcBallControlTag>,
+        WriteStorage<'s, WorldPosition>,
+        WriteStorage<'s, Transform>,
+    );
+
+    fn run(&mut self, (events, mut focus, mut mode, settings, origin, picking, map, time, menu, entities, cameras, planets, suns, ships, mut arcball_control, mut positions, mut transforms):Self::SystemData) {
+        let camera = match (&entities, &cameras).join().next() {
+            Some((camera, _)) => camera,
+            None => return,
+        };
+
+        // the scene starts out orbiting whatever the prefab's arcball targets, which becomes the first focus
+        if focus.pivot.is_none() {
+            let (target, distance) = match arcball_control.get(camera) {
+                Some(tag) => (tag.target, tag.distance),
+                None => return,
+            };
+            let position = match positions.get(target) {
+                Some(position) => position.position,
+                None => return,
+            };
+            let mut transform = Transform::default();
+            transform.set_translation(origin.to_local(&position));
+            let pivot = entities.build_entity()
+                .with(WorldPosition { position }, &mut positions)
+                .with(transform, &mut transforms)
+                .build();
+            arcball_control.insert(camera, ArcBallControlTag { target:pivot, distance })
+                .expect("Failed to create ArcBallControlTag component");
+            focus.target = Some(target);
+            focus.pivot = Some(pivot);
+            focus.distance = distance;
+            self.current = Some(target);
+        }
+        let pivot = focus.pivot.expect("Focus without a pivot");
+
+        // bodies in scene order, the planet, sun, moons and the ship
+        let targets = (&entities, &positions).join()
+            .map(|(entity, _)| entity)
+            .filter(|entity| planets.contains(*entity) || suns.contains(*entity) || ships.contains(*entity))
+            .collect::<Vec<_>>();
+
+        // the chase camera stays on the ship, and the map picks its own centre
+        // and an open menu borrows the focus buttons for its own navigation
+        if *mode != CameraMode::Chase && !map.open && !menu.open {
+            for event in events.read(&mut self.event_reader) {
+                let selected = match event {
+                    InputEvent::ActionPressed(action) if action == "focus_next" => {
+                        let index = focus.target.and_then(|target| targets.iter().position(|entity| *entity == target));
+                        index.map_or_else(|| targets.first(), |index| targets.get((index + 1) % targets.len())).copied()
+                    },
+                    InputEvent::ActionPressed(action) if action == "focus_pick" => {
+                        picking.hover.as_ref().map(|hit| hit.entity)
+                    },
+                    _ => None,
+                };
+                if let Some(target) = selected {
+                    focus.target = Some(target);
+                    // picking a body from free-fly goes back to orbiting
+                    *mode = CameraMode::ArcBall;
+                }
+            }
+        } else {
+            events.read(&mut self.event_reader).for_each(drop);
+        }
+
+        // back from free-fly the camera turns to face the pivot from where it is, so it doesn't jump
+        if *mode != CameraMode::FreeFly && !arcball_control.contains(camera) {
+            let pivot_position = match positions.get(pivot) {
+                Some(position) => origin.to_local(&position.position),
+                None => return,
+            };
+            let transform = transforms.get_mut(camera).expect("Camera without a transform");
+            let up = transform.rotation() * Vector3::y();
+            let distance = (transform.translation() - pivot_position).norm();
+            transform.face_towards(pivot_position, up);
+            arcball_control.insert(camera, ArcBallControlTag { target:pivot, distance })
+                .expect("Failed to create ArcBallControlTag component");
+            focus.distance = distance;
+        }
+
+        let target = match focus.target.filter(|target| entities.is_alive(*target)) {
+            Some(target) => target,
+            None => return,
+        };
+        let (target_position, target_radius, target_up) = match (positions.get(target), transforms.get(target)) {
+            (Some(position), Some(transform)) => (position.position, transform.scale().x, transform.rotation() * Vector3::y()),
+            _ => return,
+        };
+
+        // leave from wherever the camera is now, even halfway through another transition
+        if self.current != Some(target) {
+            self.current = Some(target);
+            let camera_up = transforms.get(camera).map_or_else(Vector3::y, |transform| transform.rotation() * Vector3::y());
+            self.departure = positions.get(pivot).map(|position| Departure {
+                position: position.position,
+                distance: arcball_control.get(camera).map_or(focus.distance, |tag| tag.distance),
+                up: camera_up,
+            });
+            focus.distance = target_radius * FOCUS_DISTANCE;
+            focus.transition = Some(0.0);
+            log::info!("Focus: {}", planets.get(target).map_or_else(|| format!("{:?}", target), |planet| planet.name.clone()));
+        }
+
+        let elapsed = match (focus.transition, &self.departure) {
+            (Some(elapsed), Some(_)) => elapsed + time.delta_real_seconds(),
+            _ => {
+                // arrived, just follow the target
+                focus.transition = None;
+                if let Some(position) = positions.get_mut(pivot) {
+                    position.position = target_position;
+                }
+                return;
+            },
+        };
+        let departure = self.departure.as_ref().expect("Transition without a departure");
+
+        let t = settings.position.progress(elapsed) as f64;
+        if let Some(position) = positions.get_mut(pivot) {
+            position.position = departure.position + (target_position - departure.position) * t;
+        }
+        if let Some(tag) = arcball_control.get_mut(camera) {
+            // in log space, so going from a moon to the sun doesn't rush the close end
+            let t = settings.distance.progress(elapsed);
+            let from = departure.distance.max(std::f32::EPSILON).ln();
+            let to = focus.distance.max(std::f32::EPSILON).ln();
+            tag.distance = (from + (to - from) * t).exp();
+        }
+        if *mode != CameraMode::FreeFly {
+            // roll the camera around where it's looking until its up matches the target's axis
+            let t = settings.up.progress(elapsed);
+            let up = departure.up.lerp(&target_up, t);
+            if let Some(transform) = transforms.get_mut(camera) {
+                let backward = transform.rotation() * Vector3::z();
+                if backward.cross(&up).norm() > 1e-3 {
+                    transform.set_rotation(UnitQuaternion::face_towards(&backward, &up));
+                }
+            }
+        }
+
+        if elapsed >= settings.duration() {
+            focus.transition = None;
+            self.departure = None;
+        } else {
+            focus.transition = Some(elapsed);
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CURVES: [Easing; 4] = [Easing::Linear, Easing::SmoothStep, Easing::QuadOut, Easing::CubicInOut];
+
+    #[test]
+    fn curves_start_at_zero_and_end_at_one() {
+        for curve in CURVES.iter() {
+            assert_eq!(curve.apply(0.0), 0.0, "{:?}", curve);
+            assert_eq!(curve.apply(1.0), 1.0, "{:?}", curve);
+            // progress outside 0..1 is clamped
+            assert_eq!(curve.apply(-0.5), 0.0, "{:?}", curve);
+            assert_eq!(curve.apply(1.5), 1.0, "{:?}", curve);
+        }
+    }
+
+    #[test]
+    fn curves_never_go_backwards() {
+        for curve in CURVES.iter() {
+            let mut previous = 0.0;
+            for step in 0..=100 {
+                let eased = curve.apply(step as f32 / 100.0);
+                assert!(eased >= previous, "{:?} goes from {} back to {} at {}", curve, previous, eased, step);
+                previous = eased;
+            }
+        }
+    }
+
+    #[test]
+    fn transition_finishes_after_its_duration() {
+        for curve in CURVES.iter() {
+            let transition = Transition { duration: 1.5, curve: *curve };
+            assert_eq!(transition.progress(0.0), 0.0);
+            assert!(transition.progress(0.75) < 1.0, "{:?}", curve);
+            assert_eq!(transition.progress(1.5), 1.0);
+            assert_eq!(transition.progress(10.0), 1.0);
+        }
+        let instant = Transition { duration: 0.0, curve: Easing::Linear };
+        assert_eq!(instant.progress(0.0), 1.0);
+    }
+
+    #[test]
+    fn every_transition_is_done_by_the_settings_duration() {
+        let settings = FocusSettings::default();
+        assert_eq!(settings.duration(), 1.5);
+        for transition in [&settings.position, &settings.distance, &settings.up].iter() {
+            assert_eq!(transition.progress(settings.duration()), 1.0);
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+
+// analogue input response, loaded from config/gamepad.ron
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct GamepadSettings {
+    // fraction of the stick travel around the centre that's ignored
+    pub dead_zone: f32,
+    // exponent of the response curve, 1 is linear, higher gives finer control near the centre
+    pub curve: f32,
+    // radians per second at full deflection
+    pub look_speed: f32,
+    pub invert_look: bool,
+}
+
+impl Default for GamepadSettings {
+    fn default() -> Self {
+        Self { dead_zone:0.15, curve:2.0, look_speed:2.0, invert_look:false }
+    }
+}
+
+impl GamepadSettings {
+    // axis value after the dead zone and curve, keys give -1, 0 or 1 which pass through unchanged
+    pub fn response(&self, value: f32) -> f32 {
+        let travel = ((value.abs() - self.dead_zone) / (1.0 - self.dead_zone).max(std::f32::EPSILON)).max(0.0).min(1.0);
+        travel.powf(self.curve).copysign(value)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(dead_zone: f32, curve: f32) -> GamepadSettings {
+        GamepadSettings { dead_zone, curve, ..Default::default() }
+    }
+
+    #[test]
+    fn dead_zone_gives_nothing() {
+        let settings = GamepadSettings::default();
+        for value in [0.0, 0.05, -0.1, 0.15, -0.15].iter() {
+            assert_eq!(settings.response(*value), 0.0, "{}", value);
+        }
+        assert!(settings.response(0.2) > 0.0);
+    }
+
+    #[test]
+    fn full_deflection_is_full_response() {
+        for curve in [0.5, 1.0, 2.0, 3.0].iter() {
+            let settings = settings(0.15, *curve);
+            assert_eq!(settings.response(1.0), 1.0);
+            assert_eq!(settings.response(-1.0), -1.0);
+        }
+    }
+
+    #[test]
+    fn response_keeps_the_sign() {
+        let settings = GamepadSettings::default();
+        for value in [0.2, 0.5, 0.9].iter() {
+            assert!(settings.response(*value) > 0.0);
+            assert_eq!(settings.response(-value), -settings.response(*value));
+        }
+    }
+
+    #[test]
+    fn whole_travel_dead_zone_stays_finite() {
+        let settings = settings(1.0, 2.0);
+        for step in -10..=10 {
+            let response = settings.response(step as f32 / 10.0);
+            assert!(response.is_finite(), "{} at {}", response, step);
+            assert_eq!(response, 0.0);
+        }
+    }
+}
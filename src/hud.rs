@@ -0,0 +1,102 @@
+use amethyst::{
+    ecs::prelude::{ Entity, System, SystemData, WriteStorage, Read },
+    derive::{SystemDesc},
+    core::timing::Time,
+    ui::{UiFinder, UiText},
+};
+use crate::flight::{FlightState, TimeWarp, Vessel, format_distance, format_speed, format_duration};
+
+// label ids in hud.ron, in the order of the readouts
+const LABELS: [&str; 12] = [
+    "hud_body",
+    "hud_altitude",
+    "hud_orbital_speed",
+    "hud_surface_speed",
+    "hud_vertical_speed",
+    "hud_apoapsis",
+    "hud_periapsis",
+    "hud_apsis_time",
+    "hud_escape",
+    "hud_fuel",
+    "hud_throttle",
+    "hud_warp",
+];
+
+// writes the flight state into the hud labels
+#[derive(SystemDesc)]
+#[system_desc(name(HudSystemDesc))]
+pub struct HudSystem {
+    // label entities, looked up once the ui has loaded
+    #[system_desc(skip)]
+    labels: Option<Vec<Entity>>,
+}
+
+impl HudSystem {
+    pub fn new() -> Self {
+        Self { labels:None }
+    }
+}
+
+impl<'s> System<'s> for HudSystem {
+    type SystemData = (
+        Read<'s, FlightState>,
+        Read<'s, Vessel>,
+        Read<'s, TimeWarp>,
+        Read<'s, Time>,
+        UiFinder<'s>,
+        WriteStorage<'s, UiText>,
+    );
+
+    fn run(&mut self, (state, vessel, warp, time, ui_finder, mut ui_texts) : Self::SystemData) {
+        if self.labels.is_none() {
+            self.labels = LABELS.iter().map(|id| ui_finder.find(id)).collect();
+        }
+        let labels = match &self.labels {
+            Some(labels) => labels,
+            None => return,
+        };
+        // every frame is too fast to read
+        if time.frame_number() % 10 != 0 {
+            return;
+        }
+
+        for (entity, text) in labels.iter().zip(readouts(&state, &vessel, &warp)) {
+            if let Some(ui) = ui_texts.get_mut(*entity) {
+                ui.text = text;
+            }
+        }
+    }
+}
+
+fn readouts(state: &FlightState, vessel: &Vessel, warp: &TimeWarp) -> Vec<String> {
+    let body = match &state.body {
+        Some(body) => body,
+        None => return LABELS.iter().map(|_| String::new()).collect(),
+    };
+    let orbit = state.orbit.as_ref();
+
+    // whichever apsis comes first
+    let apsis_time = orbit.and_then(|orbit| match (orbit.time_to_apoapsis, orbit.time_to_periapsis) {
+        (Some(ap), Some(pe)) if ap < pe => Some(format!("Ap in {}", format_duration(ap))),
+        (_, Some(pe)) => Some(format!("Pe in {}", format_duration(pe))),
+        (Some(ap), None) => Some(format!("Ap in {}", format_duration(ap))),
+        (None, None) => None,
+    });
+    let fuel = if vessel.fuel_capacity > 0.0 { vessel.fuel / vessel.fuel_capacity } else { 0.0 };
+
+    vec![
+        format!("Body: {}", body),
+        format!("Altitude: {}", format_distance(state.altitude)),
+        format!("Orbital speed: {}", format_speed(state.orbital_speed)),
+        format!("Surface speed: {}", format_speed(state.surface_speed)),
+        format!("Vertical speed: {}", format_speed(state.vertical_speed)),
+        // apsides are shown as altitudes like the altitude readout
+        format!("Apoapsis: {}", orbit.and_then(|o| o.apoapsis).map_or_else(|| "escape".to_string(), |ap| format_distance(ap - state.radius))),
+        format!("Periapsis: {}", orbit.map_or_else(|| "N/A".to_string(), |o| format_distance(o.periapsis - state.radius))),
+        format!("Next apsis: {}", apsis_time.unwrap_or_else(|| "N/A".to_string())),
+        format!("Escape margin: {}", format_speed(state.orbital_speed - state.escape_velocity)),
+        format!("Fuel: {:.0}% ({:.0} kg)", fuel * 100.0, vessel.fuel),
+        format!("Throttle: {:.0}%", vessel.throttle * 100.0),
+        format!("Time warp: {}x", warp.factor()),
+    ]
+}
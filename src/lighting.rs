@@ -0,0 +1,87 @@
+use amethyst::{
+    ecs::prelude::{ Join, System, SystemData, WriteStorage, ReadStorage },
+    derive::{SystemDesc},
+    core::{math::{Vector3}, transform::Transform},
+    renderer::{camera::Camera, light::Light},
+    utils::{
+        tag::{Tag},
+    },
+};
+use crate::origin::scene_position;
+use crate::planet::Planet;
+use crate::render::sun::Sun;
+
+// keeps every directional light pointing from the sun towards the lit body
+#[derive(SystemDesc)]
+#[system_desc(name(SunLightSystemDesc))]
+pub struct SunLightSystem {
+    // sun distance at which the lights have their configured intensity
+    #[system_desc(skip)]
+    reference_distance: Option<f32>,
+    // configured intensity of each directional light, in join order
+    #[system_desc(skip)]
+    base_intensities: Vec<f32>,
+}
+
+impl SunLightSystem {
+    pub fn new() -> Self {
+        Self { reference_distance:None, base_intensities:Vec::new() }
+    }
+}
+
+impl<'s> System<'s> for SunLightSystem {
+    type SystemData = (
+        ReadStorage<'s, Tag<Sun>>,
+        ReadStorage<'s, Planet>,
+        ReadStorage<'s, Camera>,
+        ReadStorage<'s, Transform>,
+        WriteStorage<'s, Light>,
+    );
+
+    fn run(&mut self, (suns, planets, cameras, transforms, mut lights) : Self::SystemData) {
+        // we need both a sun and a body to light before we can do anything
+        let sun_position = match (&suns, &transforms).join().next() {
+            Some((_, transform)) => scene_position(transform),
+            None => return,
+        };
+        // light the body closest to the camera, that's the one whose day/night is visible
+        let camera_position = (&cameras, &transforms).join()
+            .next()
+            .map_or(Vector3::zeros(), |(_, transform)| scene_position(transform));
+        let body_position = (&planets, &transforms, !&suns).join()
+            .map(|(_, transform, _)| scene_position(transform))
+            .min_by(|a, b| {
+                let a = (a - camera_position).norm_squared();
+                let b = (b - camera_position).norm_squared();
+                a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+            });
+        let body_position = match body_position {
+            Some(position) => position,
+            None => return,
+        };
+
+        let to_body = body_position - sun_position;
+        let distance = to_body.norm();
+        if distance <= std::f32::EPSILON {
+            return;
+        }
+        let direction = to_body / distance;
+
+        // the first distance we see is the one the scene was authored for, bodies further out
+        // from the sun get less light
+        let reference_distance = *self.reference_distance.get_or_insert(distance);
+        let falloff = (reference_distance / distance).powi(2);
+
+        let directionals = (&mut lights).join().filter_map(|light| match light {
+            Light::Directional(directional) => Some(directional),
+            _ => None,
+        });
+        for (index, directional) in directionals.enumerate() {
+            if self.base_intensities.len() <= index {
+                self.base_intensities.push(directional.intensity);
+            }
+            directional.direction = direction;
+            directional.intensity = self.base_intensities[index] * falloff;
+        }
+    }
+}
@@ -2,12 +2,22 @@ mod debug;
 mod planet;
 mod render;
 mod controls;
+mod lighting;
+mod origin;
+mod flight;
+mod hud;
+mod settings;
+mod bindings;
+mod gamepad;
+mod focus;
+mod picking;
+mod map;
+mod menu;
 
 use amethyst::{
     assets::{PrefabLoader, PrefabLoaderSystemDesc, RonFormat, PrefabData, ProgressCounter, AssetPrefab, Processor },
     core::{
         Transform,TransformBundle,
-        frame_limiter::FrameRateLimitStrategy,
         HideHierarchySystemDesc,
     },
     derive::{PrefabData},
@@ -41,12 +51,12 @@ use amethyst::{
     input::{
         is_close_requested, is_key_down, InputBundle, StringBindings
     },
-    window::{WindowBundle},
+    window::{WindowBundle, DisplayConfig},
+    config::Config,
     controls::{ArcBallControlBundle, ControlTagPrefab},
     winit::VirtualKeyCode,
     Error
 };
-use std::time::Duration;
 use serde::{Deserialize, Serialize};
 
 #[derive(Default, Deserialize, PrefabData, Serialize)]
@@ -59,10 +69,12 @@ struct ScenePrefab {
     camera: Option<CameraPrefab>,
     auto_fov: Option<AutoFov>,
     control_tag: Option<ControlTagPrefab>,
-    planet: Option<Tag<planet::Planet>>,
-    clouds: Option<Tag<planet::Clouds>>,
-    atmosphere: Option<Tag<render::atmosphere::Atmosphere>>,
+    planet: Option<planet::Planet>,
+    clouds: Option<planet::Clouds>,
+    atmosphere: Option<render::atmosphere::Atmosphere>,
     sun: Option<Tag<render::sun::Sun>>,
+    world_position: Option<origin::WorldPosition>,
+    ship: Option<Tag<flight::Ship>>,
 }
 
 #[derive(Default)]
@@ -77,15 +89,34 @@ impl SimpleState for MainState {
         data.world.register::<DebugLinesComponent>();
         data.world.register::<debug::FpsDisplay>();
 
-        // set fxaa enabled
-        data.world.insert(render::fxaa::FxaaSettings { enabled:true });
-        data.world.insert(render::tonemap::TonemapSettings { enabled:true, exposure:1.0 });
+        data.world.insert(render::tonemap::ColorGradingSettings {
+            lut: Some("lut/orbit.cube".to_string()),
+            lut_strength: 0.6,
+            ..Default::default()
+        });
+        data.world.insert(render::lens::LensSettings::default());
+        data.world.insert(render::debug_view::DebugViewSettings::default());
+        data.world.insert(render::depth::ClipPlanes::default());
+        data.world.insert(origin::FloatingOrigin::default());
+        data.world.insert(render::taa::TaaSettings::default());
+        data.world.insert(render::taa::TaaFrame::default());
+        data.world.insert(render::shader::ShaderReloads::default());
+        data.world.insert(flight::FlightState::default());
+        data.world.insert(flight::Vessel::default());
+        data.world.insert(controls::CameraMode::default());
+        data.world.insert(focus::Focus::default());
+        data.world.insert(picking::Picking::default());
+        data.world.insert(map::MapView::default());
+        data.world.insert(flight::TimeWarp::default());
+        data.world.insert(menu::MenuState::default());
 
         // register custom components
         data.world.register::<planet::Planet>();
         data.world.register::<planet::Clouds>();
         data.world.register::<render::atmosphere::Atmosphere>();
         data.world.register::<render::sun::Sun>();
+        data.world.register::<origin::WorldPosition>();
+        data.world.register::<flight::Ship>();
 
         // load the scene from the ron file
         let handle = data.world.exec(|loader: PrefabLoader<'_, ScenePrefab>| {
@@ -96,6 +127,10 @@ impl SimpleState for MainState {
         // load the ui
         data.world.exec(|mut creator: UiCreator<'_>| {
             creator.create("ui.ron",());
+            creator.create("hud.ron",());
+            creator.create("settings.ron",());
+            creator.create("controls.ron",());
+            creator.create("map.ron",());
         });
     }
 
@@ -105,9 +140,11 @@ impl SimpleState for MainState {
     }
 
     // handle application level events
-    fn handle_event(&mut self, _data: StateData<'_, GameData<'_, '_>>, event: StateEvent) -> SimpleTrans {
+    fn handle_event(&mut self, data: StateData<'_, GameData<'_, '_>>, event: StateEvent) -> SimpleTrans {
         if let StateEvent::Window(ref event) = event {
-            if is_close_requested(event) || is_key_down(event, VirtualKeyCode::Escape) {
+            // with a menu open escape closes it, or cancels a rebind, see menu.rs
+            let menu_open = data.world.read_resource::<menu::MenuState>().open;
+            if is_close_requested(event) || (is_key_down(event, VirtualKeyCode::Escape) && !menu_open) {
                 Trans::Quit
             } else {
                 Trans::None
@@ -127,6 +164,8 @@ fn main() -> amethyst::Result<()> {
     let assets_dir = app_root.join("assets");
     let config_dir = app_root.join("config");
     let display_config_path = config_dir.join("display.ron");
+    let display_config = DisplayConfig::load(&display_config_path)?;
+    let render_quality = render::quality::RenderQuality::load(config_dir.join("render.ron"))?;
     let key_bindings_path = {
         if cfg!(feature = "sdl_controller") {
             assets_dir.join("input_controller.ron")
@@ -135,6 +174,19 @@ fn main() -> amethyst::Result<()> {
         }
     };
 
+    // user overrides from the controls menu are layered on top of the bindings in assets
+    let (default_bindings, key_bindings) = bindings::load(&key_bindings_path, &config_dir.join("bindings.ron"))?;
+
+    let gamepad_settings = gamepad::GamepadSettings::load(config_dir.join("gamepad.ron"))?;
+    let focus_settings = focus::FocusSettings::load(config_dir.join("focus.ron"))?;
+    let graphics_settings = settings::GraphicsSettings::load(config_dir.join("graphics.ron"))?;
+
+    let anti_aliasing = render::ssaa::AntiAliasingSettings { mode:graphics_settings.anti_aliasing };
+    // fxaa is enabled when the anti-aliasing mode uses it
+    let fxaa_settings = render::fxaa::FxaaSettings { enabled:anti_aliasing.mode.fxaa() };
+    let tonemap_settings = graphics_settings.tonemap.clone();
+    let (frame_limit_strategy, frame_limit) = graphics_settings.frame_limit();
+
     // build gamedata
     let game_data = GameDataBuilder::default()
         .with_system_desc(
@@ -147,34 +199,130 @@ fn main() -> amethyst::Result<()> {
             "gltf_loader",
             &["scene_loader"]
         )
-        .with_bundle(TransformBundle::new())?
+        .with_bundle(
+            InputBundle::<StringBindings>::new().with_bindings(key_bindings),
+        )?
+        .with_bundle(ArcBallControlBundle::<StringBindings>::new())?
+        .with_system_desc(
+            controls::CameraModeSystemDesc::default(),
+            "camera_mode",
+            &["input_system"],
+        )
+        .with_system_desc(
+            picking::PickingSystemDesc::default(),
+            "picking",
+            &["input_system"],
+        )
+        .with_system_desc(
+            focus::FocusSystemDesc::default(),
+            "focus",
+            &["input_system", "camera_mode", "picking"],
+        )
+        .with_system_desc(
+            controls::CameraControlSystemDesc::default(),
+            "camera_controls",
+            &["input_system", "focus"],
+        )
+        .with_system_desc(
+            controls::FlySystemDesc::default(),
+            "camera_fly",
+            &["input_system", "camera_mode", "focus"],
+        )
+        .with_system_desc(
+            flight::ThrottleSystemDesc::default(),
+            "throttle_system",
+            &["input_system"]
+        )
+        .with_system_desc(
+            flight::EngineSystemDesc::default(),
+            "engine_system",
+            &["throttle_system", "camera_fly"]
+        )
+        .with_system_desc(
+            origin::FloatingOriginSystemDesc::default(),
+            "floating_origin",
+            &["arc_ball_rotation", "camera_fly", "engine_system", "focus"]
+        )
+        // after the floating origin, so the global matrices are from this frame's rebased transforms
+        .with_bundle(TransformBundle::new().with_dep(&["floating_origin"]))?
         .with_system_desc(
             HideHierarchySystemDesc::default(),
             "hide_hierarchy_system",
             &["parent_hierarchy_system"]
         )
         .with(AutoFovSystem::new(), "auto_fov", &["scene_loader"])
-        .with_bundle(
-            InputBundle::<StringBindings>::new().with_bindings_from_file(key_bindings_path)?,
-        )?
-        .with_bundle(UiBundle::<StringBindings>::new())?
-        .with_bundle(FpsCounterBundle::default())?
-        .with_bundle(ArcBallControlBundle::<StringBindings>::new())?
         .with_system_desc(
-            controls::CameraControlSystemDesc::default(),
-            "camera_controls",
-            &["input_system"],
+            render::depth::ClipPlaneSystemDesc::default(),
+            "clip_planes",
+            &["auto_fov", "transform_system"]
         )
+        .with_bundle(UiBundle::<StringBindings>::new())?
+        .with_bundle(FpsCounterBundle::default())?
         .with_system_desc(
             debug::DebugSystemDesc::default(),
             "debug_sytem",
             &["input_system"]
         )
+        .with_system_desc(
+            settings::SettingsSystemDesc::new(config_dir.clone()),
+            "settings_system",
+            &["input_system", "debug_sytem"]
+        )
+        .with_system_desc(
+            menu::MenuNavigationSystemDesc::default(),
+            "menu_navigation_system",
+            &["input_system", "settings_system"]
+        )
+        .with_system_desc(
+            bindings::ControlsMenuSystemDesc::new(config_dir.join("bindings.ron")),
+            "controls_menu_system",
+            &["input_system", "settings_system", "menu_navigation_system"]
+        )
+        .with_system_desc(
+            bindings::HelpSystemDesc::default(),
+            "help_system",
+            &["debug_sytem", "controls_menu_system"]
+        )
         .with_system_desc(
             planet::PlanetSystemDesc::default(),
             "planet_system",
             &[]
         )
+        .with_system_desc(
+            lighting::SunLightSystemDesc::default(),
+            "sun_light_system",
+            &["transform_system", "planet_system", "floating_origin"]
+        )
+        .with_system_desc(
+            flight::FlightSystemDesc::default(),
+            "flight_system",
+            &["transform_system", "floating_origin"]
+        )
+        .with_system_desc(
+            flight::TimeWarpSystemDesc::default(),
+            "time_warp_system",
+            &["input_system"]
+        )
+        .with_system_desc(
+            map::MapSystemDesc::default(),
+            "map_system",
+            &["input_system", "picking", "flight_system", "time_warp_system"]
+        )
+        .with_system_desc(
+            hud::HudSystemDesc::default(),
+            "hud_system",
+            &["flight_system", "engine_system"]
+        )
+        .with_system_desc(
+            render::dynamic_resolution::DynamicResolutionSystemDesc::default(),
+            "dynamic_resolution",
+            &[]
+        )
+        .with_system_desc(
+            render::taa::TaaJitterSystemDesc::default(),
+            "taa_jitter",
+            &["clip_planes", "transform_system", "floating_origin", "dynamic_resolution"]
+        )
         .with_system_desc(
             UiGlyphsSystemDesc::<DefaultBackend>::default(),
             "ui_glyph_system",
@@ -196,18 +344,31 @@ fn main() -> amethyst::Result<()> {
             &[],
         )
         .with(Processor::<Material>::new(), "material_processor", &[])
-        .with_bundle(WindowBundle::from_config_path(display_config_path)?)?
+        .with_bundle(WindowBundle::from_config(display_config.clone()))?;
+
+    // recompile shaders on save, before the render system so it rebuilds the same frame
+    #[cfg(feature = "shader_reload")]
+    let game_data = game_data.with_thread_local(
+        render::shader_reload::ShaderReloadSystem::new(assets_dir.join("shader")),
+    );
+
+    let game_data = game_data
         .with_thread_local(RenderingSystem::<DefaultBackend, _>::new(
             render::graph::RenderGraph::default(),
         ));
 
     // build application and run it
     let mut game = Application::build(assets_dir, MainState::default())?
-        //.with_frame_limit(FrameRateLimitStrategy::Unlimited, 9999) // this eats all available CPU cycles
-        .with_frame_limit(
-            FrameRateLimitStrategy::SleepAndYield(Duration::from_millis(2)),
-            144,
-        )
+        .with_resource(anti_aliasing)
+        .with_resource(fxaa_settings)
+        .with_resource(tonemap_settings)
+        .with_resource(graphics_settings)
+        .with_resource(display_config)
+        .with_resource(bindings::DefaultBindings { bindings:default_bindings })
+        .with_resource(gamepad_settings)
+        .with_resource(focus_settings)
+        .with_resource(render_quality)
+        .with_frame_limit(frame_limit_strategy, frame_limit)
         .build(game_data)?;
     game.run();
 
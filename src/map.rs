@@ -0,0 +1,676 @@
+use amethyst::{
+    controls::HideCursor,
+    core::{
+        math::{Point3, UnitQuaternion, Vector3, Vector4},
+        shrev::{EventChannel, ReaderId},
+        timing::Time,
+        transform::Transform,
+        HiddenPropagate,
+    },
+    derive::SystemDesc,
+    ecs::prelude::{ Join, Entity, Entities, Read, ReadExpect, ReadStorage, System, SystemData, Write, WriteStorage },
+    input::{InputEvent, InputHandler, StringBindings},
+    renderer::{
+        camera::{Camera, Projection},
+        debug_drawing::DebugLines,
+        palette::Srgba,
+        ActiveCamera,
+    },
+    ui::{UiText, UiTransform},
+    utils::{auto_fov::AutoFov, tag::Tag},
+    window::ScreenDimensions,
+    winit::MouseButton,
+};
+use std::{cmp::Ordering, f64::consts::PI};
+use crate::controls::{ZOOM_RATE, ZOOM_SMOOTHING, ZOOM_STEP};
+use crate::flight::{FlightState, Ship, TimeWarp, METERS_PER_UNIT};
+use crate::focus::Focus;
+use crate::gamepad::GamepadSettings;
+use crate::menu::MenuState;
+use crate::origin::{FloatingOrigin, WorldPosition};
+use crate::picking::Picking;
+use crate::planet::Planet;
+
+// the map camera sees the whole system, its depth range is far wider than the flight camera's
+const MAP_FOV: f32 = 1.0;
+const MAP_ZNEAR: f32 = 0.5;
+const MAP_ZFAR: f32 = 1_000_000.0;
+// radians per pixel of right mouse drag
+const DRAG_SPEED: f32 = 0.005;
+// how quickly the map centre follows its body, per second
+const CENTRE_SMOOTHING: f32 = 6.0;
+// sizes on screen in pixels
+const ICON_SIZE: f32 = 6.0;
+const LABEL_OFFSET: f32 = 12.0;
+const LABEL_SPACING: f32 = 18.0;
+const CIRCLE_SEGMENTS: usize = 64;
+const CONIC_SEGMENTS: usize = 256;
+// the prediction steps a fraction of the local orbital timescale, up to this many times
+const PREDICTION_STEP: f64 = 0.01;
+const PREDICTION_STEPS: usize = 2000;
+// how far ahead an escape trajectory is predicted, in seconds
+const ESCAPE_HORIZON: f64 = 30.0 * 86400.0;
+
+// a body as the map sees it, distances in meters
+#[derive(Clone, Debug)]
+pub struct MapBody {
+    pub entity: Entity,
+    pub name: String,
+    pub position: Vector3<f64>,
+    pub radius: f64,
+    pub mu: f64,
+    // the body whose sphere of influence it's in, none for the sun
+    pub parent: Option<usize>,
+    // radius of its own sphere of influence, infinite for the sun
+    pub soi: f64,
+}
+
+// laplace sphere of influence of a body at a distance from a much heavier parent
+pub fn soi_radius(distance: f64, mu: f64, parent_mu: f64) -> f64 {
+    distance * (mu / parent_mu).powf(0.4)
+}
+
+// fills in the parents and spheres of influence, the heaviest body is the root
+pub fn build_hierarchy(bodies: &mut [MapBody]) {
+    let mut order = (0..bodies.len()).collect::<Vec<_>>();
+    order.sort_by(|a, b| bodies[*b].mu.partial_cmp(&bodies[*a].mu).unwrap_or(Ordering::Equal));
+    for (rank, index) in order.iter().copied().enumerate() {
+        // the smallest sphere of influence of a heavier body this one is in
+        let parent = order[..rank].iter()
+            .copied()
+            .filter(|parent| (bodies[*parent].position - bodies[index].position).norm() < bodies[*parent].soi)
+            .min_by(|a, b| bodies[*a].soi.partial_cmp(&bodies[*b].soi).unwrap_or(Ordering::Equal));
+        bodies[index].parent = parent;
+        bodies[index].soi = match parent {
+            Some(parent) => soi_radius((bodies[parent].position - bodies[index].position).norm(), bodies[index].mu, bodies[parent].mu),
+            None => std::f64::INFINITY,
+        };
+    }
+}
+
+// the body whose sphere of influence a point is in
+pub fn dominant_body(bodies: &[MapBody], point: &Vector3<f64>) -> Option<usize> {
+    (0..bodies.len())
+        .filter(|index| (bodies[*index].position - point).norm() < bodies[*index].soi)
+        .min_by(|a, b| bodies[*a].soi.partial_cmp(&bodies[*b].soi).unwrap_or(Ordering::Equal))
+}
+
+// points along the conic through a state relative to a body, none where it leaves the sphere of influence
+pub fn conic_points(mu: f64, position: &Vector3<f64>, velocity: &Vector3<f64>, soi: f64, segments: usize) -> Vec<Option<Vector3<f64>>> {
+    let distance = position.norm();
+    let momentum = position.cross(velocity);
+    // falling straight up or down has no plane to draw the conic in
+    if mu <= 0.0 || distance <= 0.0 || momentum.norm() <= 1e-9 * distance * velocity.norm() {
+        return Vec::new();
+    }
+    let eccentricity_vector = ((velocity.norm_squared() - mu / distance) * position - position.dot(velocity) * velocity) / mu;
+    let eccentricity = eccentricity_vector.norm();
+    let semi_latus_rectum = momentum.norm_squared() / mu;
+    let periapsis = if eccentricity > 1e-6 { eccentricity_vector / eccentricity } else { position / distance };
+    let normal = momentum.normalize().cross(&periapsis);
+    // the whole ellipse, or a hyperbola up to just short of its asymptotes
+    let limit = if eccentricity < 1.0 { PI } else { (-1.0 / eccentricity).acos() * 0.999 };
+    (0..=segments)
+        .map(|i| {
+            let anomaly = -limit + 2.0 * limit * i as f64 / segments as f64;
+            let radius = semi_latus_rectum / (1.0 + eccentricity * anomaly.cos());
+            if radius > 0.0 && radius <= soi {
+                Some((periapsis * anomaly.cos() + normal * anomaly.sin()) * radius)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+// patched conic prediction, integrating the pull of whichever body's sphere of influence the craft is in.
+// the bodies don't orbit in this scene, so they're held where they are for the whole prediction
+pub fn predict(bodies: &[MapBody], position: &Vector3<f64>, velocity: &Vector3<f64>, duration: f64) -> Vec<Vector3<f64>> {
+    let acceleration = |point: &Vector3<f64>| -> Option<(Vector3<f64>, f64, bool)> {
+        let body = &bodies[dominant_body(bodies, point)?];
+        let relative = point - body.position;
+        let distance = relative.norm();
+        if distance <= 0.0 || body.mu <= 0.0 {
+            return None;
+        }
+        let timescale = (distance.powi(3) / body.mu).sqrt();
+        Some((-relative * body.mu / distance.powi(3), timescale, distance <= body.radius))
+    };
+
+    let (mut position, mut velocity) = (*position, *velocity);
+    let mut points = vec![position];
+    let mut elapsed = 0.0;
+    for _ in 0..PREDICTION_STEPS {
+        let (pull, timescale, crashed) = match acceleration(&position) {
+            Some(state) => state,
+            None => break,
+        };
+        if crashed || elapsed >= duration {
+            break;
+        }
+        // velocity verlet, it keeps orbits closed where euler would spiral out
+        let step = (timescale * PREDICTION_STEP).min(duration - elapsed);
+        velocity += pull * step / 2.0;
+        position += velocity * step;
+        if let Some((pull, _, _)) = acceleration(&position) {
+            velocity += pull * step / 2.0;
+        }
+        elapsed += step;
+        points.push(position);
+    }
+    points
+}
+
+// whether the map is showing and the body it's centred on
+#[derive(Default)]
+pub struct MapView {
+    pub open: bool,
+    // picked separately from the flight focus so the flight camera is left alone
+    pub centre: Option<Entity>,
+}
+
+// ui entities of the map overlay
+struct MapUi {
+    container: Entity,
+    title: Entity,
+    labels: Vec<Entity>,
+}
+
+// schematic map of the system with its own camera, the flight camera is rendered again when it closes
+#[derive(SystemDesc)]
+#[system_desc(name(MapSystemDesc))]
+pub struct MapSystem {
+    #[system_desc(event_channel_reader)]
+    event_reader: ReaderId<InputEvent<StringBindings>>,
+    #[system_desc(skip)]
+    camera: Option<Entity>,
+    // the rendered camera and whether the cursor was captured before the map opened
+    #[system_desc(skip)]
+    restore: Option<(Option<Entity>, bool)>,
+    #[system_desc(skip)]
+    ui: Option<MapUi>,
+    // where the map camera looks, following the centre body in world units
+    #[system_desc(skip)]
+    centre: Option<Vector3<f64>>,
+    #[system_desc(skip)]
+    distance: f32,
+    #[system_desc(skip)]
+    target_distance: f32,
+    #[system_desc(skip)]
+    yaw: f32,
+    #[system_desc(skip)]
+    pitch: f32,
+    // cursor position while dragging with the right mouse button
+    #[system_desc(skip)]
+    drag: Option<(f32, f32)>,
+}
+
+impl MapSystem {
+    pub fn new(event_reader: ReaderId<InputEvent<StringBindings>>) -> Self {
+        Self {
+            event_reader,
+            camera: None,
+            restore: None,
+            ui: None,
+            centre: None,
+            distance: 100.0,
+            target_distance: 100.0,
+            yaw: 0.0,
+            pitch: 1.0,
+            drag: None,
+        }
+    }
+}
+
+impl<'s> System<'s> for MapSystem {
+    type SystemData = (
+        Read<'s, EventChannel<InputEvent<StringBindings>>>,
+        Read<'s, InputHandler<StringBindings>>,
+        Write<'s, MapView>,
+        Read<'s, Focus>,
+        Read<'s, FlightState>,
+        Read<'s, TimeWarp>,
+        Read<'s, GamepadSettings>,
+        Read<'s, Picking>,
+        Read<'s, FloatingOrigin>,
+        Read<'s, Time>,
+        Read<'s, MenuState>,
+        Write<'s, ActiveCamera>,
+        Write<'s, HideCursor>,
+        Write<'s, DebugLines>,
+        ReadExpect<'s, ScreenDimensions>,
+        Entities<'s>,
+        WriteStorage<'s, Camera>,
+        WriteStorage<'s, AutoFov>,
+        ReadStorage<'s, Planet>,
+        ReadStorage<'s, Tag<Ship>>,
+        ReadStorage<'s, WorldPosition>,
+        WriteStorage<'s, Transform>,
+        WriteStorage<'s, UiTransform>,
+        WriteStorage<'s, UiText>,
+        WriteStorage<'s, HiddenPropagate>,
+    );
+
+    fn run(&mut self, (events, input, mut map, focus, state, warp, gamepad, picking, origin, time, menu, mut active_camera, mut hide_cursor, mut lines, screen, entities, mut cameras, mut auto_fovs, planets, ships, positions, mut transforms, mut ui_transforms, mut ui_texts, mut hidden):Self::SystemData) {
+        let mut toggle = false;
+        let mut next = false;
+        let mut pick = false;
+        for event in events.read(&mut self.event_reader) {
+            match event {
+                InputEvent::ActionPressed(action) if action == "map" => toggle = !toggle,
+                InputEvent::ActionPressed(action) if action == "focus_next" && !menu.open => next = true,
+                InputEvent::ActionPressed(action) if action == "focus_pick" && !menu.open => pick = true,
+                _ => (),
+            }
+        }
+
+        if self.ui.is_none() {
+            let find = |id: &str| (&entities, &ui_transforms).join().find(|(_, transform)| transform.id == id).map(|(entity, _)| entity);
+            let labels = (0..).map(|index| find(&format!("map_label_{}", index))).take_while(Option::is_some).flatten().collect();
+            if let (Some(container), Some(title)) = (find("map_container"), find("map_title")) {
+                self.ui = Some(MapUi { container, title, labels });
+            }
+        }
+
+        // bodies in scene order, the same order the focus cycles through
+        let mut bodies = (&entities, &planets, &positions, &transforms).join()
+            .map(|(entity, planet, position, transform)| {
+                let radius = transform.scale().x as f64 * METERS_PER_UNIT;
+                MapBody {
+                    entity,
+                    name: planet.name.clone(),
+                    position: position.position * METERS_PER_UNIT,
+                    radius,
+                    mu: planet.mu(radius),
+                    parent: None,
+                    soi: std::f64::INFINITY,
+                }
+            })
+            .collect::<Vec<_>>();
+        build_hierarchy(&mut bodies);
+        let ship = (&entities, &ships, &positions).join().next().map(|(entity, _, position)| (entity, position.position));
+        // how far out to look at a body from, its moons' orbits should fit
+        let default_distance = |entity: Option<Entity>| -> f32 {
+            let index = match entity.and_then(|entity| bodies.iter().position(|body| body.entity == entity)) {
+                Some(index) => index,
+                None => return 10.0,
+            };
+            let orbits = bodies.iter()
+                .filter(|body| body.parent == Some(index))
+                .map(|body| (body.position - bodies[index].position).norm())
+                .fold(0.0, f64::max);
+            ((orbits * 2.5).max(bodies[index].radius * 10.0) / METERS_PER_UNIT) as f32
+        };
+
+        if toggle {
+            map.open = !map.open;
+            if map.open {
+                // created once, after the flight camera, so everything looking for the first camera still finds that
+                let camera = match self.camera {
+                    Some(camera) => camera,
+                    None => {
+                        let camera = entities.build_entity()
+                            .with(Camera::from(Projection::perspective(screen.aspect_ratio(), MAP_FOV, MAP_ZNEAR, MAP_ZFAR)), &mut cameras)
+                            .with(AutoFov::default(), &mut auto_fovs)
+                            .with(Transform::default(), &mut transforms)
+                            .build();
+                        self.camera = Some(camera);
+                        camera
+                    },
+                };
+                self.restore = Some((active_camera.entity, hide_cursor.hide));
+                active_camera.entity = Some(camera);
+                // the cursor is free on the map for picking and dragging
+                hide_cursor.hide = false;
+                map.centre = focus.target;
+                self.centre = None;
+                self.target_distance = default_distance(map.centre);
+                self.distance = self.target_distance;
+            } else if let Some((camera, hide)) = self.restore.take() {
+                active_camera.entity = camera;
+                hide_cursor.hide = hide;
+            }
+            if let Some(ui) = &self.ui {
+                if map.open {
+                    hidden.remove(ui.container);
+                } else {
+                    hidden.insert(ui.container, HiddenPropagate::new()).expect("Failed to create HiddenPropagate component");
+                }
+            }
+            log::info!("Map {}", if map.open { "opened" } else { "closed" });
+        }
+        let camera = match self.camera {
+            Some(camera) if map.open => camera,
+            _ => return,
+        };
+
+        // pick the centre the way the flight camera picks its focus
+        let targets = bodies.iter().map(|body| body.entity).chain(ship.map(|(entity, _)| entity)).collect::<Vec<_>>();
+        let mut selected = None;
+        if next {
+            let index = map.centre.and_then(|centre| targets.iter().position(|entity| *entity == centre));
+            selected = index.map_or_else(|| targets.first(), |index| targets.get((index + 1) % targets.len())).copied();
+        }
+        if pick {
+            selected = picking.hover.as_ref().map(|hit| hit.entity).or(selected);
+        }
+        if let Some(entity) = selected {
+            map.centre = Some(entity);
+            self.target_distance = default_distance(map.centre);
+        }
+        let centre_body = map.centre.and_then(|centre| bodies.iter().find(|body| body.entity == centre));
+        let centre_target = match (centre_body, ship) {
+            (Some(body), _) => body.position / METERS_PER_UNIT,
+            (None, Some((_, position))) => position,
+            (None, None) => return,
+        };
+        let centre_radius = centre_body.map_or(0.0, |body| (body.radius / METERS_PER_UNIT) as f32);
+
+        // zoom and turn in real time, the map keeps working under time warp
+        let seconds = time.delta_real_seconds();
+        let zoom = gamepad.response(input.axis_value("zoom").unwrap_or(0.0) as f32);
+        let steps = if input.mouse_wheel_value(false) != 0.0 { zoom } else { zoom * ZOOM_RATE * seconds };
+        let min_distance = centre_radius * 3.0 + 2.0 * MAP_ZNEAR;
+        self.target_distance = (self.target_distance * ZOOM_STEP.powf(steps)).max(min_distance).min(MAP_ZFAR * 0.5);
+        let blend = (ZOOM_SMOOTHING * seconds).min(1.0);
+        self.distance = (self.distance.ln() + (self.target_distance.ln() - self.distance.ln()) * blend).exp();
+
+        let speed = gamepad.look_speed * seconds;
+        self.yaw -= gamepad.response(input.axis_value("camera_yaw").unwrap_or(0.0) as f32) * speed;
+        self.pitch += gamepad.response(input.axis_value("camera_pitch").unwrap_or(0.0) as f32) * speed;
+        match (input.mouse_button_is_down(MouseButton::Right), input.mouse_position()) {
+            (true, Some(cursor)) => {
+                if let Some(previous) = self.drag {
+                    self.yaw -= (cursor.0 - previous.0) * DRAG_SPEED;
+                    self.pitch += (cursor.1 - previous.1) * DRAG_SPEED;
+                }
+                self.drag = Some(cursor);
+            },
+            _ => self.drag = None,
+        }
+        self.pitch = self.pitch.max(-1.55).min(1.55);
+
+        let centre = match self.centre {
+            Some(centre) => centre + (centre_target - centre) * (CENTRE_SMOOTHING * seconds).min(1.0) as f64,
+            None => centre_target,
+        };
+        self.centre = Some(centre);
+        let rotation = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), self.yaw)
+            * UnitQuaternion::from_axis_angle(&Vector3::x_axis(), -self.pitch);
+        let camera_position = origin.to_local(&centre) + rotation * Vector3::new(0.0, 0.0, self.distance);
+        if let Some(transform) = transforms.get_mut(camera) {
+            transform.set_translation(camera_position);
+            transform.set_rotation(rotation);
+        }
+
+        // icons and labels keep their size on screen, so they're scaled with the distance to the camera
+        let projection = match cameras.get(camera) {
+            Some(camera) => camera.projection().as_matrix().clone_owned(),
+            None => return,
+        };
+        let pixel = 2.0 / (projection[(1, 1)].abs() * screen.height());
+        let (right, up) = (rotation * Vector3::x(), rotation * Vector3::y());
+        let to_local = |meters: &Vector3<f64>| origin.to_local(&(meters / METERS_PER_UNIT));
+        let screen_size = |point: &Vector3<f32>, pixels: f32| (point - camera_position).norm() * pixel * pixels;
+
+        for body in bodies.iter() {
+            let position = to_local(&body.position);
+            let colour = match body.parent {
+                None => Srgba::new(1.0, 0.85, 0.3, 1.0),
+                Some(parent) if bodies[parent].parent.is_none() => Srgba::new(0.4, 0.7, 1.0, 1.0),
+                Some(_) => Srgba::new(0.7, 0.7, 0.7, 1.0),
+            };
+            let radius = ((body.radius / METERS_PER_UNIT) as f32).max(screen_size(&position, ICON_SIZE));
+            circle(&mut lines, &position, &(right * radius), &(up * radius), colour);
+
+            if let Some(parent) = body.parent {
+                // bodies are on rails and never actually move, the orbit is a nominal circle in the parent's xz plane
+                // through where the body sits, not a conic from its velocity
+                let centre = to_local(&bodies[parent].position);
+                let offset = position - centre;
+                let across = Vector3::y().cross(&offset);
+                if across.norm() > std::f32::EPSILON {
+                    let across = across.normalize() * offset.norm();
+                    circle(&mut lines, &centre, &offset, &across, Srgba::new(colour.red, colour.green, colour.blue, 0.4));
+                }
+                let soi = (body.soi / METERS_PER_UNIT) as f32;
+                circle(&mut lines, &position, &(right * soi), &(up * soi), Srgba::new(0.5, 0.5, 0.8, 0.3));
+            }
+        }
+
+        if let Some((_, ship_position)) = ship {
+            let position = origin.to_local(&ship_position);
+            let size = screen_size(&position, ICON_SIZE);
+            let colour = Srgba::new(0.3, 1.0, 0.4, 1.0);
+            let corners = [position + up * size, position + right * size, position - up * size, position - right * size];
+            for (i, corner) in corners.iter().enumerate() {
+                lines.draw_line(Point3::from(*corner), Point3::from(corners[(i + 1) % corners.len()]), colour);
+            }
+
+            // the orbit around the body the hud reports on, then where the ship goes once it leaves it
+            if let Some(body) = state.body.as_ref().and_then(|name| bodies.iter().find(|body| body.name == *name)) {
+                let points = conic_points(state.mu, &state.position, &state.velocity, body.soi, CONIC_SEGMENTS);
+                for pair in points.windows(2) {
+                    if let (Some(a), Some(b)) = (pair[0], pair[1]) {
+                        lines.draw_line(Point3::from(to_local(&(body.position + a))), Point3::from(to_local(&(body.position + b))), colour);
+                    }
+                }
+            }
+            let duration = match state.orbit.as_ref().filter(|orbit| orbit.apoapsis.is_some() && state.mu > 0.0) {
+                Some(orbit) => 1.5 * 2.0 * PI * (orbit.semi_major_axis.powi(3) / state.mu).sqrt(),
+                None => ESCAPE_HORIZON,
+            };
+            let prediction = predict(&bodies, &(ship_position * METERS_PER_UNIT), &state.velocity, duration);
+            for pair in prediction.windows(2) {
+                lines.draw_line(Point3::from(to_local(&pair[0])), Point3::from(to_local(&pair[1])), Srgba::new(1.0, 0.5, 0.2, 0.8));
+            }
+        }
+
+        // labels next to the icons, heaviest first, skipping any that would overlap one already placed
+        let ui = match &self.ui {
+            Some(ui) => ui,
+            None => return,
+        };
+        if let Some(title) = ui_texts.get_mut(ui.title) {
+            let name = centre_body.map_or("Ship", |body| body.name.as_str());
+            title.text = format!("Map: {}    Time warp: {}x", name, warp.factor());
+        }
+        let mut named = bodies.iter().map(|body| (body.mu, body.name.clone(), body.position / METERS_PER_UNIT)).collect::<Vec<_>>();
+        named.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+        if let Some((_, position)) = ship {
+            named.push((0.0, "Ship".to_string(), position));
+        }
+        let view = transforms.get(camera).and_then(|transform| transform.global_matrix().try_inverse());
+        let mut placed: Vec<(f32, f32)> = Vec::new();
+        let mut labels = ui.labels.iter();
+        for (_, name, position) in named.iter() {
+            let point = origin.to_local(position);
+            let clip = match view {
+                Some(view) => projection * view * Vector4::new(point.x, point.y, point.z, 1.0),
+                None => break,
+            };
+            // behind the camera
+            if clip.w <= 0.0 {
+                continue;
+            }
+            // the projection flips y, so this is from the top like the cursor
+            let x = (clip.x / clip.w * 0.5 + 0.5) * screen.width();
+            let y = (clip.y / clip.w * 0.5 + 0.5) * screen.height();
+            if placed.iter().any(|other| (other.0 - x).hypot(other.1 - y) < LABEL_SPACING) {
+                continue;
+            }
+            let label = match labels.next() {
+                Some(label) => *label,
+                None => break,
+            };
+            placed.push((x, y));
+            if let Some(transform) = ui_transforms.get_mut(label) {
+                transform.local_x = x + LABEL_OFFSET;
+                transform.local_y = screen.height() - y;
+            }
+            if let Some(text) = ui_texts.get_mut(label) {
+                text.text = name.clone();
+            }
+        }
+        for label in labels {
+            if let Some(text) = ui_texts.get_mut(*label) {
+                text.text.clear();
+            }
+        }
+    }
+}
+
+fn circle(lines: &mut DebugLines, centre: &Vector3<f32>, a: &Vector3<f32>, b: &Vector3<f32>, colour: Srgba) {
+    let point = |i: usize| {
+        let angle = 2.0 * std::f32::consts::PI * i as f32 / CIRCLE_SEGMENTS as f32;
+        Point3::from(centre + a * angle.cos() + b * angle.sin())
+    };
+    for i in 0..CIRCLE_SEGMENTS {
+        lines.draw_line(point(i), point(i + 1), colour);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst::ecs::{World, WorldExt};
+
+    const SUN: usize = 0;
+    const EARTH: usize = 1;
+    const MOON: usize = 2;
+    const MARS: usize = 3;
+
+    // the bodies in the scene the way the map system builds them, positions and radii in scene units
+    fn scene() -> Vec<MapBody> {
+        let world = World::new();
+        let body = |name: &str, position: [f64; 3], radius: f64, gravity: f64| {
+            let radius = radius * METERS_PER_UNIT;
+            MapBody {
+                entity: world.entities().create(),
+                name: name.to_string(),
+                position: Vector3::from(position) * METERS_PER_UNIT,
+                radius,
+                mu: gravity * radius * radius,
+                parent: None,
+                soi: 0.0,
+            }
+        };
+        let mut bodies = vec![
+            body("Sun", [23481.0, 0.0, 0.0], 109.166, 274.0),
+            body("Earth", [0.0, 0.0, 0.0], 1.0, 9.81),
+            body("Moon", [0.0, 0.0, 60.3], 0.273, 1.62),
+            body("Mars", [23481.0, 0.0, 35691.0], 0.532, 3.72),
+        ];
+        build_hierarchy(&mut bodies);
+        bodies
+    }
+
+    fn assert_relative(a: f64, b: f64, tolerance: f64) {
+        assert!((a - b).abs() <= tolerance * b.abs(), "{} != {}", a, b);
+    }
+
+    #[test]
+    fn earth_soi_is_about_a_million_km() {
+        let bodies = scene();
+        let distance = (bodies[EARTH].position - bodies[SUN].position).norm();
+        // the textbook laplace radius is 924000 km
+        assert_relative(soi_radius(distance, bodies[EARTH].mu, bodies[SUN].mu), 9.24e8, 0.02);
+        assert_eq!(bodies[EARTH].soi, soi_radius(distance, bodies[EARTH].mu, bodies[SUN].mu));
+    }
+
+    #[test]
+    fn soi_grows_with_distance_and_mass() {
+        assert_eq!(soi_radius(2.0, 1.0, 1e5), 2.0 * soi_radius(1.0, 1.0, 1e5));
+        assert!(soi_radius(1.0, 2.0, 1e5) > soi_radius(1.0, 1.0, 1e5));
+        assert_eq!(soi_radius(1.0, 1.0, 1.0), 1.0);
+    }
+
+    #[test]
+    fn hierarchy_follows_the_spheres_of_influence() {
+        let bodies = scene();
+        assert_eq!(bodies[SUN].parent, None);
+        assert!(bodies[SUN].soi.is_infinite());
+        assert_eq!(bodies[EARTH].parent, Some(SUN));
+        assert_eq!(bodies[MOON].parent, Some(EARTH));
+        assert_eq!(bodies[MARS].parent, Some(SUN));
+        assert!(bodies[MOON].soi < bodies[EARTH].soi);
+        assert_eq!(dominant_body(&bodies, &bodies[MOON].position), Some(MOON));
+        assert_eq!(dominant_body(&bodies, &(bodies[EARTH].position + Vector3::new(0.0, 1e8, 0.0))), Some(EARTH));
+        assert_eq!(dominant_body(&bodies, &(bodies[EARTH].position + Vector3::new(0.0, 1e10, 0.0))), Some(SUN));
+    }
+
+    #[test]
+    fn circular_orbit_conic_keeps_its_radius() {
+        let mu: f64 = 3.986e14;
+        let radius = 7e6;
+        let position = Vector3::new(radius, 0.0, 0.0);
+        let velocity = Vector3::new(0.0, 0.0, -(mu / radius).sqrt());
+        let points = conic_points(mu, &position, &velocity, 1e9, 64);
+        assert_eq!(points.len(), 65);
+        for point in points {
+            let point = point.expect("a closed orbit inside the sphere of influence is drawn all the way round");
+            assert_relative(point.norm(), radius, 1e-9);
+            assert!(point.y.abs() < 1e-6 * radius);
+        }
+    }
+
+    #[test]
+    fn escape_conic_stops_at_the_soi() {
+        let mu: f64 = 3.986e14;
+        let radius = 7e6;
+        let soi = 10.0 * radius;
+        let position = Vector3::new(radius, 0.0, 0.0);
+        let velocity = Vector3::new(0.0, 0.0, -1.5 * (2.0 * mu / radius).sqrt());
+        let points = conic_points(mu, &position, &velocity, soi, 256);
+        assert!(points.first().unwrap().is_none() && points.last().unwrap().is_none());
+        let inside = points.iter().flatten().collect::<Vec<_>>();
+        assert!(!inside.is_empty());
+        assert!(inside.iter().all(|point| point.norm() <= soi));
+        // it starts at periapsis, which is as low as it gets
+        assert!(inside.iter().all(|point| point.norm() >= radius * (1.0 - 1e-9)));
+    }
+
+    #[test]
+    fn radial_fall_has_no_conic() {
+        let position = Vector3::new(7e6, 0.0, 0.0);
+        assert!(conic_points(3.986e14, &position, &(position * 1e-3), 1e9, 64).is_empty());
+    }
+
+    #[test]
+    fn closed_orbit_comes_back_round() {
+        let bodies = scene();
+        let mu = bodies[EARTH].mu;
+        let radius = 2.0 * bodies[EARTH].radius;
+        let position = bodies[EARTH].position + Vector3::new(radius, 0.0, 0.0);
+        let velocity = Vector3::new(0.0, 0.0, -(mu / radius).sqrt());
+        let period = 2.0 * PI * (radius.powi(3) / mu).sqrt();
+        let points = predict(&bodies, &position, &velocity, period);
+        assert!(points.len() < PREDICTION_STEPS, "ran out of steps before the orbit closed");
+        for point in points.iter() {
+            assert_relative((point - bodies[EARTH].position).norm(), radius, 1e-3);
+        }
+        assert!((points.last().unwrap() - position).norm() < 1e-2 * radius);
+    }
+
+    #[test]
+    fn escape_ends_up_around_the_sun() {
+        let bodies = scene();
+        let mu = bodies[EARTH].mu;
+        let radius = 2.0 * bodies[EARTH].radius;
+        let position = bodies[EARTH].position + Vector3::new(radius, 0.0, 0.0);
+        let velocity = Vector3::new(0.0, 0.0, -1.5 * (2.0 * mu / radius).sqrt());
+        let points = predict(&bodies, &position, &velocity, ESCAPE_HORIZON);
+        let last = points.last().unwrap();
+        assert!((last - bodies[EARTH].position).norm() > bodies[EARTH].soi);
+        assert_eq!(dominant_body(&bodies, last), Some(SUN));
+    }
+
+    #[test]
+    fn prediction_stops_at_the_surface() {
+        let bodies = scene();
+        let position = bodies[EARTH].position + Vector3::new(2.0 * bodies[EARTH].radius, 0.0, 0.0);
+        let points = predict(&bodies, &position, &Vector3::zeros(), ESCAPE_HORIZON);
+        assert!(points.len() < PREDICTION_STEPS);
+        assert!((points.last().unwrap() - bodies[EARTH].position).norm() <= bodies[EARTH].radius);
+    }
+}
@@ -0,0 +1,312 @@
+use amethyst::{
+    core::{
+        shrev::{EventChannel, ReaderId},
+        HiddenPropagate,
+    },
+    derive::SystemDesc,
+    ecs::prelude::{ Entity, Read, ReadStorage, System, SystemData, Write, WriteStorage },
+    input::{Bindings, Button, InputEvent, InputHandler, StringBindings},
+    ui::{UiEvent, UiEventType, UiFinder, UiText},
+    winit::VirtualKeyCode,
+};
+use crate::bindings;
+use crate::settings;
+
+const FOCUSED_COLOR: [f32; 4] = [1.0, 0.8, 0.2, 1.0];
+const NORMAL_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+// whether a menu has the gamepad, the systems sharing its buttons leave them alone while it does
+#[derive(Default)]
+pub struct MenuState {
+    pub open: bool,
+    // the controls menu is waiting for a button to rebind, so presses aren't navigation
+    pub capturing: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Navigate {
+    Up,
+    Down,
+    Left,
+    Right,
+    Activate,
+    Back,
+}
+
+// every controller button is taken by the flight bindings, so the menus borrow these while open
+const ACTIONS: [(&str, Navigate); 4] = [
+    ("warp_down", Navigate::Left),
+    ("warp_up", Navigate::Right),
+    ("focus_pick", Navigate::Activate),
+    ("focus_next", Navigate::Back),
+];
+// the two buttons of this emulated axis move the focus up and down
+const VERTICAL_AXIS: &str = "move_y";
+
+// what a button does in a menu, follows the current bindings so rebinding moves the menu controls too
+fn navigation(bindings: &Bindings<StringBindings>, button: &Button) -> Option<Navigate> {
+    // the mouse clicks the menus directly, and the left button is focus_pick as well
+    if let Button::Mouse(_) = button {
+        return None;
+    }
+    // escape always backs out, the game only quits on it with no menu open
+    if *button == Button::Key(VirtualKeyCode::Escape) {
+        return Some(Navigate::Back);
+    }
+    if let Some((pos, neg)) = bindings.axis(VERTICAL_AXIS).and_then(bindings::emulated) {
+        if pos == button {
+            return Some(Navigate::Up);
+        }
+        if neg == button {
+            return Some(Navigate::Down);
+        }
+    }
+    ACTIONS.iter()
+        .find(|(action, _)| bindings.action_bindings(*action).any(|combo| combo == std::slice::from_ref(button)))
+        .map(|(_, navigate)| *navigate)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Menu {
+    Settings,
+    Controls,
+}
+
+// something in a menu the focus can rest on
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Item {
+    // a settings row, its value label is highlighted and left and right step it
+    Setting(&'static str),
+    // a row of the controls list
+    Row(usize),
+    Button(&'static str),
+}
+
+impl Menu {
+    fn container(self) -> &'static str {
+        match self {
+            Menu::Settings => "settings_container",
+            Menu::Controls => "controls_container",
+        }
+    }
+
+    fn items(self) -> Vec<Item> {
+        match self {
+            Menu::Settings => settings::rows().map(Item::Setting)
+                .chain(vec![Item::Button("settings_controls"), Item::Button("settings_close")])
+                .collect(),
+            Menu::Controls => (0..bindings::ROWS).map(Item::Row)
+                .chain(vec![Item::Button("controls_reset"), Item::Button("controls_back")])
+                .collect(),
+        }
+    }
+
+    // the ui element a press clicks, if any
+    fn target(self, item: &Item, navigate: Navigate) -> Option<String> {
+        match (self, item, navigate) {
+            (Menu::Settings, _, Navigate::Back) => Some("settings_close".to_string()),
+            (Menu::Controls, _, Navigate::Back) => Some("controls_back".to_string()),
+            (Menu::Settings, Item::Setting(prefix), Navigate::Left) => Some(format!("{}_prev", prefix)),
+            (Menu::Settings, Item::Setting(prefix), Navigate::Right) => Some(format!("{}_next", prefix)),
+            (Menu::Settings, Item::Setting(prefix), Navigate::Activate) => Some(format!("{}_next", prefix)),
+            // left and right turn the pages of the controls list wherever the focus is
+            (Menu::Controls, _, Navigate::Left) => Some("controls_previous_page".to_string()),
+            (Menu::Controls, _, Navigate::Right) => Some("controls_next_page".to_string()),
+            (_, Item::Row(index), Navigate::Activate) => Some(format!("controls_row_{}", index)),
+            (_, Item::Button(id), Navigate::Activate) => Some(id.to_string()),
+            _ => None,
+        }
+    }
+}
+
+impl Item {
+    // the ui element that shows the focus
+    fn highlight(&self) -> String {
+        match self {
+            Item::Setting(prefix) => format!("{}_value", prefix),
+            Item::Row(index) => format!("controls_row_{}", index),
+            Item::Button(id) => id.to_string(),
+        }
+    }
+}
+
+// next focus index after an up or down press, wrapping around
+fn step(focused: usize, count: usize, navigate: Navigate) -> usize {
+    match navigate {
+        Navigate::Up => (focused + count - 1) % count,
+        Navigate::Down => (focused + 1) % count,
+        _ => focused,
+    }
+}
+
+// moves a focus through the open menu with the gamepad and clicks the focused element for it
+#[derive(SystemDesc)]
+#[system_desc(name(MenuNavigationSystemDesc))]
+pub struct MenuNavigationSystem {
+    #[system_desc(event_channel_reader)]
+    input_reader: ReaderId<InputEvent<StringBindings>>,
+    #[system_desc(skip)]
+    menu: Option<Menu>,
+    #[system_desc(skip)]
+    focused: usize,
+    // the highlighted element, labels are recoloured and buttons get the hover image
+    #[system_desc(skip)]
+    highlighted: Option<Entity>,
+}
+
+impl MenuNavigationSystem {
+    pub fn new(input_reader: ReaderId<InputEvent<StringBindings>>) -> Self {
+        Self { input_reader, menu:None, focused:0, highlighted:None }
+    }
+}
+
+impl<'s> System<'s> for MenuNavigationSystem {
+    type SystemData = (
+        Read<'s, EventChannel<InputEvent<StringBindings>>>,
+        Write<'s, EventChannel<UiEvent>>,
+        Read<'s, InputHandler<StringBindings>>,
+        Write<'s, MenuState>,
+        UiFinder<'s>,
+        ReadStorage<'s, HiddenPropagate>,
+        WriteStorage<'s, UiText>,
+    );
+
+    fn run(&mut self, (input_events, mut ui_events, input, mut state, ui_finder, hidden, mut ui_texts): Self::SystemData) {
+        let presses = input_events.read(&mut self.input_reader)
+            .filter_map(|event| match event {
+                InputEvent::ButtonPressed(button) => navigation(&input.bindings, button),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        let open = [Menu::Settings, Menu::Controls].iter().copied()
+            .find(|menu| ui_finder.find(menu.container()).map_or(false, |entity| !hidden.contains(entity)));
+        state.open = open.is_some();
+        // every menu starts out focused on its first item
+        if open != self.menu {
+            self.menu = open;
+            self.focused = 0;
+        }
+
+        let highlight = match open {
+            Some(menu) => {
+                let items = menu.items();
+                if !state.capturing {
+                    for navigate in presses {
+                        self.focused = step(self.focused, items.len(), navigate);
+                        let target = menu.target(&items[self.focused], navigate).and_then(|id| ui_finder.find(&id));
+                        if let Some(target) = target {
+                            ui_events.single_write(UiEvent::new(UiEventType::Click, target));
+                        }
+                    }
+                }
+                ui_finder.find(&items[self.focused].highlight())
+            },
+            None => None,
+        };
+
+        if highlight == self.highlighted {
+            return;
+        }
+        if let Some(previous) = self.highlighted {
+            match ui_texts.get_mut(previous) {
+                Some(text) => text.color = NORMAL_COLOR,
+                None => ui_events.single_write(UiEvent::new(UiEventType::HoverStop, previous)),
+            }
+        }
+        if let Some(current) = highlight {
+            match ui_texts.get_mut(current) {
+                Some(text) => text.color = FOCUSED_COLOR,
+                None => ui_events.single_write(UiEvent::new(UiEventType::HoverStart, current)),
+            }
+        }
+        self.highlighted = highlight;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst::{input::{Axis, ControllerButton}, winit::{MouseButton, VirtualKeyCode}};
+
+    fn bindings() -> Bindings<StringBindings> {
+        let mut bindings = Bindings::<StringBindings>::default();
+        bindings.insert_axis("move_y", Axis::Emulated {
+            pos: Button::Controller(0, ControllerButton::DPadUp),
+            neg: Button::Controller(0, ControllerButton::DPadDown),
+        }).unwrap();
+        bindings.insert_action_binding("warp_down".to_string(), vec![Button::Controller(0, ControllerButton::DPadLeft)]).unwrap();
+        bindings.insert_action_binding("warp_up".to_string(), vec![Button::Controller(0, ControllerButton::DPadRight)]).unwrap();
+        bindings.insert_action_binding("focus_pick".to_string(), vec![Button::Mouse(MouseButton::Left)]).unwrap();
+        bindings.insert_action_binding("focus_pick".to_string(), vec![Button::Controller(0, ControllerButton::A)]).unwrap();
+        bindings.insert_action_binding("focus_next".to_string(), vec![Button::Controller(0, ControllerButton::B)]).unwrap();
+        bindings
+    }
+
+    #[test]
+    fn gamepad_buttons_navigate() {
+        let bindings = bindings();
+        let pad = |button| navigation(&bindings, &Button::Controller(0, button));
+        assert_eq!(pad(ControllerButton::DPadUp), Some(Navigate::Up));
+        assert_eq!(pad(ControllerButton::DPadDown), Some(Navigate::Down));
+        assert_eq!(pad(ControllerButton::DPadLeft), Some(Navigate::Left));
+        assert_eq!(pad(ControllerButton::DPadRight), Some(Navigate::Right));
+        assert_eq!(pad(ControllerButton::A), Some(Navigate::Activate));
+        assert_eq!(pad(ControllerButton::B), Some(Navigate::Back));
+        assert_eq!(pad(ControllerButton::Start), None);
+    }
+
+    #[test]
+    fn mouse_and_unbound_keys_dont_navigate() {
+        let bindings = bindings();
+        assert_eq!(navigation(&bindings, &Button::Mouse(MouseButton::Left)), None);
+        assert_eq!(navigation(&bindings, &Button::Key(VirtualKeyCode::Return)), None);
+    }
+
+    #[test]
+    fn escape_backs_out() {
+        assert_eq!(navigation(&bindings(), &Button::Key(VirtualKeyCode::Escape)), Some(Navigate::Back));
+        assert_eq!(navigation(&Bindings::<StringBindings>::default(), &Button::Key(VirtualKeyCode::Escape)), Some(Navigate::Back));
+    }
+
+    #[test]
+    fn focus_wraps() {
+        assert_eq!(step(0, 9, Navigate::Up), 8);
+        assert_eq!(step(8, 9, Navigate::Down), 0);
+        assert_eq!(step(3, 9, Navigate::Down), 4);
+        assert_eq!(step(3, 9, Navigate::Activate), 3);
+    }
+
+    #[test]
+    fn settings_rows_step_their_value() {
+        let items = Menu::Settings.items();
+        assert_eq!(items[0], Item::Setting("settings_anti_aliasing"));
+        assert_eq!(Menu::Settings.target(&items[0], Navigate::Left).as_deref(), Some("settings_anti_aliasing_prev"));
+        assert_eq!(Menu::Settings.target(&items[0], Navigate::Right).as_deref(), Some("settings_anti_aliasing_next"));
+        assert_eq!(Menu::Settings.target(&items[0], Navigate::Up), None);
+        assert_eq!(items[0].highlight(), "settings_anti_aliasing_value");
+        let controls = Item::Button("settings_controls");
+        assert_eq!(Menu::Settings.target(&controls, Navigate::Activate).as_deref(), Some("settings_controls"));
+        assert_eq!(Menu::Settings.target(&controls, Navigate::Left), None);
+    }
+
+    #[test]
+    fn back_leaves_either_menu() {
+        for menu in [Menu::Settings, Menu::Controls].iter() {
+            let expected = if *menu == Menu::Settings { "settings_close" } else { "controls_back" };
+            for item in menu.items() {
+                assert_eq!(menu.target(&item, Navigate::Back).as_deref(), Some(expected));
+            }
+        }
+    }
+
+    #[test]
+    fn controls_rows_rebind_and_turn_pages() {
+        let items = Menu::Controls.items();
+        assert_eq!(items.len(), bindings::ROWS + 2);
+        assert_eq!(Menu::Controls.target(&items[2], Navigate::Activate).as_deref(), Some("controls_row_2"));
+        assert_eq!(Menu::Controls.target(&items[2], Navigate::Left).as_deref(), Some("controls_previous_page"));
+        assert_eq!(Menu::Controls.target(&items[2], Navigate::Right).as_deref(), Some("controls_next_page"));
+        assert_eq!(Menu::Controls.target(items.last().unwrap(), Navigate::Activate).as_deref(), Some("controls_back"));
+    }
+}
@@ -0,0 +1,133 @@
+use amethyst::{
+    assets::PrefabData,
+    derive::{PrefabData, SystemDesc},
+    ecs::prelude::{ Join, Component, DenseVecStorage, Entity, System, SystemData, WriteStorage, ReadStorage, Write },
+    core::{math::{Vector3}, transform::Transform},
+    renderer::camera::Camera,
+    Error,
+};
+use serde::{Deserialize, Serialize};
+
+// position in f64 world space, the rendered transform is derived from this relative to the origin
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+#[serde(default)]
+pub struct WorldPosition {
+    pub position: Vector3<f64>,
+}
+
+impl Component for WorldPosition {
+    type Storage = DenseVecStorage<Self>;
+}
+
+// resource with the world position that's currently at the render origin
+pub struct FloatingOrigin {
+    pub origin: Vector3<f64>,
+    // distance the camera can move from the origin before everything is rebased around it
+    pub rebase_distance: f32,
+}
+
+impl Default for FloatingOrigin {
+    fn default() -> Self {
+        Self { origin:Vector3::zeros(), rebase_distance:1000.0 }
+    }
+}
+
+impl FloatingOrigin {
+    // translation of a world position relative to the origin, small enough to keep f32 precision
+    pub fn to_local(&self, position: &Vector3<f64>) -> Vector3<f32> {
+        (position - self.origin).map(|v| v as f32)
+    }
+
+    pub fn to_world(&self, translation: &Vector3<f32>) -> Vector3<f64> {
+        self.origin + translation.map(|v| v as f64)
+    }
+
+    // moves the origin to a translation relative to it, like where the camera is
+    pub fn rebase(&mut self, translation: &Vector3<f32>) {
+        self.origin = self.to_world(translation);
+    }
+}
+
+// translation of a transform in the rendered scene around the origin, after the transform system ran
+pub fn scene_position(transform: &Transform) -> Vector3<f32> {
+    transform.global_matrix().column(3).xyz()
+}
+
+// keeps the camera near the origin and positions everything with a world position around it
+#[derive(SystemDesc)]
+#[system_desc(name(FloatingOriginSystemDesc))]
+pub struct FloatingOriginSystem;
+
+impl<'s> System<'s> for FloatingOriginSystem {
+    type SystemData = (
+        Write<'s, FloatingOrigin>,
+        ReadStorage<'s, WorldPosition>,
+        ReadStorage<'s, Camera>,
+        WriteStorage<'s, Transform>,
+    );
+
+    fn run(&mut self, (mut origin, positions, cameras, mut transforms) : Self::SystemData) {
+        // rebase when the camera wandered too far from the origin
+        let camera_offset = (&cameras, &transforms).join()
+            .map(|(_, transform)| *transform.translation())
+            .next();
+        if let Some(offset) = camera_offset {
+            if offset.norm() > origin.rebase_distance {
+                origin.rebase(&offset);
+                // cameras don't have a world position, so they're moved along with the origin
+                for (_, transform) in (&cameras, &mut transforms).join() {
+                    let translation = transform.translation() - offset;
+                    transform.set_translation(translation);
+                }
+                log::debug!("Rebased floating origin to {:?}", origin.origin);
+            }
+        }
+
+        for (position, transform) in (&positions, &mut transforms).join() {
+            transform.set_translation(origin.to_local(&position.position));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flight::{METERS_PER_AU, METERS_PER_UNIT};
+
+    const MILLIMETER: f64 = 0.001 / METERS_PER_UNIT;
+
+    // somewhere on a 1 au orbit around the sun at the scene origin
+    fn one_au() -> Vector3<f64> {
+        Vector3::new(0.6, 0.01, -0.8) * (METERS_PER_AU / METERS_PER_UNIT)
+    }
+
+    #[test]
+    fn rebase_moves_the_origin_to_the_translation() {
+        let mut origin = FloatingOrigin { origin:one_au(), ..Default::default() };
+        let camera = Vector3::new(1200.5f32, -35.25, 80.125);
+        let world = origin.to_world(&camera);
+        origin.rebase(&camera);
+        assert_eq!(origin.origin, world);
+        assert_eq!(origin.to_local(&world), Vector3::zeros());
+    }
+
+    #[test]
+    fn sub_millimeter_round_trip_near_the_camera_at_1_au() {
+        let mut origin = FloatingOrigin { origin:one_au(), ..Default::default() };
+        origin.rebase(&Vector3::new(1200.5, -35.25, 80.125));
+        // a craft a hundred meters and one a few kilometers from the camera
+        for offset in [Vector3::new(60.0, -80.0, 0.0), Vector3::new(-2500.0, 1200.0, 3100.0)].iter() {
+            let craft = origin.origin + offset / METERS_PER_UNIT;
+            let error = (origin.to_world(&origin.to_local(&craft)) - craft).norm();
+            assert!(error < MILLIMETER, "{} m round trip error", error * METERS_PER_UNIT);
+        }
+    }
+
+    #[test]
+    fn f32_alone_is_kilometers_off_at_1_au() {
+        let craft = one_au() + Vector3::new(60.0, -80.0, 0.0) / METERS_PER_UNIT;
+        let error = (craft.map(|v| v as f32).map(|v| v as f64) - craft).norm();
+        assert!(error * METERS_PER_UNIT > 1000.0);
+    }
+}
@@ -0,0 +1,312 @@
+use amethyst::{
+    controls::HideCursor,
+    core::{
+        math::{Matrix4, Vector3, Vector4},
+        shrev::{EventChannel, ReaderId},
+        transform::Transform,
+    },
+    derive::SystemDesc,
+    ecs::prelude::{ Join, Entity, Entities, Read, ReadExpect, ReadStorage, System, SystemData, Write },
+    input::{InputEvent, InputHandler, StringBindings},
+    renderer::{camera::Camera, ActiveCamera},
+    utils::tag::Tag,
+    window::ScreenDimensions,
+};
+use crate::flight::Ship;
+use crate::origin::{scene_position, FloatingOrigin};
+use crate::planet::Planet;
+use crate::render::camera::active_camera;
+use crate::render::sun::Sun;
+
+// bodies smaller than this on screen can still be picked, in pixels
+const PICK_RADIUS: f32 = 12.0;
+
+// a ray in render space, the direction is normalised
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: Vector3<f32>,
+    pub direction: Vector3<f32>,
+}
+
+// ray through a pixel, from the camera's projection and global matrix, the cursor is in pixels from the top left
+pub fn unproject(projection: &Matrix4<f32>, camera: &Matrix4<f32>, screen: (f32, f32), cursor: (f32, f32)) -> Option<Ray> {
+    // the projection flips y, so ndc points down like the cursor. a depth halfway between the planes is in
+    // front of the camera whichever way the depth is mapped
+    let ndc = Vector4::new(cursor.0 / screen.0 * 2.0 - 1.0, cursor.1 / screen.1 * 2.0 - 1.0, 0.5, 1.0);
+    let view = projection.try_inverse()? * ndc;
+    if view.w == 0.0 {
+        return None;
+    }
+    let origin = camera.column(3).xyz();
+    let point = (camera * Vector4::new(view.x / view.w, view.y / view.w, view.z / view.w, 1.0)).xyz();
+    let direction = (point - origin).try_normalize(std::f32::EPSILON)?;
+    Some(Ray { origin, direction })
+}
+
+// distance along the ray to where it enters the sphere, or leaves it when it starts inside
+pub fn ray_sphere(ray: &Ray, centre: &Vector3<f32>, radius: f32) -> Option<f32> {
+    let offset = ray.origin - centre;
+    let b = offset.dot(&ray.direction);
+    let c = offset.norm_squared() - radius * radius;
+    let discriminant = b * b - c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    if -b - root >= 0.0 {
+        Some(-b - root)
+    } else if -b + root >= 0.0 {
+        Some(-b + root)
+    } else {
+        None
+    }
+}
+
+// latitude and longitude in degrees of a point in the body's own frame, the poles are on its y axis
+pub fn lat_lon(point: &Vector3<f64>) -> (f64, f64) {
+    let distance = point.norm();
+    if distance == 0.0 {
+        return (0.0, 0.0);
+    }
+    let latitude = (point.y / distance).max(-1.0).min(1.0).asin().to_degrees();
+    let longitude = (-point.z).atan2(point.x).to_degrees();
+    (latitude, longitude)
+}
+
+// something under the cursor
+#[derive(Clone, Debug)]
+pub struct Hit {
+    pub entity: Entity,
+    // in world space
+    pub point: Vector3<f64>,
+    // degrees, only on a planet or moon surface
+    pub lat_lon: Option<(f64, f64)>,
+}
+
+// resource with what's under the cursor, or the middle of the screen while the cursor is captured
+#[derive(Default)]
+pub struct Picking {
+    pub ray: Option<Ray>,
+    pub hover: Option<Hit>,
+}
+
+// casts a ray from the cursor through the active camera into the bodies every frame
+#[derive(SystemDesc)]
+#[system_desc(name(PickingSystemDesc))]
+pub struct PickingSystem {
+    #[system_desc(event_channel_reader)]
+    event_reader: ReaderId<InputEvent<StringBindings>>,
+}
+
+impl PickingSystem {
+    pub fn new(event_reader: ReaderId<InputEvent<StringBindings>>) -> Self {
+        Self { event_reader }
+    }
+}
+
+impl<'s> System<'s> for PickingSystem {
+    type SystemData = (
+        Read<'s, EventChannel<InputEvent<StringBindings>>>,
+        Read<'s, InputHandler<StringBindings>>,
+        Write<'s, Picking>,
+        Write<'s, HideCursor>,
+        Read<'s, ActiveCamera>,
+        Read<'s, FloatingOrigin>,
+        ReadExpect<'s, ScreenDimensions>,
+        Entities<'s>,
+        ReadStorage<'s, Camera>,
+        ReadStorage<'s, Planet>,
+        ReadStorage<'s, Tag<Sun>>,
+        ReadStorage<'s, Tag<Ship>>,
+        ReadStorage<'s, Transform>,
+    );
+
+    fn run(&mut self, (events, input, mut picking, mut hide_cursor, active, origin, screen, entities, cameras, planets, suns, ships, transforms):Self::SystemData) {
+        // the cursor action frees the mouse to point at things, which stops mouse look
+        let toggles = events
+            .read(&mut self.event_reader)
+            .filter(|event| matches!(event, InputEvent::ActionPressed(action) if action == "cursor"))
+            .count();
+        if toggles % 2 == 1 {
+            hide_cursor.hide = !hide_cursor.hide;
+        }
+
+        picking.ray = None;
+        picking.hover = None;
+        let camera = active_camera(&active, &entities, &cameras);
+        let (projection, camera_matrix) = match camera.and_then(|camera| Some((cameras.get(camera)?, transforms.get(camera)?))) {
+            Some((camera, transform)) => (camera.projection().as_matrix().clone_owned(), *transform.global_matrix()),
+            None => return,
+        };
+        let screen_size = (screen.width(), screen.height());
+        let cursor = match input.mouse_position() {
+            Some(position) if !hide_cursor.hide => position,
+            _ => (screen_size.0 / 2.0, screen_size.1 / 2.0),
+        };
+        let ray = match unproject(&projection, &camera_matrix, screen_size, cursor) {
+            Some(ray) => ray,
+            None => return,
+        };
+        // angle a pixel covers at the centre of the screen
+        let pixel = 2.0 / (projection[(1, 1)].abs() * screen_size.1);
+
+        let mut nearest: Option<(f32, Hit)> = None;
+        for (entity, transform) in (&entities, &transforms).join() {
+            if !(planets.contains(entity) || suns.contains(entity) || ships.contains(entity)) {
+                continue;
+            }
+            let centre = scene_position(transform);
+            let radius = transform.scale().x;
+            let hit = match ray_sphere(&ray, &centre, radius) {
+                Some(distance) => {
+                    let point = ray.origin + ray.direction * distance;
+                    // into the body's frame, its scale is the radius so this is on the unit sphere
+                    let local = transform.global_matrix().try_inverse()
+                        .map(|inverse| (inverse * Vector4::new(point.x, point.y, point.z, 1.0)).xyz().map(|v| v as f64));
+                    Some((distance, Hit {
+                        entity,
+                        point: origin.to_world(&point),
+                        lat_lon: local.filter(|_| planets.contains(entity)).map(|local| lat_lon(&local)),
+                    }))
+                },
+                None => {
+                    // small and far away bodies are picked by a sphere that's a few pixels wide on screen
+                    let reach = (centre - ray.origin).norm() * pixel * PICK_RADIUS;
+                    ray_sphere(&ray, &centre, reach.max(radius)).map(|distance| {
+                        (distance, Hit { entity, point: origin.to_world(&centre), lat_lon: None })
+                    })
+                },
+            };
+            if let Some((distance, hit)) = hit {
+                if nearest.as_ref().map_or(true, |(nearest, _)| distance < *nearest) {
+                    nearest = Some((distance, hit));
+                }
+            }
+        }
+        picking.ray = Some(ray);
+        picking.hover = nearest.map(|(_, hit)| hit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst::core::math::{Isometry3, Translation3, UnitQuaternion};
+    use crate::render::depth::projection_terms;
+
+    // the authored near plane, and one the ClipPlaneSystem pulled out towards a surface
+    const NEAR_PLANES: [f32; 2] = [0.1, 50.0];
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+    }
+
+    fn assert_close_vector(a: &Vector3<f32>, b: &Vector3<f32>) {
+        assert!((a - b).norm() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    // the perspective amethyst builds, y flipped, with the depth terms the ClipPlaneSystem writes
+    fn perspective(aspect: f32, fovy: f32, znear: f32) -> Matrix4<f32> {
+        let focal = 1.0 / (fovy / 2.0).tan();
+        let (m22, m23) = projection_terms(znear, 100000.0);
+        let mut matrix = Matrix4::zeros();
+        matrix[(0, 0)] = focal / aspect;
+        matrix[(1, 1)] = -focal;
+        matrix[(2, 2)] = m22;
+        matrix[(2, 3)] = m23;
+        matrix[(3, 2)] = -1.0;
+        matrix
+    }
+
+    // the prefab camera, and a wide window with the vertical fov AutoFov keeps its horizontal fov with
+    fn projections(znear: f32) -> Vec<(Matrix4<f32>, (f32, f32), f32)> {
+        let aspect = 1920.0 / 1080.0;
+        let fovy = 2.0 * ((1.361356817f32 / 2.0).tan() * 1.3 / aspect).atan();
+        vec![
+            (perspective(1.3, 1.0471975512, znear), (1300.0, 1000.0), 1.0471975512),
+            (perspective(aspect, fovy, znear), (1920.0, 1080.0), fovy),
+        ]
+    }
+
+    fn camera() -> Matrix4<f32> {
+        Isometry3::from_parts(Translation3::new(1.0, -2.0, 4.0), UnitQuaternion::from_euler_angles(0.3, 2.0, 0.1)).to_homogeneous()
+    }
+
+    fn forward(camera: &Matrix4<f32>) -> Vector3<f32> {
+        (camera * Vector4::new(0.0, 0.0, -1.0, 0.0)).xyz()
+    }
+
+    #[test]
+    fn centre_pixel_looks_forward() {
+        let camera = camera();
+        for znear in NEAR_PLANES.iter() {
+            for (projection, screen, _) in projections(*znear) {
+                let ray = unproject(&projection, &camera, screen, (screen.0 / 2.0, screen.1 / 2.0)).unwrap();
+                assert_close_vector(&ray.origin, &Vector3::new(1.0, -2.0, 4.0));
+                assert_close_vector(&ray.direction, &forward(&camera));
+            }
+        }
+    }
+
+    #[test]
+    fn top_edge_is_half_the_fov_up() {
+        let camera = camera();
+        let up = (camera * Vector4::new(0.0, 1.0, 0.0, 0.0)).xyz();
+        for znear in NEAR_PLANES.iter() {
+            for (projection, screen, fovy) in projections(*znear) {
+                let ray = unproject(&projection, &camera, screen, (screen.0 / 2.0, 0.0)).unwrap();
+                assert_close(ray.direction.dot(&forward(&camera)).acos(), fovy / 2.0);
+                assert!(ray.direction.dot(&up) > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn ray_hits_the_near_side() {
+        let ray = Ray { origin: Vector3::new(0.0, 0.0, 10.0), direction: -Vector3::z() };
+        assert_eq!(ray_sphere(&ray, &Vector3::zeros(), 2.0), Some(8.0));
+    }
+
+    #[test]
+    fn ray_misses() {
+        let ray = Ray { origin: Vector3::new(0.0, 3.0, 10.0), direction: -Vector3::z() };
+        assert_eq!(ray_sphere(&ray, &Vector3::zeros(), 2.0), None);
+        // pointing away from it
+        let ray = Ray { origin: Vector3::new(0.0, 0.0, 10.0), direction: Vector3::z() };
+        assert_eq!(ray_sphere(&ray, &Vector3::zeros(), 2.0), None);
+    }
+
+    #[test]
+    fn ray_grazes_the_tangent() {
+        let ray = Ray { origin: Vector3::new(0.0, 2.0, 10.0), direction: -Vector3::z() };
+        assert_eq!(ray_sphere(&ray, &Vector3::zeros(), 2.0), Some(10.0));
+    }
+
+    #[test]
+    fn ray_from_inside_leaves() {
+        let ray = Ray { origin: Vector3::new(0.0, 1.0, 0.0), direction: Vector3::y() };
+        assert_eq!(ray_sphere(&ray, &Vector3::zeros(), 2.0), Some(1.0));
+    }
+
+    #[test]
+    fn poles_are_on_the_y_axis() {
+        assert_eq!(lat_lon(&Vector3::new(0.0, 3.0, 0.0)).0, 90.0);
+        assert_eq!(lat_lon(&Vector3::new(0.0, -3.0, 0.0)).0, -90.0);
+    }
+
+    #[test]
+    fn prime_meridian_is_on_the_x_axis() {
+        assert_eq!(lat_lon(&Vector3::new(3.0, 0.0, 0.0)), (0.0, 0.0));
+        // east is towards -z, and the antimeridian is behind
+        assert_eq!(lat_lon(&Vector3::new(0.0, 0.0, -3.0)), (0.0, 90.0));
+        let (latitude, longitude) = lat_lon(&Vector3::new(-3.0, 0.0, 0.0));
+        assert!(latitude == 0.0 && longitude.abs() == 180.0);
+        let (latitude, longitude) = lat_lon(&Vector3::new(1.0, 1.0, 0.0));
+        assert!((latitude - 45.0).abs() < 1e-9 && longitude == 0.0);
+    }
+
+    #[test]
+    fn centre_has_no_direction() {
+        assert_eq!(lat_lon(&Vector3::zeros()), (0.0, 0.0));
+    }
+}
@@ -1,23 +1,52 @@
 use amethyst::{
-    ecs::{NullStorage},
-    ecs::prelude::{ Join, Component, System, SystemData, WriteStorage, ReadStorage, Read },
-    derive::{SystemDesc},
+    assets::PrefabData,
+    ecs::prelude::{ Join, Component, DenseVecStorage, Entity, System, SystemData, WriteStorage, ReadStorage, Read },
+    derive::{PrefabData, SystemDesc},
     core::{timing::Time, transform::Transform},
-    utils::{
-        tag::{Tag},
-    },
+    Error,
 };
+use serde::{Deserialize, Serialize};
+
+// a planet, moon or the sun, its radius is the scale of its unit sphere mesh
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+#[serde(default)]
+pub struct Planet {
+    pub name: String,
+    // surface gravity in m/s²
+    pub gravity: f32,
+    // radians per second around the planet axis
+    pub rotation_speed: f32,
+}
+
+impl Planet {
+    // gravitational parameter from the surface gravity and the radius in meters
+    pub fn mu(&self, radius: f64) -> f64 {
+        self.gravity as f64 * radius * radius
+    }
+}
 
-#[derive(Clone, Default)]
-pub struct Planet;
 impl Component for Planet {
-    type Storage = NullStorage<Self>;
+    type Storage = DenseVecStorage<Self>;
+}
+
+// cloud layer, a child of the planet it covers
+#[derive(Clone, Debug, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+#[serde(default)]
+pub struct Clouds {
+    // radians per second around the planet axis
+    pub rotation_speed: f32,
+}
+
+impl Default for Clouds {
+    fn default() -> Self {
+        Self { rotation_speed:0.015 }
+    }
 }
 
-#[derive(Clone, Default)]
-pub struct Clouds;
 impl Component for Clouds {
-    type Storage = NullStorage<Self>;
+    type Storage = DenseVecStorage<Self>;
 }
 
 #[derive(SystemDesc)]
@@ -26,15 +55,18 @@ pub struct PlanetSystem;
 
 impl<'s> System<'s> for PlanetSystem {
     type SystemData = (
-        ReadStorage<'s, Tag<Planet>>,
-        ReadStorage<'s, Tag<Clouds>>,
+        ReadStorage<'s, Planet>,
+        ReadStorage<'s, Clouds>,
         WriteStorage<'s, Transform>,
         Read<'s, Time>,
     );
 
-    fn run(&mut self, (_planets, clouds, mut transforms, time) : Self::SystemData) {
-        for (_, transform) in (&clouds, &mut transforms).join() {
-            transform.append_rotation_y_axis(0.015 * time.delta_seconds());
+    fn run(&mut self, (planets, clouds, mut transforms, time) : Self::SystemData) {
+        for (planet, transform) in (&planets, &mut transforms).join() {
+            transform.append_rotation_y_axis(planet.rotation_speed * time.delta_seconds());
+        }
+        for (cloud, transform) in (&clouds, &mut transforms).join() {
+            transform.append_rotation_y_axis(cloud.rotation_speed * time.delta_seconds());
         }
     }
-}
\ No newline at end of file
+}
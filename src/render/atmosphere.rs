@@ -5,26 +5,32 @@ use amethyst::renderer::{
     Backend, Factory, Mesh,
     submodules::{DynamicVertexBuffer, EnvironmentSub },
     ChangeDetection,
-    pod::VertexArgs,
     pipeline::{PipelineDescBuilder, PipelinesBuilder},
     util,
     batch::{GroupIterator, OrderedOneLevelBatch},
 };
 use amethyst::core::{
+    math::Vector3,
     transform::Transform,
     transform::components::Parent,
 };
 use amethyst::{
-    ecs::{NullStorage, World},
-    ecs::prelude::{ Join, Component, SystemData, ReadStorage, Read },
-    assets::{AssetStorage, Handle},
+    ecs::{World, WorldExt},
+    ecs::prelude::{ Join, Component, DenseVecStorage, Entities, Entity, SystemData, ReadStorage, WriteStorage, Read },
+    assets::{AssetStorage, Handle, PrefabData},
+    derive::PrefabData,
     error::Error,
-    utils::tag::{Tag},
+    renderer::{camera::Camera, ActiveCamera},
 };
 use derivative::Derivative;
+use glsl_layout::*;
+use serde::{Deserialize, Serialize};
+use crate::planet::Planet;
+use crate::render::camera::active_camera;
+use crate::render::depth;
 use rendy::{
     command::{QueueId, RenderPassEncoder},
-    hal::{self, device::Device, pso, pso::ShaderStageFlags},
+    hal::{self, device::Device, format::Format, pso, pso::ShaderStageFlags},
     graph::{
         render::{PrepareResult, RenderGroup, RenderGroupDesc},
         GraphContext, NodeBuffer, NodeImage,
@@ -32,14 +38,64 @@ use rendy::{
     mesh::{
         VertexFormat, TexCoord, Tangent, Position, Normal, AsVertex
     },
-    shader::{Shader, SpirvShader},
 };
 
-// component to tag the atmosphere object
-#[derive(Clone, Default)]
-pub struct Atmosphere;
+// atmosphere shell around a planet, a child of the planet it surrounds
+#[derive(Clone, Debug, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+#[serde(default)]
+pub struct Atmosphere {
+    pub color: [f32; 3],
+    // optical depth when looking straight down through the shell
+    pub density: f32,
+}
+
+impl Default for Atmosphere {
+    fn default() -> Self {
+        Self { color:[0.123, 0.34, 0.8], density:0.3 }
+    }
+}
+
 impl Component for Atmosphere {
-    type Storage = NullStorage<Self>;
+    type Storage = DenseVecStorage<Self>;
+}
+
+// per instance arguments, the shell and ground spheres are derived from the model matrix and
+// the planet radius in the shader, shells the camera is inside of are drawn at the far plane
+/// layout(location = 4) in mat4 model;
+/// layout(location = 8) in vec4 atmosphere; // color and density
+/// layout(location = 9) in vec4 ground; // planet radius, 1 when drawn at the far plane
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, AsStd140)]
+#[repr(C, align(16))]
+pub struct AtmosphereInstanceArgs {
+    pub model: mat4,
+    pub atmosphere: vec4,
+    pub ground: vec4,
+}
+
+impl AsVertex for AtmosphereInstanceArgs {
+    fn vertex() -> VertexFormat {
+        VertexFormat::new((
+            (Format::Rgba32Sfloat, "model"),
+            (Format::Rgba32Sfloat, "model"),
+            (Format::Rgba32Sfloat, "model"),
+            (Format::Rgba32Sfloat, "model"),
+            (Format::Rgba32Sfloat, "atmosphere"),
+            (Format::Rgba32Sfloat, "ground"),
+        ))
+    }
+}
+
+impl AtmosphereInstanceArgs {
+    pub fn new(transform: &Transform, atmosphere: &Atmosphere, planet_radius: f32, inside: bool) -> Self {
+        let model: [[f32; 4]; 4] = (*transform.global_matrix()).into();
+        let [r, g, b] = atmosphere.color;
+        Self {
+            model: model.into(),
+            atmosphere: [r, g, b, atmosphere.density].into(),
+            ground: [planet_radius, if inside { 1.0 } else { 0.0 }, 0.0, 0.0].into(),
+        }
+    }
 }
 
 // plugin
@@ -73,21 +129,6 @@ impl<B: Backend> RenderPlugin<B> for RenderAtmosphere {
     }
 }
 
-// load our shader
-lazy_static::lazy_static! {
-    static ref VERTEX:SpirvShader = SpirvShader::from_bytes(
-        include_bytes!("../../assets/shader/atmosphere.vert.spv"),
-        ShaderStageFlags::VERTEX,
-        "main",
-    ).unwrap();
-
-    static ref FRAGMENT:SpirvShader = SpirvShader::from_bytes(
-        include_bytes!("../../assets/shader/atmosphere.frag.spv"),
-        ShaderStageFlags::FRAGMENT,
-        "main",
-    ).unwrap();
-}
-
 // plugin desc
 #[derive(Clone, PartialEq, Derivative)]
 #[derivative(Debug(bound = ""), Default(bound = ""))]
@@ -106,7 +147,7 @@ fn build_custom_pipeline<B: Backend>(
     framebuffer_height: u32,
     vertex_format: &[VertexFormat],
     layouts: Vec<&B::DescriptorSetLayout>,
-) -> Result<(B::GraphicsPipeline, B::PipelineLayout), failure::Error> {
+) -> Result<(Vec<B::GraphicsPipeline>, B::PipelineLayout), failure::Error> {
     let pipeline_layout = unsafe {
         factory
             .device()
@@ -118,38 +159,59 @@ fn build_custom_pipeline<B: Backend>(
         .iter()
         .map(|f| (f.clone(), pso::VertexInputRate::Vertex))
         .chain(Some((
-            VertexArgs::vertex(),
+            AtmosphereInstanceArgs::vertex(),
             pso::VertexInputRate::Instance(1)
         )))
         .collect::<Vec<_>>();
 
     // get shaders
-    let shader_vertex = unsafe { VERTEX.module(factory).unwrap() };
-    let shader_fragment = unsafe { FRAGMENT.module(factory).unwrap() };
-
-    // build the pipeline
+    let shaders = crate::render::shader::module(factory, "atmosphere.vert").and_then(|vertex| {
+        match crate::render::shader::module(factory, "atmosphere.frag") {
+            Ok(fragment) => Ok((vertex, fragment)),
+            Err(e) => {
+                unsafe { factory.destroy_shader_module(vertex); }
+                Err(e)
+            },
+        }
+    });
+    let (shader_vertex, shader_fragment) = match shaders {
+        Ok(shaders) => shaders,
+        Err(e) => {
+            unsafe { factory.device().destroy_pipeline_layout(pipeline_layout); }
+            return Err(e);
+        },
+    };
+
+    // build the pipelines, one to see the shell from outside and one for when the camera is inside
+    // it, the latter draws the far side of the shell at the far plane so it only covers the sky
+    // the scene left empty
+    let pipeline_desc = |face, depth_test| {
+        PipelineDescBuilder::new()
+            .with_vertex_desc(&vertex_desc)
+            .with_shaders(util::simple_shader_set(
+                &shader_vertex,
+                Some(&shader_fragment),
+            ))
+            .with_layout(&pipeline_layout)
+            .with_subpass(subpass)
+            .with_framebuffer_size(framebuffer_width, framebuffer_height)
+            .with_face_culling(face)
+            .with_depth_test(depth_test)
+            // alpha blended
+            .with_blend_targets(vec![pso::ColorBlendDesc {
+                mask: pso::ColorMask::ALL,
+                blend: Some(pso::BlendState::ALPHA),
+            }])
+    };
     let pipes = PipelinesBuilder::new()
-        .with_pipeline(
-            PipelineDescBuilder::new()
-                .with_vertex_desc(&vertex_desc)
-                .with_shaders(util::simple_shader_set(
-                    &shader_vertex,
-                    Some(&shader_fragment),
-                ))
-                .with_layout(&pipeline_layout)
-                .with_subpass(subpass)
-                .with_framebuffer_size(framebuffer_width, framebuffer_height)
-                .with_face_culling(pso::Face::BACK)
-                .with_depth_test(pso::DepthTest {
-                    fun: pso::Comparison::Less,
-                    write: false, // as our shader will be transparent
-                })
-                // alpha blended
-                .with_blend_targets(vec![pso::ColorBlendDesc {
-                    mask: pso::ColorMask::ALL,
-                    blend: Some(pso::BlendState::ALPHA),
-                }])
-        )
+        .with_pipeline(pipeline_desc(pso::Face::BACK, pso::DepthTest {
+            fun: depth::COMPARISON,
+            write: false, // as our shader will be transparent
+        }))
+        .with_pipeline(pipeline_desc(pso::Face::FRONT, pso::DepthTest {
+            fun: depth::FAR_COMPARISON,
+            write: false,
+        }))
         .build(factory, None);
     
     // destroy the shaders when loaded
@@ -166,7 +228,7 @@ fn build_custom_pipeline<B: Backend>(
             }
             Err(e)
         }
-        Ok(mut pipes) => Ok((pipes.remove(0), pipeline_layout)),
+        Ok(pipes) => Ok((pipes, pipeline_layout)),
     }
 }
 
@@ -176,7 +238,7 @@ impl<B: Backend> RenderGroupDesc<B, World> for DrawAtmosphereDesc {
         _ctx: &GraphContext<B>,
         factory: &mut Factory<B>,
         _queue: QueueId,
-        _aux: &World,
+        aux: &World,
         framebuffer_width: u32,
         framebuffer_height: u32,
         subpass: hal::pass::Subpass<'_, B>,
@@ -198,7 +260,7 @@ impl<B: Backend> RenderGroupDesc<B, World> for DrawAtmosphereDesc {
             TexCoord::vertex(),
         ];
 
-        let (pipeline, pipeline_layout) = build_custom_pipeline(
+        let (mut pipelines, pipeline_layout) = build_custom_pipeline(
             factory,
             subpass,
             framebuffer_width,
@@ -210,13 +272,19 @@ impl<B: Backend> RenderGroupDesc<B, World> for DrawAtmosphereDesc {
         // not sure if/why this is needed but this is done in base_3d as well
         vertex_format.sort();
 
+        let inside_pipeline = pipelines.remove(1);
+        let pipeline = pipelines.remove(0);
+
         Ok(Box::new(DrawAtmosphere::<B> {
             pipeline: pipeline,
+            inside_pipeline: inside_pipeline,
             pipeline_layout: pipeline_layout,
             env: env,
             batches: Default::default(),
             vertex_format: vertex_format,
             models: DynamicVertexBuffer::new(),
+            inside_batches: Default::default(),
+            inside_models: DynamicVertexBuffer::new(),
             change: Default::default(),
         }))
     }
@@ -226,12 +294,16 @@ impl<B: Backend> RenderGroupDesc<B, World> for DrawAtmosphereDesc {
 #[derive(Debug)]
 pub struct DrawAtmosphere<B: Backend> {
     pipeline: B::GraphicsPipeline,
+    inside_pipeline: B::GraphicsPipeline,
     pipeline_layout: B::PipelineLayout,
     env: EnvironmentSub<B>,
-    batches: OrderedOneLevelBatch<u32, VertexArgs>,
+    batches: OrderedOneLevelBatch<u32, AtmosphereInstanceArgs>,
     vertex_format: Vec<VertexFormat>,
-    models: DynamicVertexBuffer<B, VertexArgs>,
-    change: ChangeDetection
+    models: DynamicVertexBuffer<B, AtmosphereInstanceArgs>,
+    // shells the camera is inside of, drawn with the inside pipeline
+    inside_batches: OrderedOneLevelBatch<u32, AtmosphereInstanceArgs>,
+    inside_models: DynamicVertexBuffer<B, AtmosphereInstanceArgs>,
+    change: ChangeDetection,
 }
 
 impl<B: Backend> RenderGroup<B, World> for DrawAtmosphere<B> {
@@ -247,15 +319,23 @@ impl<B: Backend> RenderGroup<B, World> for DrawAtmosphere<B> {
         let (
             mesh_storage,
             meshes,
-            atmosphere,
+            atmospheres,
+            planets,
+            cameras,
+            active,
+            entities,
             transforms,
-            parents
+            parents,
         ) = <(
             Read<'_, AssetStorage<Mesh>>,
             ReadStorage<'_, Handle<Mesh>>,
-            ReadStorage<'_, Tag<Atmosphere>>,
+            ReadStorage<'_, Atmosphere>,
+            ReadStorage<'_, Planet>,
+            ReadStorage<'_, Camera>,
+            Read<'_, ActiveCamera>,
+            Entities<'_>,
             ReadStorage<'_, Transform>,
-            ReadStorage<'_, Parent>
+            ReadStorage<'_, Parent>,
         )>::fetch(world);
 
         // prepare environemnt
@@ -263,31 +343,42 @@ impl<B: Backend> RenderGroup<B, World> for DrawAtmosphere<B> {
 
         // clear batches
         self.batches.swap_clear();
+        self.inside_batches.swap_clear();
 
         // refs
         let batches_ref = &mut self.batches;
+        let inside_batches_ref = &mut self.inside_batches;
         let mut changed = false;
 
+        let camera_position = active_camera(&active, &entities, &cameras)
+            .and_then(|camera| transforms.get(camera))
+            .map(|t| sphere(t).0);
+
         // setup the batches
         let meshes_joined = (&meshes, &transforms, &parents).join();
-        let mut tags_joined = (&atmosphere).join();
 
-        meshes_joined.filter_map(|joindata| {
-                // we need to check if the parent has our tag
-                if let Some(_) = tags_joined.get_unchecked(joindata.2.entity.id()) {
-                    return Some(joindata);
-                }
-                None
+        meshes_joined.filter_map(|(mesh, tform, parent)| {
+                // we need to check if the parent is an atmosphere, which in turn is a child of its planet
+                let atmosphere = atmospheres.get(parent.entity)?;
+                let shell_transform = transforms.get(parent.entity)?;
+                let planet_radius = parents.get(parent.entity)
+                    .filter(|planet| planets.contains(planet.entity))
+                    .and_then(|planet| transforms.get(planet.entity))
+                    .map_or(0.0, |planet_transform| sphere(planet_transform).1);
+                let (center, radius) = sphere(shell_transform);
+                let inside = camera_position.map_or(false, |camera| (camera - center).norm() < radius);
+                Some(((inside, mesh.id()), AtmosphereInstanceArgs::new(tform, atmosphere, planet_radius, inside)))
             })
-            .map(|(mesh, tform, _)| {
-                ((mesh.id()),VertexArgs::from_object_data(tform, None))
-            })
-            .for_each_group(|mesh_id, data| {
+            .for_each_group(|(inside, mesh_id), data| {
                 if mesh_storage.contains_id(mesh_id) {
-                    batches_ref.insert(mesh_id, data.drain(..));
+                    if inside {
+                        inside_batches_ref.insert(mesh_id, data.drain(..));
+                    } else {
+                        batches_ref.insert(mesh_id, data.drain(..));
+                    }
                 }
             });
-        
+
         // write models
         self.models.write(
             factory,
@@ -295,9 +386,15 @@ impl<B: Backend> RenderGroup<B, World> for DrawAtmosphere<B> {
             self.batches.count() as u64,
             Some(self.batches.data()),
         );
+        self.inside_models.write(
+            factory,
+            index,
+            self.inside_batches.count() as u64,
+            Some(self.inside_batches.data()),
+        );
 
         // update changed status
-        changed = changed || self.batches.changed();
+        changed = changed || self.batches.changed() || self.inside_batches.changed();
 
         self.change.prepare_result(index, changed)
     }
@@ -315,26 +412,35 @@ impl<B: Backend> RenderGroup<B, World> for DrawAtmosphere<B> {
 
         let models_loc = self.vertex_format.len() as u32;
 
-        encoder.bind_graphics_pipeline(&self.pipeline);
-        self.env.bind(index, layout, 0, encoder);
-
-        if self.models.bind(index, models_loc, 0, encoder) {
-            for (mesh, range) in self.batches.iter() {
-                if let Some(mesh) =
-                    B::unwrap_mesh(unsafe { mesh_storage.get_by_id_unchecked(*mesh) })
-                {
-                    if let Err(error) = mesh.bind_and_draw(
-                        0,
-                        &self.vertex_format,
-                        range.clone(),
-                        encoder,
-                    ) {
-                        log::warn!(
-                            "Trying to draw a mesh that lacks {:?} vertex attributes. Pass {} requires attributes {:?}.",
-                            error.not_found.attributes,
-                            "Atmosphere",
+        // shells seen from outside first, then the ones the camera is in
+        for &inside in &[false, true] {
+            let (pipeline, models, batches) = if inside {
+                (&self.inside_pipeline, &mut self.inside_models, &self.inside_batches)
+            } else {
+                (&self.pipeline, &mut self.models, &self.batches)
+            };
+
+            encoder.bind_graphics_pipeline(pipeline);
+            self.env.bind(index, layout, 0, encoder);
+
+            if models.bind(index, models_loc, 0, encoder) {
+                for (mesh, range) in batches.iter() {
+                    if let Some(mesh) =
+                        B::unwrap_mesh(unsafe { mesh_storage.get_by_id_unchecked(*mesh) })
+                    {
+                        if let Err(error) = mesh.bind_and_draw(
+                            0,
                             &self.vertex_format,
-                        );
+                            range.clone(),
+                            encoder,
+                        ) {
+                            log::warn!(
+                                "Trying to draw a mesh that lacks {:?} vertex attributes. Pass {} requires attributes {:?}.",
+                                error.not_found.attributes,
+                                "Atmosphere",
+                                &self.vertex_format,
+                            );
+                        }
                     }
                 }
             }
@@ -344,7 +450,14 @@ impl<B: Backend> RenderGroup<B, World> for DrawAtmosphere<B> {
     fn dispose(self: Box<Self>, factory: &mut Factory<B>, _aux: &World) {
         unsafe {
             factory.device().destroy_graphics_pipeline(self.pipeline);
+            factory.device().destroy_graphics_pipeline(self.inside_pipeline);
             factory.device().destroy_pipeline_layout(self.pipeline_layout);
         }
     }
+}
+
+// world space center and radius of a unit sphere mesh placed with this transform
+fn sphere(transform: &Transform) -> (Vector3<f32>, f32) {
+    let matrix = transform.global_matrix();
+    (matrix.column(3).xyz(), matrix.column(0).xyz().norm())
 }
\ No newline at end of file
@@ -0,0 +1,20 @@
+use amethyst::{
+    ecs::{
+        prelude::{Entity, Join},
+        storage::MaskedStorage,
+        world::EntitiesRes,
+        Storage,
+    },
+    renderer::{camera::Camera, ActiveCamera},
+};
+use std::ops::Deref;
+
+// the camera the scene is rendered from, picked the same way amethyst's own passes pick it
+pub fn active_camera<D>(active: &ActiveCamera, entities: &EntitiesRes, cameras: &Storage<'_, Camera, D>) -> Option<Entity>
+where
+    D: Deref<Target = MaskedStorage<Camera>>,
+{
+    active.entity
+        .filter(|entity| cameras.contains(*entity))
+        .or_else(|| (entities, cameras).join().next().map(|(entity, _)| entity))
+}
@@ -0,0 +1,74 @@
+use amethyst::renderer::rendy::hal::format::{ChannelType, Format};
+
+// transfer function the last pass applies before writing to the surface, so a colour is encoded
+// exactly once whether or not the surface format encodes on write
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputTransfer {
+    // the surface is srgb, the hardware encodes the linear colour
+    Linear,
+    // the surface is unorm, the shader encodes with the srgb curve
+    Srgb,
+}
+
+impl Default for OutputTransfer {
+    fn default() -> Self {
+        OutputTransfer::Srgb
+    }
+}
+
+impl OutputTransfer {
+    pub fn for_surface(format: Format) -> Self {
+        if is_srgb(format) {
+            OutputTransfer::Linear
+        } else {
+            OutputTransfer::Srgb
+        }
+    }
+
+    // whether the shader has to encode
+    pub fn shader_encodes(self) -> bool {
+        self == OutputTransfer::Srgb
+    }
+}
+
+// formats that encode on write and decode on read
+pub fn is_srgb(format: Format) -> bool {
+    format.base_format().1 == ChannelType::Srgb
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst::core::math::Matrix4;
+    use crate::render::debug_view::{DebugView, DebugViewUniformArgs};
+    use crate::render::lens::{LensSettings, LensUniformArgs};
+
+    // surface formats and whether the shader has to encode for them
+    const SURFACES: [(Format, bool); 6] = [
+        (Format::Bgra8Unorm, true),
+        (Format::Rgba8Unorm, true),
+        (Format::A2b10g10r10Unorm, true),
+        (Format::Bgra8Srgb, false),
+        (Format::Rgba8Srgb, false),
+        (Format::Abgr8Srgb, false),
+    ];
+
+    #[test]
+    fn shader_encodes_only_for_linear_surfaces() {
+        for (format, encodes) in SURFACES.iter() {
+            assert_eq!(OutputTransfer::for_surface(*format).shader_encodes(), *encodes, "{:?}", format);
+        }
+    }
+
+    // the lens and debug view passes are the ones that write to the surface
+    #[test]
+    fn post_passes_write_the_encode_flag() {
+        for (format, encodes) in SURFACES.iter() {
+            let output = OutputTransfer::for_surface(*format);
+            let lens = LensUniformArgs::new(&LensSettings::default(), 1920.0, 1080.0, 0.0, output);
+            assert_eq!(bool::from(lens.encode_srgb), *encodes, "lens on {:?}", format);
+            let debug_view = DebugViewUniformArgs::new(&Matrix4::identity(), 1920.0, 1080.0, DebugView::Off, 1.0, output);
+            assert_eq!(bool::from(debug_view.encode_srgb), *encodes, "debug view on {:?}", format);
+        }
+    }
+}
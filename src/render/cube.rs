@@ -0,0 +1,239 @@
+// parser for .cube 3D lookup tables, as exported by most grading tools
+use failure::Fail;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CubeLut {
+    pub title: Option<String>,
+    // number of entries along each axis
+    pub size: usize,
+    pub domain_min: [f32; 3],
+    pub domain_max: [f32; 3],
+    // size^3 entries with red changing fastest, then green, then blue
+    pub data: Vec<[f32; 3]>,
+}
+
+#[derive(Debug, PartialEq, Fail)]
+pub enum CubeError {
+    #[fail(display = "line {}: unknown keyword {}", line, keyword)]
+    UnknownKeyword { line: usize, keyword: String },
+    #[fail(display = "line {}: {}", line, message)]
+    Malformed { line: usize, message: String },
+    #[fail(display = "1D lookup tables aren't supported")]
+    Unsupported1D,
+    #[fail(display = "missing LUT_3D_SIZE")]
+    MissingSize,
+    #[fail(display = "expected {} entries but found {}", expected, found)]
+    WrongEntryCount { expected: usize, found: usize },
+}
+
+impl CubeLut {
+    // lookup table that leaves every colour unchanged
+    pub fn identity(size: usize) -> Self {
+        let max = (size - 1).max(1) as f32;
+        let mut data = Vec::with_capacity(size * size * size);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    data.push([r as f32 / max, g as f32 / max, b as f32 / max]);
+                }
+            }
+        }
+        Self { title:None, size, domain_min:[0.0; 3], domain_max:[1.0; 3], data }
+    }
+
+    pub fn parse(source: &str) -> Result<Self, CubeError> {
+        let mut title = None;
+        let mut size = None;
+        let mut domain_min = [0.0; 3];
+        let mut domain_max = [1.0; 3];
+        let mut data = Vec::new();
+
+        for (index, line) in source.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let keyword = line.split_whitespace().next().unwrap_or_default();
+            let rest = line[keyword.len()..].trim();
+
+            match keyword {
+                "TITLE" => {
+                    title = Some(rest.trim_matches('"').to_string());
+                },
+                "LUT_3D_SIZE" => {
+                    let value = parse_values::<usize>(rest, 1, line_number)?[0];
+                    if value < 2 {
+                        return Err(CubeError::Malformed {
+                            line: line_number,
+                            message: format!("LUT_3D_SIZE must be at least 2, got {}", value),
+                        });
+                    }
+                    size = Some(value);
+                },
+                "LUT_1D_SIZE" => return Err(CubeError::Unsupported1D),
+                "DOMAIN_MIN" => domain_min = to_rgb(parse_values(rest, 3, line_number)?),
+                "DOMAIN_MAX" => domain_max = to_rgb(parse_values(rest, 3, line_number)?),
+                // resolve writes the input range as a single pair
+                "LUT_3D_INPUT_RANGE" => {
+                    let range = parse_values::<f32>(rest, 2, line_number)?;
+                    domain_min = [range[0]; 3];
+                    domain_max = [range[1]; 3];
+                },
+                _ if starts_numeric(keyword) => {
+                    data.push(to_rgb(parse_values(line, 3, line_number)?));
+                },
+                _ => {
+                    return Err(CubeError::UnknownKeyword {
+                        line: line_number,
+                        keyword: keyword.to_string(),
+                    });
+                },
+            }
+        }
+
+        let size = size.ok_or(CubeError::MissingSize)?;
+        let expected = size * size * size;
+        if data.len() != expected {
+            return Err(CubeError::WrongEntryCount { expected, found: data.len() });
+        }
+
+        Ok(Self { title, size, domain_min, domain_max, data })
+    }
+
+    // entries as rgba, the layout expected when uploading to a 3D texture
+    pub fn to_rgba(&self) -> Vec<[f32; 4]> {
+        self.data.iter().map(|[r, g, b]| [*r, *g, *b, 1.0]).collect()
+    }
+}
+
+fn starts_numeric(word: &str) -> bool {
+    word.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '+' || c == '.')
+}
+
+fn parse_values<T: std::str::FromStr>(text: &str, count: usize, line: usize) -> Result<Vec<T>, CubeError> {
+    let values = text
+        .split_whitespace()
+        .map(|word| word.parse::<T>().map_err(|_| CubeError::Malformed {
+            line,
+            message: format!("invalid number {}", word),
+        }))
+        .collect::<Result<Vec<T>, CubeError>>()?;
+    if values.len() != count {
+        return Err(CubeError::Malformed {
+            line,
+            message: format!("expected {} values, found {}", count, values.len()),
+        });
+    }
+    Ok(values)
+}
+
+fn to_rgb(values: Vec<f32>) -> [f32; 3] {
+    [values[0], values[1], values[2]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a 2x2x2 table that swaps red and blue
+    const SWAP: &str = "\
+TITLE \"Swap red and blue\"
+LUT_3D_SIZE 2
+
+0.0 0.0 0.0
+0.0 0.0 1.0
+0.0 1.0 0.0
+0.0 1.0 1.0
+1.0 0.0 0.0
+1.0 0.0 1.0
+1.0 1.0 0.0
+1.0 1.0 1.0
+";
+
+    #[test]
+    fn parses_a_3d_lut() {
+        let lut = CubeLut::parse(SWAP).unwrap();
+        assert_eq!(lut.title.as_deref(), Some("Swap red and blue"));
+        assert_eq!(lut.size, 2);
+        assert_eq!(lut.domain_min, [0.0; 3]);
+        assert_eq!(lut.domain_max, [1.0; 3]);
+        assert_eq!(lut.data.len(), 8);
+        // red changes fastest, so the second entry is for pure red
+        assert_eq!(lut.data[1], [0.0, 0.0, 1.0]);
+        assert_eq!(lut.data[4], [1.0, 0.0, 0.0]);
+        assert_eq!(lut.to_rgba()[1], [0.0, 0.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn identity_round_trips_through_the_parser() {
+        let identity = CubeLut::identity(3);
+        let source = format!(
+            "LUT_3D_SIZE 3\n{}",
+            identity.data.iter().map(|[r, g, b]| format!("{} {} {}\n", r, g, b)).collect::<String>()
+        );
+        assert_eq!(CubeLut::parse(&source).unwrap(), identity);
+    }
+
+    #[test]
+    fn reads_the_domain() {
+        let source = format!("DOMAIN_MIN 0.0 -0.5 0.1\nDOMAIN_MAX 1.0 2.0 4.5\n{}", SWAP);
+        let lut = CubeLut::parse(&source).unwrap();
+        assert_eq!(lut.domain_min, [0.0, -0.5, 0.1]);
+        assert_eq!(lut.domain_max, [1.0, 2.0, 4.5]);
+    }
+
+    #[test]
+    fn reads_the_input_range() {
+        let source = format!("LUT_3D_INPUT_RANGE -0.25 1.5\n{}", SWAP);
+        let lut = CubeLut::parse(&source).unwrap();
+        assert_eq!(lut.domain_min, [-0.25; 3]);
+        assert_eq!(lut.domain_max, [1.5; 3]);
+    }
+
+    #[test]
+    fn skips_comments_and_blank_lines() {
+        let source = SWAP
+            .lines()
+            .flat_map(|line| vec!["# a comment", "", "   ", line])
+            .collect::<Vec<_>>()
+            .join("\r\n");
+        assert_eq!(CubeLut::parse(&source).unwrap(), CubeLut::parse(SWAP).unwrap());
+    }
+
+    #[test]
+    fn rejects_a_wrong_entry_count() {
+        let source = SWAP.lines().take(10).collect::<Vec<_>>().join("\n");
+        assert_eq!(CubeLut::parse(&source), Err(CubeError::WrongEntryCount { expected:8, found:7 }));
+    }
+
+    #[test]
+    fn rejects_a_missing_size() {
+        let source = SWAP.replace("LUT_3D_SIZE 2", "");
+        assert_eq!(CubeLut::parse(&source), Err(CubeError::MissingSize));
+    }
+
+    #[test]
+    fn rejects_1d_luts() {
+        let source = "LUT_1D_SIZE 2\n0.0 0.0 0.0\n1.0 1.0 1.0\n";
+        assert_eq!(CubeLut::parse(source), Err(CubeError::Unsupported1D));
+    }
+
+    #[test]
+    fn rejects_unknown_keywords() {
+        let source = SWAP.replace("LUT_3D_SIZE 2", "LUT_3D_SIZE 2\nLUT_4D_SIZE 2");
+        assert_eq!(
+            CubeLut::parse(&source),
+            Err(CubeError::UnknownKeyword { line:3, keyword:"LUT_4D_SIZE".to_string() })
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_entries() {
+        let source = SWAP.replace("0.0 1.0 1.0", "0.0 1.0");
+        assert!(matches!(CubeLut::parse(&source), Err(CubeError::Malformed { line:7, .. })));
+        let source = SWAP.replace("LUT_3D_SIZE 2", "LUT_3D_SIZE 1");
+        assert!(matches!(CubeLut::parse(&source), Err(CubeError::Malformed { line:2, .. })));
+    }
+}
@@ -0,0 +1,516 @@
+// debug view render pipeline, replaces the final image with one of the intermediate buffers. the
+// graph it shows isn't quite the one that renders the game: the scene is drawn single sampled, as
+// the view reads the scene images directly rather than resolving them, and taa is left out with its
+// jitter, so the depth and normals line up with the pixels they're shown on
+
+use amethyst::{
+    core::math::Matrix4,
+    ecs::{World, Entities, Read, ReadStorage},
+    prelude::*,
+    renderer::{camera::Camera, ActiveCamera},
+    window::ScreenDimensions,
+};
+use rendy::{
+    command::{QueueId, RenderPassEncoder },
+    hal::{
+        self, 
+        device::Device, pso::DescriptorPool,
+        format::Format, image::Filter::Nearest, image::WrapMode 
+    },
+    graph::{
+        render::{
+            PrepareResult,
+            SimpleGraphicsPipelineDesc,
+            SimpleGraphicsPipeline,
+            Layout, SetLayout
+        },
+        GraphContext, NodeBuffer, NodeImage, ImageAccess,
+    },
+    mesh::{
+        VertexFormat, AsVertex
+    },
+    memory,
+    resource::{ 
+        self,Escape,BufferInfo,Buffer,
+        Handle as RendyHandle,DescriptorSetLayout,
+        ImageViewInfo,SamplerInfo,ImageView,Sampler,
+    },
+    factory::{Factory},
+};
+use glsl_layout::*;
+use crate::render::camera::active_camera;
+use crate::render::color::OutputTransfer;
+use std::mem::size_of;
+use crate::render::depth;
+use crate::render::tonemap::TonemapSettings;
+
+// intermediate buffer shown instead of the final image
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebugView {
+    Off,
+    // raw hdr colour in false colour by exposure value
+    Hdr,
+    // linearised depth
+    Depth,
+    // view space normals reconstructed from the depth buffer
+    Normals,
+    // tonemapped image before fxaa
+    Tonemapped,
+    // pixels fxaa considers an edge
+    FxaaEdges,
+}
+
+impl Default for DebugView {
+    fn default() -> Self {
+        DebugView::Off
+    }
+}
+
+impl DebugView {
+    pub fn next(self) -> Self {
+        match self {
+            DebugView::Off => DebugView::Hdr,
+            DebugView::Hdr => DebugView::Depth,
+            DebugView::Depth => DebugView::Normals,
+            DebugView::Normals => DebugView::Tonemapped,
+            DebugView::Tonemapped => DebugView::FxaaEdges,
+            DebugView::FxaaEdges => DebugView::Off,
+        }
+    }
+
+    // mode index in the shader
+    fn index(self) -> i32 {
+        match self {
+            DebugView::Off => 0,
+            DebugView::Hdr => 1,
+            DebugView::Depth => 2,
+            DebugView::Normals => 3,
+            DebugView::Tonemapped => 4,
+            DebugView::FxaaEdges => 5,
+        }
+    }
+}
+
+// debug view resource, the pass is only part of the render graph while a view is selected and the
+// graph is rebuilt without supersampling and taa for as long as it is
+#[derive(Default)]
+pub struct DebugViewSettings {
+    pub view: DebugView,
+}
+
+// uniform arguments
+/// layout(std140, set = 0, binding = 0) uniform DebugViewUniformArgs {
+///    uniform mat4 inverse_projection;
+///    uniform float screen_width;
+///    uniform float screen_height;
+///    uniform int mode;
+///    uniform float exposure;
+///    uniform float znear;
+///    uniform float zfar;
+///    uniform bool encode_srgb;
+/// };
+#[derive(Clone, Copy, Debug, AsStd140)]
+#[repr(C, align(4))]
+pub struct DebugViewUniformArgs {
+    pub inverse_projection: mat4,
+    pub screen_width: float,
+    pub screen_height: float,
+    pub mode: int,
+    pub exposure: float,
+    pub znear: float,
+    pub zfar: float,
+    pub encode_srgb: boolean,
+}
+
+impl DebugViewUniformArgs {
+    pub fn new(projection: &Matrix4<f32>, width: f32, height: f32, view: DebugView, exposure: f32, output: OutputTransfer) -> Self {
+        // depth is linearised with the clip planes the projection currently has
+        let (znear, zfar) = depth::clip_planes(projection[(2, 2)], projection[(2, 3)]);
+        let inverse_projection: [[f32; 4]; 4] = projection
+            .try_inverse()
+            .unwrap_or_else(Matrix4::identity)
+            .into();
+        Self {
+            inverse_projection: inverse_projection.into(),
+            screen_width: width,
+            screen_height: height,
+            mode: view.index(),
+            exposure,
+            znear,
+            zfar,
+            encode_srgb: output.shader_encodes().into(),
+        }
+    }
+}
+
+/// Vertex Arguments to pass into shader.
+/// layout(location = 0) out VertexData {
+///    vec2 position;
+///    vec2 tex_coord;
+/// } vertex;
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, AsStd140)]
+#[repr(C, align(4))]
+pub struct DebugViewVertexArgs {
+    pub position: vec2,
+    pub tex_coord: vec2,
+}
+
+/// Required to send data into the shader.
+/// These names must match the shader.
+impl AsVertex for DebugViewVertexArgs {
+    fn vertex() -> VertexFormat {
+        VertexFormat::new((
+            (Format::Rg32Sfloat, "position"),
+            (Format::Rg32Sfloat, "tex_coord"),
+        ))
+    }
+}
+
+
+// the pipeline itself, it writes to the surface so it applies the output transfer function
+#[derive(Debug, Default)]
+pub struct PipelineDesc {
+    pub output: OutputTransfer,
+}
+
+#[derive(Debug)]
+pub struct Pipeline<B: hal::Backend> {
+    buffer: Escape<Buffer<B>>,
+    sets: Vec<B::DescriptorSet>,
+    descriptor_pool: B::DescriptorPool,
+    image_sampler: Escape<Sampler<B>>,
+    image_views: Vec<Escape<ImageView<B>>>,
+    vertex_buffer: Escape<Buffer<B>>,
+    settings: Settings,
+    output: OutputTransfer,
+}
+
+// utility to calculte the uniform size and offset including alignment
+#[derive(Debug, PartialEq, Eq)]
+struct Settings {
+    align: u64
+}
+
+impl Settings {
+    const UNIFORM_SIZE:u64 = size_of::<<DebugViewUniformArgs as AsStd140>::Std140>() as u64;
+
+    #[inline]
+    fn buffer_frame_size(&self) -> u64 {
+        ((Self::UNIFORM_SIZE - 1) / self.align + 1) * self.align
+    }
+
+    #[inline]
+    fn uniform_offset(&self, index: u64) -> u64 {
+        self.buffer_frame_size() * index as u64
+    }
+}
+
+impl<B> SimpleGraphicsPipelineDesc<B, World> for PipelineDesc
+where B: hal::Backend {
+    type Pipeline = Pipeline<B>;
+
+    // hdr, depth and tonemapped images
+    fn images(&self) -> Vec<ImageAccess> {
+        vec![
+            ImageAccess {
+                access: hal::image::Access::SHADER_READ,
+                usage: hal::image::Usage::SAMPLED,
+                layout: hal::image::Layout::ShaderReadOnlyOptimal,
+                stages: hal::pso::PipelineStage::FRAGMENT_SHADER,
+            },
+            ImageAccess {
+                access: hal::image::Access::SHADER_READ,
+                usage: hal::image::Usage::SAMPLED,
+                layout: hal::image::Layout::DepthStencilReadOnlyOptimal,
+                stages: hal::pso::PipelineStage::FRAGMENT_SHADER,
+            },
+            ImageAccess {
+                access: hal::image::Access::SHADER_READ,
+                usage: hal::image::Usage::SAMPLED,
+                layout: hal::image::Layout::ShaderReadOnlyOptimal,
+                stages: hal::pso::PipelineStage::FRAGMENT_SHADER,
+            },
+        ]
+    }
+
+    fn depth_stencil(&self) -> Option<hal::pso::DepthStencilDesc> {
+        None
+    }
+
+    fn vertices(
+        &self,
+    ) -> Vec<(
+        Vec<hal::pso::Element<hal::format::Format>>,
+        hal::pso::ElemStride,
+        hal::pso::VertexInputRate,
+    )> {
+        vec![
+            DebugViewVertexArgs::vertex().gfx_vertex_input_desc(hal::pso::VertexInputRate::Vertex),
+        ]
+    }
+
+    fn load_shader_set(
+        &self,
+        factory: &mut Factory<B>,
+        _world: &World,
+    ) -> rendy::shader::ShaderSet<B> {
+        crate::render::shader::shader_set(factory, "fsquad.vert", "debug_view.frag")
+    }
+
+    fn layout(&self) -> Layout {
+        Layout {
+            sets: vec![SetLayout {
+                bindings: vec![
+                    hal::pso::DescriptorSetLayoutBinding {
+                        binding: 0,
+                        ty: hal::pso::DescriptorType::UniformBuffer,
+                        count: 1,
+                        stage_flags: hal::pso::ShaderStageFlags::FRAGMENT,
+                        immutable_samplers: false,
+                    },
+                    hal::pso::DescriptorSetLayoutBinding {
+                        binding: 1,
+                        ty: hal::pso::DescriptorType::CombinedImageSampler,
+                        count: 3,
+                        stage_flags: hal::pso::ShaderStageFlags::FRAGMENT,
+                        immutable_samplers: false,
+                    },
+                ],
+            }],
+            push_constants: Vec::new(),
+        }
+    }
+
+    fn build<'a>(
+        self,
+        ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        _queue: QueueId,
+        _world: &World,
+        buffers: Vec<NodeBuffer>,
+        images: Vec<NodeImage>,
+        set_layouts: &[RendyHandle<DescriptorSetLayout<B>>],
+    ) -> Result<Pipeline<B>, failure::Error> {
+        assert!(buffers.is_empty());
+        assert!(images.len() == 3);
+        assert!(set_layouts.len() == 1);
+
+        let align_limit = hal::adapter::PhysicalDevice::limits(factory.physical()).min_uniform_buffer_offset_alignment;
+        let settings = Settings { align:align_limit };
+        let frames = 3;
+
+        let mut descriptor_pool = unsafe {
+            factory.create_descriptor_pool(
+                frames,
+                vec![
+                    hal::pso::DescriptorRangeDesc {
+                        ty: hal::pso::DescriptorType::UniformBuffer,
+                        count: frames,
+                    },
+                    hal::pso::DescriptorRangeDesc {
+                        ty: hal::pso::DescriptorType::CombinedImageSampler,
+                        count: frames * 3,
+                    },
+                ],
+                hal::pso::DescriptorPoolCreateFlags::empty(),
+            )?
+        };
+
+        let image_sampler = factory
+            .create_sampler(SamplerInfo {
+                min_filter:Nearest,
+                mag_filter:Nearest,
+                mip_filter:Nearest,
+                wrap_mode:(WrapMode::Clamp,WrapMode::Clamp,WrapMode::Clamp),
+                lod_bias:hal::image::Lod::ZERO,
+                lod_range:hal::image::Lod::ZERO .. hal::image::Lod::MAX,
+                comparison:None,
+                border:[0.0,0.0,0.0,0.0].into(),
+                normalized:true,
+                anisotropic:hal::image::Anisotropic::Off
+            })
+            .unwrap();
+
+        let image_views = images.iter().map(|image| {
+            let image_handle = ctx
+                .get_image(image.id)
+                .expect("Input image missing");
+            factory
+                .create_image_view(
+                    image_handle.clone(),
+                    ImageViewInfo {
+                        view_kind: resource::ViewKind::D2,
+                        format: image_handle.format(),
+                        swizzle: hal::format::Swizzle::NO,
+                        range: image.range.clone(),
+                    },
+                )
+                .expect("Could not create input image view")
+        }).collect::<Vec<_>>();
+
+        let buffer = factory
+            .create_buffer(
+                BufferInfo {
+                    size: settings.buffer_frame_size() * frames as u64,
+                    usage: hal::buffer::Usage::UNIFORM,
+                },
+                rendy::memory::MemoryUsageValue::Dynamic,
+            )
+            .unwrap();
+
+        let mut sets = Vec::with_capacity(frames);
+        for index in 0..frames {
+            unsafe {
+                let set = descriptor_pool.allocate_set(&set_layouts[0].raw()).unwrap();
+                factory.write_descriptor_sets(vec![
+                    hal::pso::DescriptorSetWrite {
+                        set: &set,
+                        binding: 0,
+                        array_offset: 0,
+                        descriptors: Some(hal::pso::Descriptor::Buffer(
+                            buffer.raw(),
+                            Some(settings.uniform_offset(index as u64))
+                            ..Some(
+                                settings.uniform_offset(index as u64) + Settings::UNIFORM_SIZE,
+                            ),
+                        )),
+                    },
+                    hal::pso::DescriptorSetWrite {
+                        set: &set,
+                        binding: 1,
+                        array_offset: 0,
+                        descriptors: image_views.iter().zip(images.iter()).map(|(view, image)| {
+                            hal::pso::Descriptor::CombinedImageSampler(
+                                view.raw(),
+                                image.layout,
+                                image_sampler.raw()
+                            )
+                        }).collect::<Vec<_>>(),
+                    }
+                ]);
+                sets.push(set);
+            }
+        }
+
+        // create a static vertex buffer
+        let vbuf_size = DebugViewVertexArgs::vertex().stride as u64 * 6;
+        let mut vertex_buffer = factory.create_buffer(
+            BufferInfo {
+                size: vbuf_size,
+                usage: hal::buffer::Usage::VERTEX
+            },
+            memory::Dynamic,
+        ).unwrap();
+        unsafe {
+            factory
+                .upload_visible_buffer(
+                    &mut vertex_buffer,
+                    0,
+                    &[
+                        DebugViewVertexArgs { position:[-1f32,1f32].into(), tex_coord:[0f32,1f32].into() },
+                        DebugViewVertexArgs { position:[1f32,-1f32].into(), tex_coord:[1f32,0f32].into() },
+                        DebugViewVertexArgs { position:[-1f32,-1f32].into(), tex_coord:[0f32,0f32].into() },
+                        DebugViewVertexArgs { position:[1f32,-1f32].into(), tex_coord:[1f32,0f32].into() },
+                        DebugViewVertexArgs { position:[-1f32,1f32].into(), tex_coord:[0f32,1f32].into() },
+                        DebugViewVertexArgs { position:[1f32,1f32].into(), tex_coord:[1f32,1f32].into() },
+                    ],
+                )
+                .unwrap();
+        }
+
+        Ok(Pipeline {
+            output: self.output,
+            buffer,
+            sets,
+            image_views,
+            image_sampler,
+            descriptor_pool,
+            settings,
+            vertex_buffer,
+        })
+    }
+}
+
+impl<B> SimpleGraphicsPipeline<B, World> for Pipeline<B>
+where
+    B: hal::Backend,
+{
+    type Desc = PipelineDesc;
+
+    fn prepare(
+        &mut self,
+        factory: &Factory<B>,
+        _queue: QueueId,
+        _set_layouts: &[RendyHandle<DescriptorSetLayout<B>>],
+        index: usize,
+        world: &World,
+    ) -> PrepareResult {
+        let dimensions = world.read_resource::<ScreenDimensions>();
+        let debug_view = world.read_resource::<DebugViewSettings>();
+        let tonemap_settings = world.read_resource::<TonemapSettings>();
+        let (entities, active, cameras) = world.system_data::<(Entities<'_>, Read<'_, ActiveCamera>, ReadStorage<'_, Camera>)>();
+
+        let projection = active_camera(&active, &entities, &cameras)
+            .and_then(|camera| cameras.get(camera))
+            .map_or_else(Matrix4::identity, |camera| *camera.projection().as_matrix());
+
+        // write to the uniform
+        unsafe {
+            factory
+                .upload_visible_buffer(
+                    &mut self.buffer,
+                    self.settings.uniform_offset(index as u64),
+                    &[DebugViewUniformArgs::new(
+                        &projection,
+                        dimensions.width(),
+                        dimensions.height(),
+                        debug_view.view,
+                        tonemap_settings.exposure,
+                        self.output,
+                    ).std140()],
+                )
+                .unwrap()
+        };
+        
+        PrepareResult::DrawRecord
+    }
+
+    fn draw(
+        &mut self,
+        layout: &B::PipelineLayout,
+        mut encoder: RenderPassEncoder<'_, B>,
+        index: usize,
+        _world: &World,
+    ) {
+        unsafe {
+            encoder.bind_graphics_descriptor_sets(
+                layout,
+                0,
+                Some(&self.sets[index]),
+                std::iter::empty(),
+            );
+
+            encoder.bind_vertex_buffers(0, Some((self.vertex_buffer.raw(), 0)));
+
+            encoder.draw(0..6, 0..1);
+        }
+    }
+
+    fn dispose(mut self, factory: &mut Factory<B>, _world: &World) {
+        unsafe {
+            self.descriptor_pool.reset();
+            factory.destroy_descriptor_pool(self.descriptor_pool);
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uniform_matches_the_shader() {
+        assert_uniform_layout!(DebugViewUniformArgs, "debug_view.frag", Settings::UNIFORM_SIZE, [
+            inverse_projection, screen_width, screen_height, mode, exposure, znear, zfar, encode_srgb
+        ]);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,174 @@
+// depth buffer configuration shared by the render graph and the custom pipelines. the scene depth is a
+// standard D32 float buffer tested with Less, the one comparison amethyst's pbr and debug lines groups
+// build their pipelines with, so precision is kept by pulling the near plane out instead
+use amethyst::{
+    core::transform::Transform,
+    ecs::prelude::{ Join, System, SystemData, ReadStorage, WriteStorage, Write, Entities, Entity },
+    derive::{SystemDesc},
+    renderer::camera::{Camera, Projection},
+    utils::tag::Tag,
+};
+use rendy::hal::{
+    command::{ClearDepthStencil, ClearValue},
+    pso,
+};
+use std::collections::HashMap;
+use crate::flight::Ship;
+use crate::origin::scene_position;
+use crate::planet::Planet;
+
+// the near plane sits at this fraction of the distance to the nearest surface
+const NEAR_FRACTION: f32 = 0.5;
+// and at most this far in from the far plane, so a camera far from everything keeps some depth range
+const MIN_DEPTH_RANGE: f32 = 1000.0;
+
+// value the depth buffer is cleared to, the far plane
+pub const CLEAR_DEPTH: f32 = 1.0;
+
+// comparison that lets closer fragments pass
+pub const COMPARISON: pso::Comparison = pso::Comparison::Less;
+
+// comparison for geometry drawn at the far plane, it passes where the depth is still cleared
+pub const FAR_COMPARISON: pso::Comparison = pso::Comparison::LessEqual;
+
+pub fn clear_value() -> ClearValue {
+    ClearValue::DepthStencil(ClearDepthStencil(CLEAR_DEPTH, 0))
+}
+
+// the z row terms of a perspective projection matrix, (m22, m23), mapping the near plane to 0 and the far plane to 1
+pub fn projection_terms(znear: f32, zfar: f32) -> (f32, f32) {
+    (zfar / (znear - zfar), zfar * znear / (znear - zfar))
+}
+
+// inverse of projection_terms, recovers (znear, zfar) from the z row of a projection matrix
+pub fn clip_planes(m22: f32, m23: f32) -> (f32, f32) {
+    (m23 / m22, m23 / (m22 + 1.0))
+}
+
+// near plane for a camera some distance above the nearest surface, never closer than it was authored
+pub fn near_plane(surface_distance: f32, znear: f32, zfar: f32) -> f32 {
+    if !surface_distance.is_finite() {
+        return znear;
+    }
+    (surface_distance * NEAR_FRACTION).min(zfar / MIN_DEPTH_RANGE).max(znear)
+}
+
+// clip planes the cameras were authored with, the projection matrices only have the current ones
+#[derive(Default)]
+pub struct ClipPlanes {
+    planes: HashMap<Entity, (f32, f32)>,
+}
+
+impl ClipPlanes {
+    pub fn authored(&self, camera: Entity) -> Option<(f32, f32)> {
+        self.planes.get(&camera).copied()
+    }
+}
+
+// moves the near plane of every perspective camera out to half the distance to the nearest body or ship,
+// which keeps the depth precision close to the surface wherever the camera is
+#[derive(SystemDesc)]
+#[system_desc(name(ClipPlaneSystemDesc))]
+pub struct ClipPlaneSystem;
+
+impl<'s> System<'s> for ClipPlaneSystem {
+    type SystemData = (
+        Entities<'s>,
+        Write<'s, ClipPlanes>,
+        WriteStorage<'s, Camera>,
+        ReadStorage<'s, Transform>,
+        ReadStorage<'s, Planet>,
+        ReadStorage<'s, Tag<Ship>>,
+    );
+
+    fn run(&mut self, (entities, mut clip_planes, mut cameras, transforms, planets, ships) : Self::SystemData) {
+        for (entity, camera, transform) in (&*entities, &mut cameras, &transforms).join() {
+            if let Projection::Perspective(perspective) = camera.projection_mut() {
+                let (znear, zfar) = *clip_planes.planes
+                    .entry(entity)
+                    .or_insert_with(|| (perspective.near(), perspective.far()));
+                let position = scene_position(transform);
+                let surface_distance = (&transforms, (&planets).maybe(), (&ships).maybe()).join()
+                    .filter(|(_, planet, ship)| planet.is_some() || ship.is_some())
+                    .map(|(transform, _, _)| (scene_position(transform) - position).norm() - transform.scale().x)
+                    .fold(std::f32::INFINITY, f32::min);
+                let (m22, m23) = projection_terms(near_plane(surface_distance, znear, zfar), zfar);
+                let matrix = perspective.as_matrix_mut();
+                matrix[(2, 2)] = m22;
+                matrix[(2, 3)] = m23;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ndc depth of a view space distance in front of the camera
+    fn depth(m22: f32, m23: f32, distance: f32) -> f32 {
+        (m22 * -distance + m23) / distance
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert_within(a, b, 1e-4);
+    }
+
+    // relative to b, or absolute around 0
+    fn assert_within(a: f32, b: f32, tolerance: f32) {
+        assert!((a - b).abs() <= tolerance * b.abs().max(1.0), "{} != {}", a, b);
+    }
+
+    #[test]
+    fn maps_near_to_0_and_far_to_1() {
+        let (m22, m23) = projection_terms(0.1, 100000.0);
+        assert_close(depth(m22, m23, 0.1), 0.0);
+        assert_close(depth(m22, m23, 100000.0), CLEAR_DEPTH);
+    }
+
+    #[test]
+    fn closer_passes_the_comparison() {
+        assert_eq!(COMPARISON, pso::Comparison::Less);
+        let (m22, m23) = projection_terms(0.1, 100.0);
+        let (near, far) = (depth(m22, m23, 1.0), depth(m22, m23, 2.0));
+        assert!(near < far);
+        assert!(far < CLEAR_DEPTH && far > 0.0);
+    }
+
+    #[test]
+    fn clip_planes_inverts_projection_terms() {
+        for (znear, zfar) in [(0.1, 30000.0), (1.0, 10.0), (0.01, 2000.0), (50.0, 100000.0)].iter() {
+            let (m22, m23) = projection_terms(*znear, *zfar);
+            let (near, far) = clip_planes(m22, m23);
+            assert_close(near, *znear);
+            // the far plane comes from a term close to -1, so f32 loses some of it at large ratios
+            assert_within(far, *zfar, 1e-2);
+        }
+    }
+
+    #[test]
+    fn near_plane_follows_the_surface() {
+        assert_eq!(near_plane(3.0, 0.1, 100000.0), 1.5);
+        // never closer than authored
+        assert_eq!(near_plane(0.01, 0.1, 100000.0), 0.1);
+        assert_eq!(near_plane(-1.0, 0.1, 100000.0), 0.1);
+        // nothing around
+        assert_eq!(near_plane(std::f32::INFINITY, 0.1, 100000.0), 0.1);
+        // far from everything there's still depth range left
+        assert_eq!(near_plane(90000.0, 0.1, 100000.0), 100.0);
+    }
+
+    #[test]
+    fn pulled_out_near_plane_resolves_finer_depth() {
+        // distance between the two closest depths a D32 float stores 1000 units out
+        let resolution = |znear: f32| {
+            let (m22, m23) = projection_terms(znear, 100000.0);
+            let stored = depth(m22, m23, 1000.0);
+            let next = f32::from_bits(stored.to_bits() + 1);
+            // back to a view distance in f64, so only the stored depth is rounded
+            let distance = |d: f32| m23 as f64 / (d as f64 + m22 as f64);
+            (distance(next) - distance(stored)).abs()
+        };
+        assert!(resolution(near_plane(1000.0, 0.1, 100000.0)) * 100.0 < resolution(0.1));
+    }
+}
@@ -0,0 +1,270 @@
+use amethyst::{
+    derive::SystemDesc,
+    ecs::prelude::{Read, System, SystemData, Write},
+    utils::fps_counter::FpsCounter,
+    window::DisplayConfig,
+};
+use crate::render::quality::RenderQuality;
+use crate::settings::GraphicsSettings;
+
+// picks a render scale that keeps the frame time near a target, every change rebuilds the render
+// graph so the scale moves in fixed steps and only after the frame time stayed off target for a while
+#[derive(Clone, Debug)]
+pub struct DynamicResolutionController {
+    pub target_frame_time: f32,
+    pub min_scale: f32,
+    pub max_scale: f32,
+    pub step: f32,
+    // frame time ratios to the target outside of which the scale is lowered or raised
+    pub lower_threshold: f32,
+    pub raise_threshold: f32,
+    // updates the frame time has to stay past a threshold before the scale changes
+    pub patience: u32,
+    // updates to ignore after a change, the rebuild and the new scale need to settle first
+    pub settle: u32,
+    // the frame limiter or vsync hold frames back to about the target time, so how long a frame took
+    // says nothing about how much time was left over
+    pub capped: bool,
+    // updates before a scale that had to be lowered from is tried again
+    pub retry: u32,
+    over: u32,
+    under: u32,
+    cooldown: u32,
+    // scale last lowered from and the updates left until it's tried again
+    ceiling: f32,
+    ceiling_updates: u32,
+}
+
+impl DynamicResolutionController {
+    pub fn new(target_frame_time: f32) -> Self {
+        Self {
+            target_frame_time,
+            min_scale: 0.5,
+            max_scale: 1.0,
+            step: 0.1,
+            lower_threshold: 1.05,
+            raise_threshold: 0.8,
+            patience: 30,
+            settle: 60,
+            capped: false,
+            retry: 600,
+            over: 0,
+            under: 0,
+            cooldown: 0,
+            ceiling: std::f32::INFINITY,
+            ceiling_updates: 0,
+        }
+    }
+
+    // new scale for a frame time given the current scale, the same scale when nothing should change
+    pub fn update(&mut self, frame_time: f32, scale: f32) -> f32 {
+        if self.ceiling_updates > 0 {
+            self.ceiling_updates -= 1;
+        }
+        if self.cooldown > 0 {
+            self.cooldown -= 1;
+            return scale;
+        }
+
+        let ratio = frame_time / self.target_frame_time;
+        // a capped frame on time may have had plenty left over, the only way to find out is trying the
+        // next scale up and dropping back if that runs over
+        let headroom = ratio < self.raise_threshold || (self.capped && ratio <= self.lower_threshold);
+        if ratio > self.lower_threshold {
+            self.over += 1;
+            self.under = 0;
+        } else if headroom {
+            self.under += 1;
+            self.over = 0;
+        } else {
+            self.over = 0;
+            self.under = 0;
+        }
+
+        let new_scale = if self.over >= self.patience {
+            // the frame time is roughly proportional to the pixel count, so to the scale squared
+            let ideal = scale / ratio.sqrt();
+            let steps = ((scale - ideal) / self.step).ceil().max(1.0);
+            scale - steps * self.step
+        } else if self.under >= self.patience {
+            // raise one step at a time, overshooting means dropping right back down, so a scale that
+            // was too slow isn't tried again for a while
+            if self.ceiling_updates > 0 && scale + self.step >= self.ceiling - std::f32::EPSILON {
+                scale
+            } else {
+                scale + self.step
+            }
+        } else {
+            scale
+        };
+        let new_scale = self.quantize(new_scale);
+
+        if new_scale < scale - std::f32::EPSILON {
+            self.ceiling = scale;
+            self.ceiling_updates = self.retry;
+        }
+        if (new_scale - scale).abs() > std::f32::EPSILON {
+            self.over = 0;
+            self.under = 0;
+            self.cooldown = self.settle;
+        }
+        new_scale
+    }
+
+    // scale rounded to a whole number of steps within the limits
+    fn quantize(&self, scale: f32) -> f32 {
+        let steps = (scale / self.step).round();
+        (steps * self.step).max(self.min_scale).min(self.max_scale)
+    }
+}
+
+// drives the dynamic scale of the render quality from the measured frame rate
+#[derive(SystemDesc)]
+#[system_desc(name(DynamicResolutionSystemDesc))]
+pub struct DynamicResolutionSystem {
+    #[system_desc(skip)]
+    controller: Option<DynamicResolutionController>,
+}
+
+impl DynamicResolutionSystem {
+    pub fn new() -> Self {
+        Self { controller:None }
+    }
+}
+
+impl<'s> System<'s> for DynamicResolutionSystem {
+    type SystemData = (
+        Read<'s, FpsCounter>,
+        Read<'s, GraphicsSettings>,
+        Read<'s, DisplayConfig>,
+        Write<'s, RenderQuality>,
+    );
+
+    fn run(&mut self, (fps_counter, graphics, display, mut quality) : Self::SystemData) {
+        if !quality.dynamic_resolution || quality.target_fps <= 0.0 {
+            self.controller = None;
+            if quality.dynamic_scale != 1.0 {
+                quality.dynamic_scale = 1.0;
+            }
+            return;
+        }
+
+        let target_frame_time = 1.0 / quality.target_fps;
+        let controller = self.controller
+            .get_or_insert_with(|| DynamicResolutionController::new(target_frame_time));
+        controller.target_frame_time = target_frame_time;
+        // the refresh rate isn't known, so vsync is taken to hold frames at the target
+        let frame_cap_time = if graphics.frame_cap > 0 { 1.0 / graphics.frame_cap as f32 } else { 0.0 };
+        controller.capped = display.vsync || frame_cap_time >= target_frame_time * controller.raise_threshold;
+
+        let fps = fps_counter.sampled_fps();
+        if fps <= 0.0 {
+            return;
+        }
+        let scale = controller.update(1.0 / fps, quality.dynamic_scale);
+        if scale != quality.dynamic_scale {
+            log::debug!("Dynamic resolution scale {:.1} at {:.1} fps", scale, fps);
+            quality.dynamic_scale = scale;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TARGET: f32 = 1.0 / 60.0;
+
+    // scale after feeding the same frame time a number of times
+    fn run(controller: &mut DynamicResolutionController, frame_time: f32, mut scale: f32, updates: u32) -> f32 {
+        for _ in 0..updates {
+            scale = controller.update(frame_time, scale);
+        }
+        scale
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn holds_the_scale_inside_the_band() {
+        let mut controller = DynamicResolutionController::new(TARGET);
+        assert_eq!(run(&mut controller, TARGET * 1.04, 0.8, 1000), 0.8);
+        assert_eq!(run(&mut controller, TARGET * 0.81, 0.8, 1000), 0.8);
+        // frames flipping across the band never build up enough patience either way
+        let mut scale = 0.8;
+        for update in 0..1000 {
+            let frame_time = if update % 2 == 0 { TARGET * 1.5 } else { TARGET * 0.5 };
+            scale = controller.update(frame_time, scale);
+        }
+        assert_eq!(scale, 0.8);
+    }
+
+    #[test]
+    fn changes_after_the_patience_runs_out() {
+        let mut controller = DynamicResolutionController::new(TARGET);
+        let patience = controller.patience;
+        assert_eq!(run(&mut controller, TARGET * 1.1, 0.8, patience - 1), 0.8);
+        assert_close(controller.update(TARGET * 1.1, 0.8), 0.7);
+    }
+
+    #[test]
+    fn waits_for_the_cooldown_after_a_change() {
+        let mut controller = DynamicResolutionController::new(TARGET);
+        let (patience, settle) = (controller.patience, controller.settle);
+        let scale = run(&mut controller, TARGET * 0.5, 0.6, patience);
+        assert_close(scale, 0.7);
+        // still fast, but nothing changes until it settled and the patience ran out again
+        assert_eq!(run(&mut controller, TARGET * 0.5, scale, settle + patience - 1), scale);
+        assert_close(controller.update(TARGET * 0.5, scale), 0.8);
+    }
+
+    #[test]
+    fn quantizes_to_whole_steps() {
+        let mut controller = DynamicResolutionController::new(TARGET);
+        let patience = controller.patience;
+        // twice the target time wants about 1 / sqrt(2) of the scale, rounded down to a step
+        let scale = run(&mut controller, TARGET * 2.0, 1.0, patience);
+        assert_close(scale, 0.7);
+        // a scale between steps snaps to the nearest one straight away
+        let mut controller = DynamicResolutionController::new(TARGET);
+        assert_close(controller.update(TARGET, 0.63), 0.6);
+        let mut controller = DynamicResolutionController::new(TARGET);
+        assert_close(controller.update(TARGET, 0.67), 0.7);
+    }
+
+    #[test]
+    fn clamps_to_the_scale_limits() {
+        let mut controller = DynamicResolutionController::new(TARGET);
+        assert_eq!(run(&mut controller, TARGET * 10.0, 1.0, 1000), controller.min_scale);
+        let mut controller = DynamicResolutionController::new(TARGET);
+        assert_eq!(run(&mut controller, TARGET * 0.1, 0.5, 1000), controller.max_scale);
+    }
+
+    #[test]
+    fn capped_frames_on_target_recover_to_the_max_scale() {
+        let mut controller = DynamicResolutionController::new(TARGET);
+        assert_eq!(run(&mut controller, TARGET, 0.5, 1000), 0.5);
+        // held at the target by the frame limiter, the time left over is hidden
+        let mut controller = DynamicResolutionController::new(TARGET);
+        controller.capped = true;
+        assert_eq!(run(&mut controller, TARGET, 0.5, 1000), controller.max_scale);
+    }
+
+    #[test]
+    fn capped_raise_that_runs_over_waits_before_retrying() {
+        let mut controller = DynamicResolutionController::new(TARGET);
+        controller.capped = true;
+        let (patience, settle, retry) = (controller.patience, controller.settle, controller.retry);
+        let scale = run(&mut controller, TARGET, 0.7, patience);
+        assert_close(scale, 0.8);
+        // too slow at 0.8, back down to 0.7
+        let scale = run(&mut controller, TARGET * 1.1, scale, settle + patience);
+        assert_close(scale, 0.7);
+        // on target again, but 0.8 isn't tried until the retry time passed
+        let scale = run(&mut controller, TARGET, scale, retry - 1);
+        assert_close(scale, 0.7);
+        assert_close(run(&mut controller, TARGET, scale, settle + patience), 0.8);
+    }
+}
@@ -10,7 +10,7 @@ use rendy::{
     command::{QueueId, RenderPassEncoder },
     hal::{
         self, 
-        device::Device, pso::ShaderStageFlags, pso::DescriptorPool,
+        device::Device, pso::DescriptorPool,
         format::Format, image::Filter::Linear, image::WrapMode 
     },
     graph::{
@@ -25,7 +25,6 @@ use rendy::{
     mesh::{
         VertexFormat, AsVertex
     },
-    shader::{SpirvShader},
     memory,
     resource::{ 
         self,Escape,BufferInfo,Buffer,
@@ -43,25 +42,6 @@ pub struct FxaaSettings {
     pub enabled: bool,
 }
 
-// load our shader pair
-lazy_static::lazy_static! {
-    static ref VERTEX:SpirvShader = SpirvShader::from_bytes(
-        include_bytes!("../../assets/shader/fsquad.vert.spv"),
-        ShaderStageFlags::VERTEX,
-        "main",
-    ).unwrap();
-
-    static ref FRAGMENT:SpirvShader = SpirvShader::from_bytes(
-        include_bytes!("../../assets/shader/fxaa.frag.spv"),
-        ShaderStageFlags::FRAGMENT,
-        "main",
-    ).unwrap();
-
-    static ref SHADERS: rendy::shader::ShaderSetBuilder = rendy::shader::ShaderSetBuilder::default()
-        .with_vertex(&*VERTEX).unwrap()
-        .with_fragment(&*FRAGMENT).unwrap();
-}
-
 // uniform arguments
 /// layout(std140, set = 0, binding = 0) uniform FXAAUniformArgs {
 ///    uniform float screen_width;
@@ -122,7 +102,7 @@ struct Settings {
 }
 
 impl Settings {
-    const UNIFORM_SIZE:u64 = size_of::<FXAAUniformArgs>() as u64;
+    const UNIFORM_SIZE:u64 = size_of::<<FXAAUniformArgs as AsStd140>::Std140>() as u64;
 
     #[inline]
     fn buffer_frame_size(&self) -> u64 {
@@ -169,7 +149,7 @@ where B: hal::Backend {
         factory: &mut Factory<B>,
         _world: &World,
     ) -> rendy::shader::ShaderSet<B> {
-        SHADERS.build(factory, Default::default()).unwrap()
+        crate::render::shader::shader_set(factory, "fsquad.vert", "fxaa.frag")
     }
 
     fn layout(&self) -> Layout {
@@ -255,7 +235,7 @@ where B: hal::Backend {
                 image_handle.clone(),
                 ImageViewInfo {
                     view_kind: resource::ViewKind::D2,
-                    format: hal::format::Format::Rgba8Unorm,
+                    format: image_handle.format(),
                     swizzle: hal::format::Swizzle::NO,
                     range: images[0].range.clone(),
                 },
@@ -404,4 +384,13 @@ where
             factory.destroy_descriptor_pool(self.descriptor_pool);
         }
     }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uniform_matches_the_shader() {
+        assert_uniform_layout!(FXAAUniformArgs, "fxaa.frag", Settings::UNIFORM_SIZE, [screen_width, screen_height, enabled]);
+    }
 }
\ No newline at end of file
@@ -7,7 +7,7 @@ use amethyst::{
         types::DefaultBackend,
         Factory, Format, GraphBuilder, GraphCreator, Kind,
         RenderGroupDesc, SubpassBuilder,
-        rendy::graph::render::{SimpleGraphicsPipeline,RenderGroupBuilder},
+        rendy::graph::render::{SimpleGraphicsPipeline,SimpleGraphicsPipelineDesc,RenderGroupBuilder},
     },
     ui::{
         DrawUiDesc,
@@ -15,16 +15,62 @@ use amethyst::{
     window::{ScreenDimensions, Window },
 };
 //use crate::fxaa::DrawFXAADesc;
+use crate::render::tonemap::ColorGradingSettings;
+use crate::render::debug_view::{DebugView, DebugViewSettings};
+use crate::render::ssaa::{AntiAliasing, AntiAliasingSettings};
+use crate::render::quality::RenderQuality;
+use crate::render::color::OutputTransfer;
+use crate::render::shader::ShaderReloads;
 
 #[derive(Default)]
 pub struct RenderGraph {
     dimensions: Option<ScreenDimensions>,
+    lut: Option<String>,
+    debug_view: bool,
+    anti_aliasing: Option<AntiAliasing>,
+    quality: Option<RenderQuality>,
+    shader_reloads: u32,
     dirty: bool,
 }
 
 impl GraphCreator<DefaultBackend> for RenderGraph {
     // indicate if it should be rebuilt
     fn rebuild(&mut self, world: &World) -> bool {
+        // Rebuild when the colour grading lut changes, it's uploaded when the tonemap pass is built.
+        let lut = world.try_fetch::<ColorGradingSettings>().and_then(|s| s.lut.clone());
+        if self.lut != lut {
+            self.lut = lut;
+            self.dirty = true;
+        }
+
+        // Rebuild when a debug view is switched on or off, the pass is only added when in use.
+        let debug_view = world.try_fetch::<DebugViewSettings>().map_or(false, |s| s.view != DebugView::Off);
+        if self.debug_view != debug_view {
+            self.debug_view = debug_view;
+            self.dirty = true;
+        }
+
+        // Rebuild when the anti-aliasing mode changes, ssaa changes the size of the scene attachments.
+        let anti_aliasing = world.try_fetch::<AntiAliasingSettings>().map(|s| s.mode);
+        if self.anti_aliasing != anti_aliasing {
+            self.anti_aliasing = anti_aliasing;
+            self.dirty = true;
+        }
+
+        // Rebuild when the hdr format or render scale changes, they size and format the scene images.
+        let quality = world.try_fetch::<RenderQuality>().map(|s| s.clone());
+        if self.quality != quality {
+            self.quality = quality;
+            self.dirty = true;
+        }
+
+        // Rebuild when shaders were reloaded, every pipeline loads its shaders again.
+        let shader_reloads = world.try_fetch::<ShaderReloads>().map_or(0, |s| s.count);
+        if self.shader_reloads != shader_reloads {
+            self.shader_reloads = shader_reloads;
+            self.dirty = true;
+        }
+
         // Rebuild when dimensions change, but wait until at least two frames have the same.
         let new_dimensions = world.try_fetch::<ScreenDimensions>();
         use std::ops::Deref;
@@ -46,7 +92,7 @@ impl GraphCreator<DefaultBackend> for RenderGraph {
     ) -> GraphBuilder<DefaultBackend, World> {
         use amethyst::renderer::rendy::{
             graph::present::PresentNode,
-            hal::command::{ClearDepthStencil, ClearValue},
+            hal::command::ClearValue,
         };
 
         self.dirty = false;
@@ -56,37 +102,77 @@ impl GraphCreator<DefaultBackend> for RenderGraph {
         let dimensions = self.dimensions.as_ref().unwrap();
         let window_kind = Kind::D2(dimensions.width() as u32, dimensions.height() as u32, 1, 1);
 
+        // Samples per pixel of the scene, the scene attachments are supersampled rather than
+        // multisampled so the built-in pbr and debug lines groups can draw into them, see ssaa.rs
+        let samples = crate::render::ssaa::sample_count(factory, world);
+        let requested = self.anti_aliasing.unwrap_or_default().samples();
+        if samples < requested && !self.debug_view {
+            log::warn!("{}x supersampling doesn't fit at this resolution, using {}x", requested, samples);
+        }
+
+        // The 3d passes render at the render scale, tonemapping samples them back up to the window size
+        let quality = self.quality.clone().unwrap_or_default();
+        let (render_width, render_height) = quality.render_size(dimensions.width(), dimensions.height());
+        let render_kind = Kind::D2(render_width, render_height, 1, 1);
+        let (scene_width, scene_height) = crate::render::ssaa::supersampled_size((render_width, render_height), samples);
+        let scene_kind = Kind::D2(scene_width, scene_height, 1, 1);
+        let hdr_format = quality.hdr_format.format();
+
         // Create a new drawing surface in our window
         let surface = factory.create_surface(&window);
         let surface_format = factory.get_surface_format(&surface);
+        // The passes writing to the surface encode only when the surface format doesn't
+        let output_transfer = OutputTransfer::for_surface(surface_format);
 
         // Begin building our RenderGraph
         let mut graph_builder = GraphBuilder::new();
 
-        // HDR color output
+        // HDR color output, resolved from the supersampled scene color when ssaa is on
         let hdr = graph_builder.create_image(
-            window_kind,
+            render_kind,
             1,
-            Format::Rgba32Sfloat,
+            hdr_format,
             Some(ClearValue::Color([0.0, 0.0, 0.0, 1.0].into())),
         );
+        // Scene depth, cleared to the far plane. Every group in the main pass tests with the same comparison,
+        // see depth.rs.
         let depth = graph_builder.create_image(
+            scene_kind,
+            1,
+            Format::D32Sfloat,
+            Some(crate::render::depth::clear_value()),
+        );
+
+        // Supersampled scene color
+        let scene_color = if samples > 1 {
+            graph_builder.create_image(
+                scene_kind,
+                1,
+                hdr_format,
+                Some(ClearValue::Color([0.0, 0.0, 0.0, 1.0].into())),
+            )
+        } else {
+            hdr
+        };
+
+        // The ui keeps its own depth buffer at the window size
+        let ui_depth = graph_builder.create_image(
             window_kind,
             1,
             Format::D32Sfloat,
-            Some(ClearValue::DepthStencil(ClearDepthStencil(1.0, 0))),
+            Some(crate::render::depth::clear_value()),
         );
 
-        // Tone mapped output
+        // Tone mapped output, srgb so the post passes read and write linear colour without banding
         let tonemapped = graph_builder.create_image(
             window_kind,
             1,
-            Format::Rgba8Unorm, //Format::Rgba8Unorm,
+            Format::Rgba8Srgb,
             Some(ClearValue::Color([0.0, 0.0, 0.0, 1.0].into())),
         );
 
-        // Antialiased output
-        let antialiased = graph_builder.create_image(
+        // Final output with lens effects, presented to the window
+        let output = graph_builder.create_image(
             window_kind,
             1,
             surface_format,
@@ -101,44 +187,130 @@ impl GraphCreator<DefaultBackend> for RenderGraph {
                 .with_group(DrawPbrTransparentDesc::default().builder())
                 .with_group(crate::render::atmosphere::DrawAtmosphereDesc::default().builder())
                 .with_group(crate::render::sun::DrawSunDesc::default().builder())
-                .with_color(hdr)
+                .with_color(scene_color)
                 .with_depth_stencil(depth)
                 .into_pass(),
         );
 
+        // SSAA resolve pass
+        let scene_pass = if samples > 1 {
+            graph_builder.add_node(
+                crate::render::ssaa::Pipeline::builder()
+                    .with_image(scene_color)
+                    .into_subpass()
+                    .with_dependency(main_pass)
+                    .with_color(hdr)
+                    .into_pass()
+            )
+        } else {
+            main_pass
+        };
+
+        // TAA pass, blends the jittered frame with the history and keeps a copy for the next frame
+        // the debug views show the unjittered scene, see debug_view.rs
+        let taa = self.anti_aliasing == Some(AntiAliasing::Taa) && !self.debug_view;
+        let (post_input, post_dependency) = if taa {
+            let taa_hdr = graph_builder.create_image(
+                render_kind,
+                1,
+                hdr_format,
+                None,
+            );
+            // not cleared, the contents have to survive until the next frame
+            let history = graph_builder.create_image(
+                render_kind,
+                1,
+                hdr_format,
+                None,
+            );
+            let taa_pass = graph_builder.add_node(
+                crate::render::taa::Pipeline::builder()
+                    .with_image(hdr)
+                    .with_image(depth)
+                    .with_image(history)
+                    .into_subpass()
+                    .with_dependency(scene_pass)
+                    .with_color(taa_hdr)
+                    .into_pass()
+            );
+            graph_builder.add_node(
+                crate::render::history::Pipeline::builder()
+                    .with_image(taa_hdr)
+                    .into_subpass()
+                    .with_dependency(taa_pass)
+                    .with_color(history)
+                    .into_pass()
+            );
+            (taa_hdr, taa_pass)
+        } else {
+            (hdr, scene_pass)
+        };
+
         // Post processing pass
         let tonemap_pass = graph_builder.add_node(
             crate::render::tonemap::Pipeline::builder()
-                .with_image(hdr)
+                .with_image(post_input)
                 .into_subpass()
-                .with_dependency(main_pass)
+                .with_dependency(post_dependency)
                 .with_color(tonemapped)
                 .into_pass()
         );
 
-        // FXAA pass
-        let fxaa_pass = graph_builder.add_node(
-            crate::render::fxaa::Pipeline::builder()
-                .with_image(tonemapped)
-                .into_subpass()
-                .with_dependency(tonemap_pass)
-                .with_color(antialiased)
-                .into_pass()
-        );
+        let final_pass = if self.debug_view {
+            // Debug view pass, replaces the post processed image with an intermediate buffer
+            graph_builder.add_node(
+                crate::render::debug_view::PipelineDesc { output: output_transfer }.builder()
+                    .with_image(hdr)
+                    .with_image(depth)
+                    .with_image(tonemapped)
+                    .into_subpass()
+                    .with_dependency(tonemap_pass)
+                    .with_color(output)
+                    .into_pass()
+            )
+        } else {
+            // Antialiased output
+            let antialiased = graph_builder.create_image(
+                window_kind,
+                1,
+                Format::Rgba8Srgb,
+                Some(ClearValue::Color([0.0, 0.0, 0.0, 1.0].into())),
+            );
+
+            // FXAA pass
+            let fxaa_pass = graph_builder.add_node(
+                crate::render::fxaa::Pipeline::builder()
+                    .with_image(tonemapped)
+                    .into_subpass()
+                    .with_dependency(tonemap_pass)
+                    .with_color(antialiased)
+                    .into_pass()
+            );
+
+            // Lens effects pass
+            graph_builder.add_node(
+                crate::render::lens::PipelineDesc { output: output_transfer }.builder()
+                    .with_image(antialiased)
+                    .into_subpass()
+                    .with_dependency(fxaa_pass)
+                    .with_color(output)
+                    .into_pass()
+            )
+        };
 
         // UI pass
         let ui_pass = graph_builder.add_node(
             SubpassBuilder::new()
                 .with_group(DrawUiDesc::default().builder())
-                .with_dependency(fxaa_pass)
-                .with_color(antialiased)
-                .with_depth_stencil(depth)
+                .with_dependency(final_pass)
+                .with_color(output)
+                .with_depth_stencil(ui_depth)
                 .into_pass()
         );
 
         // Finally, add the pass to the graph
         let _present = graph_builder
-            .add_node(PresentNode::builder(factory, surface, antialiased).with_dependency(ui_pass));
+            .add_node(PresentNode::builder(factory, surface, output).with_dependency(ui_pass));
 
         graph_builder
     }
@@ -1,5 +1,26 @@
+// checks the shader uniform blocks against the rust structs in the tests of the passes, declared
+// first so its macro is in scope for them
+#[cfg(test)]
+#[macro_use]
+mod shader_layout;
 pub mod graph;
+pub mod depth;
+pub mod camera;
 pub mod atmosphere;
 pub mod sun;
 pub mod fxaa;
-pub mod tonemap;
\ No newline at end of file
+pub mod ssaa;
+pub mod taa;
+pub mod history;
+pub mod tonemap;
+pub mod lens;
+pub mod debug_view;
+pub mod cube;
+pub mod color;
+pub mod shader;
+#[cfg(feature = "shader_reload")]
+pub mod shader_reload;
+#[cfg(feature = "shader_reload")]
+pub mod shader_compile;
+pub mod quality;
+pub mod dynamic_resolution;
\ No newline at end of file
@@ -0,0 +1,71 @@
+use amethyst::renderer::Format;
+use serde::{Deserialize, Serialize};
+
+// format of the hdr scene images
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum HdrFormat {
+    // 16 bytes per pixel, full float precision
+    R32G32B32A32,
+    // 8 bytes per pixel, plenty for colour
+    R16G16B16A16,
+    // 4 bytes per pixel, no alpha and no negative values
+    B10G11R11,
+}
+
+impl Default for HdrFormat {
+    fn default() -> Self {
+        HdrFormat::R32G32B32A32
+    }
+}
+
+impl HdrFormat {
+    pub fn format(self) -> Format {
+        match self {
+            HdrFormat::R32G32B32A32 => Format::Rgba32Sfloat,
+            HdrFormat::R16G16B16A16 => Format::Rgba16Sfloat,
+            HdrFormat::B10G11R11 => Format::B10g11r11Ufloat,
+        }
+    }
+}
+
+// render quality resource, loaded from config/render.ron
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RenderQuality {
+    pub hdr_format: HdrFormat,
+    // size of the 3d passes relative to the window, the post passes upscale to the window size
+    pub render_scale: f32,
+    // lower the render scale when frames take longer than the target frame rate allows
+    pub dynamic_resolution: bool,
+    pub target_fps: f32,
+    // multiplier on the render scale picked by the dynamic resolution controller
+    #[serde(skip)]
+    pub dynamic_scale: f32,
+}
+
+impl Default for RenderQuality {
+    fn default() -> Self {
+        Self {
+            hdr_format: HdrFormat::default(),
+            render_scale: 1.0,
+            dynamic_resolution: false,
+            target_fps: 60.0,
+            dynamic_scale: 1.0,
+        }
+    }
+}
+
+impl RenderQuality {
+    pub const MIN_RENDER_SCALE: f32 = 0.5;
+    pub const MAX_RENDER_SCALE: f32 = 2.0;
+
+    pub fn render_scale(&self) -> f32 {
+        (self.render_scale * self.dynamic_scale).max(Self::MIN_RENDER_SCALE).min(Self::MAX_RENDER_SCALE)
+    }
+
+    // size of the 3d passes for a window size
+    pub fn render_size(&self, width: f32, height: f32) -> (u32, u32) {
+        let scale = self.render_scale();
+        (((width * scale).round() as u32).max(1), ((height * scale).round() as u32).max(1))
+    }
+}
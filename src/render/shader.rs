@@ -0,0 +1,108 @@
+// shaders from assets/shader, baked in as spir-v or, with the shader_reload feature, compiled from
+// the glsl on disk so they can be edited while running
+
+use rendy::{
+    factory::Factory,
+    hal::{self, pso::ShaderStageFlags},
+    shader::{Shader, ShaderSet, ShaderSetBuilder, SpirvShader},
+};
+use std::{borrow::Cow, fmt::Debug};
+
+// counts shader reloads, the render graph rebuilds its pipelines when it changes
+#[derive(Default)]
+pub struct ShaderReloads {
+    pub count: u32,
+}
+
+// spir-v compiled by the build script
+fn baked(name: &str) -> &'static [u8] {
+    match name {
+        "fsquad.vert" => &include_bytes!(concat!(env!("OUT_DIR"), "/fsquad.vert.spv"))[..],
+        "atmosphere.vert" => &include_bytes!(concat!(env!("OUT_DIR"), "/atmosphere.vert.spv"))[..],
+        "atmosphere.frag" => &include_bytes!(concat!(env!("OUT_DIR"), "/atmosphere.frag.spv"))[..],
+        "sun.vert" => &include_bytes!(concat!(env!("OUT_DIR"), "/sun.vert.spv"))[..],
+        "sun.frag" => &include_bytes!(concat!(env!("OUT_DIR"), "/sun.frag.spv"))[..],
+        "fxaa.frag" => &include_bytes!(concat!(env!("OUT_DIR"), "/fxaa.frag.spv"))[..],
+        "tonemap.frag" => &include_bytes!(concat!(env!("OUT_DIR"), "/tonemap.frag.spv"))[..],
+        "lens.frag" => &include_bytes!(concat!(env!("OUT_DIR"), "/lens.frag.spv"))[..],
+        "debug_view.frag" => &include_bytes!(concat!(env!("OUT_DIR"), "/debug_view.frag.spv"))[..],
+        "resolve.frag" => &include_bytes!(concat!(env!("OUT_DIR"), "/resolve.frag.spv"))[..],
+        "taa.frag" => &include_bytes!(concat!(env!("OUT_DIR"), "/taa.frag.spv"))[..],
+        "history.frag" => &include_bytes!(concat!(env!("OUT_DIR"), "/history.frag.spv"))[..],
+        _ => panic!("Unknown shader {}", name),
+    }
+}
+
+// the latest spir-v of a shader, reloaded from disk when that's enabled and it compiled
+pub fn spirv(name: &str) -> Cow<'static, [u8]> {
+    #[cfg(feature = "shader_reload")]
+    {
+        if let Some(spirv) = crate::render::shader_reload::compiled(name) {
+            return Cow::Owned(spirv);
+        }
+    }
+    Cow::Borrowed(baked(name))
+}
+
+pub fn stage(name: &str) -> ShaderStageFlags {
+    if name.ends_with(".vert") {
+        ShaderStageFlags::VERTEX
+    } else {
+        ShaderStageFlags::FRAGMENT
+    }
+}
+
+// loads a shader by file name, like "fxaa.frag", falling back to the baked one if the reloaded
+// spir-v is unusable
+pub fn load(name: &str) -> SpirvShader {
+    SpirvShader::from_bytes(&spirv(name), stage(name), "main")
+        .or_else(|e| {
+            log::error!("Failed to load shader {}, using the built-in one: {}", name, e);
+            SpirvShader::from_bytes(baked(name), stage(name), "main")
+        })
+        .expect("Built-in shader is invalid")
+}
+
+fn built_in(name: &str) -> Result<SpirvShader, failure::Error> {
+    SpirvShader::from_bytes(baked(name), stage(name), "main").map_err(error)
+}
+
+fn error<E: Debug>(error: E) -> failure::Error {
+    failure::format_err!("{:?}", error)
+}
+
+// shader module of a shader, the device may still reject reloaded spir-v that parsed, so that falls
+// back to the baked one as well
+pub fn module<B: hal::Backend>(factory: &Factory<B>, name: &str) -> Result<B::ShaderModule, failure::Error> {
+    match unsafe { load(name).module(factory) } {
+        Ok(module) => Ok(module),
+        Err(e) => {
+            log::error!("Failed to create shader module {}, using the built-in one: {:?}", name, e);
+            unsafe { built_in(name)?.module(factory).map_err(error) }
+        },
+    }
+}
+
+fn build_set<B: hal::Backend>(factory: &mut Factory<B>, vertex: &SpirvShader, fragment: &SpirvShader) -> Result<ShaderSet<B>, failure::Error> {
+    ShaderSetBuilder::default()
+        .with_vertex(vertex).map_err(error)?
+        .with_fragment(fragment).map_err(error)?
+        .build(factory, Default::default())
+        .map_err(error)
+}
+
+// shader set of a vertex and fragment shader pair, the baked pair when the reloaded one won't build
+pub fn shader_set<B: hal::Backend>(factory: &mut Factory<B>, vertex: &str, fragment: &str) -> ShaderSet<B> {
+    build_set(factory, &load(vertex), &load(fragment))
+        .or_else(|e| {
+            log::error!("Failed to build shaders {} and {}, using the built-in ones: {}", vertex, fragment, e);
+            build_set(factory, &built_in(vertex)?, &built_in(fragment)?)
+        })
+        .expect("Built-in shader is invalid")
+}
+
+// whether reloaded spir-v can replace a shader, checked before the render graph is rebuilt with it
+#[cfg(feature = "shader_reload")]
+pub fn validate(name: &str, spirv: &[u8]) -> Result<(), failure::Error> {
+    SpirvShader::from_bytes(spirv, stage(name), "main").map(|_| ()).map_err(error)
+}
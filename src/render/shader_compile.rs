@@ -0,0 +1,31 @@
+// glsl to spir-v, shared by the build script and the shader_reload feature so both compile alike
+
+use std::{fs, path::Path};
+
+// compiles a glsl file, includes are resolved relative to the including file and passed to included
+pub fn compile(path: &Path, included: &dyn Fn(&Path)) -> Result<Vec<u8>, String> {
+    let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let kind = if path.extension().map_or(false, |e| e == "vert") {
+        shaderc::ShaderKind::Vertex
+    } else {
+        shaderc::ShaderKind::Fragment
+    };
+
+    let mut compiler = shaderc::Compiler::new().ok_or("Failed to create the shader compiler")?;
+    let mut options = shaderc::CompileOptions::new().ok_or("Failed to create the shader compiler options")?;
+    options.set_include_callback(|requested, _, requesting, _| {
+        let include = Path::new(requesting).parent().unwrap_or_else(|| Path::new("")).join(requested);
+        included(&include);
+        fs::read_to_string(&include)
+            .map(|content| shaderc::ResolvedInclude {
+                resolved_name: include.to_string_lossy().into_owned(),
+                content,
+            })
+            .map_err(|e| format!("{}: {}", include.display(), e))
+    });
+
+    compiler
+        .compile_into_spirv(&source, kind, &path.to_string_lossy(), "main", Some(&options))
+        .map(|artifact| artifact.as_binary_u8().to_vec())
+        .map_err(|e| e.to_string())
+}
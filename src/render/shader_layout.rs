@@ -0,0 +1,303 @@
+// checks the uniform blocks of the compiled shaders against the std140 structs glsl_layout derives
+// for the rust uniform structs, used by the tests of the passes
+
+use std::collections::HashMap;
+
+// a uniform block with its members, their offsets and sizes
+pub struct UniformBlock {
+    pub name: String,
+    pub members: Vec<(String, u32, u32)>,
+}
+
+impl UniformBlock {
+    // bytes up to the end of the last member, std140 rounds the block up to 16 from there
+    pub fn size(&self) -> u32 {
+        self.members.iter().map(|(_, offset, size)| offset + size).max().unwrap_or(0)
+    }
+}
+
+// the types a uniform member can have
+enum Type {
+    Scalar(u32),
+    Vector(u32, u32),
+    Matrix(u32, u32),
+    Array(u32, u32),
+}
+
+// reads the block structs out of the spir-v, named by OpName with member offsets from OpMemberDecorate
+// and sizes from their types
+pub fn uniform_blocks(spirv: &[u8]) -> Vec<UniformBlock> {
+    const OP_NAME: u32 = 5;
+    const OP_MEMBER_NAME: u32 = 6;
+    const OP_TYPE_BOOL: u32 = 20;
+    const OP_TYPE_INT: u32 = 21;
+    const OP_TYPE_FLOAT: u32 = 22;
+    const OP_TYPE_VECTOR: u32 = 23;
+    const OP_TYPE_MATRIX: u32 = 24;
+    const OP_TYPE_ARRAY: u32 = 28;
+    const OP_TYPE_STRUCT: u32 = 30;
+    const OP_CONSTANT: u32 = 43;
+    const OP_DECORATE: u32 = 71;
+    const OP_MEMBER_DECORATE: u32 = 72;
+    const DECORATION_BLOCK: u32 = 2;
+    const DECORATION_ARRAY_STRIDE: u32 = 6;
+    const DECORATION_MATRIX_STRIDE: u32 = 7;
+    const DECORATION_OFFSET: u32 = 35;
+
+    let words = spirv
+        .chunks(4)
+        .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect::<Vec<_>>();
+
+    let mut names = HashMap::new();
+    let mut member_names = HashMap::new();
+    let mut member_offsets = HashMap::new();
+    let mut matrix_strides = HashMap::new();
+    let mut array_strides = HashMap::new();
+    let mut types = HashMap::new();
+    let mut structs = HashMap::new();
+    let mut constants = HashMap::new();
+    let mut blocks = Vec::new();
+
+    // the header is 5 words, then instructions with their word count in the high half
+    let mut index = 5;
+    while index < words.len() {
+        let count = (words[index] >> 16) as usize;
+        let opcode = words[index] & 0xffff;
+        if count == 0 {
+            break;
+        }
+        let operands = &words[index + 1..index + count];
+        match opcode {
+            OP_NAME => { names.insert(operands[0], string(&operands[1..])); },
+            OP_MEMBER_NAME => { member_names.insert((operands[0], operands[1]), string(&operands[2..])); },
+            // bools have no size of their own, they're a 4 byte uint in a block
+            OP_TYPE_BOOL => { types.insert(operands[0], Type::Scalar(4)); },
+            OP_TYPE_INT | OP_TYPE_FLOAT => { types.insert(operands[0], Type::Scalar(operands[1] / 8)); },
+            OP_TYPE_VECTOR => { types.insert(operands[0], Type::Vector(operands[1], operands[2])); },
+            OP_TYPE_MATRIX => { types.insert(operands[0], Type::Matrix(operands[1], operands[2])); },
+            OP_TYPE_ARRAY => { types.insert(operands[0], Type::Array(operands[1], operands[2])); },
+            OP_TYPE_STRUCT => { structs.insert(operands[0], operands[1..].to_vec()); },
+            OP_CONSTANT => { constants.insert(operands[1], operands[2]); },
+            OP_DECORATE if operands[1] == DECORATION_BLOCK => blocks.push(operands[0]),
+            OP_DECORATE if operands[1] == DECORATION_ARRAY_STRIDE => { array_strides.insert(operands[0], operands[2]); },
+            OP_MEMBER_DECORATE if operands[2] == DECORATION_OFFSET => {
+                member_offsets.insert((operands[0], operands[1]), operands[3]);
+            },
+            OP_MEMBER_DECORATE if operands[2] == DECORATION_MATRIX_STRIDE => {
+                matrix_strides.insert((operands[0], operands[1]), operands[3]);
+            },
+            _ => (),
+        }
+        index += count;
+    }
+
+    // size of a member's type, matrices take a column stride per column
+    fn size(types: &HashMap<u32, Type>, constants: &HashMap<u32, u32>, array_strides: &HashMap<u32, u32>, matrix_stride: u32, id: u32) -> u32 {
+        match types.get(&id) {
+            Some(Type::Scalar(size)) => *size,
+            Some(Type::Vector(component, count)) => count * size(types, constants, array_strides, matrix_stride, *component),
+            Some(Type::Matrix(_, columns)) => columns * matrix_stride,
+            Some(Type::Array(_, length)) => {
+                constants.get(length).copied().unwrap_or(0) * array_strides.get(&id).copied().unwrap_or(0)
+            },
+            None => 0,
+        }
+    }
+
+    blocks
+        .into_iter()
+        .filter_map(|id| {
+            let member_types = structs.get(&id).cloned().unwrap_or_default();
+            let mut members = member_offsets
+                .iter()
+                .filter(|((block, _), _)| *block == id)
+                .map(|(key, offset)| {
+                    let matrix_stride = matrix_strides.get(key).copied().unwrap_or(16);
+                    let size = member_types.get(key.1 as usize)
+                        .map_or(0, |ty| size(&types, &constants, &array_strides, matrix_stride, *ty));
+                    (key.1, member_names.get(key).cloned().unwrap_or_default(), *offset, size)
+                })
+                .collect::<Vec<_>>();
+            // blocks without offsets are vertex interfaces, not uniforms
+            if members.is_empty() {
+                return None;
+            }
+            members.sort();
+            Some(UniformBlock {
+                name: names.get(&id).cloned().unwrap_or_default(),
+                members: members.into_iter().map(|(_, name, offset, size)| (name, offset, size)).collect(),
+            })
+        })
+        .collect()
+}
+
+// nul terminated utf-8 packed in words
+fn string(words: &[u32]) -> String {
+    let bytes = words.iter().flat_map(|w| w.to_le_bytes().to_vec()).take_while(|b| *b != 0).collect::<Vec<_>>();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+// differences between a uniform block and the fields of a std140 struct, with its size
+pub fn compare(shader: &str, block: &UniformBlock, fields: &[(String, u32, u32)], std140_size: usize) -> Vec<String> {
+    let mut errors = Vec::new();
+    if block.members.len() != fields.len() {
+        errors.push(format!(
+            "{}: uniform block {} has {} members but the rust struct has {} fields",
+            shader, block.name, block.members.len(), fields.len()
+        ));
+    }
+    for ((member, shader_offset, shader_size), (field, offset, size)) in block.members.iter().zip(fields) {
+        if member != field {
+            errors.push(format!("{}: {} member {} is named {} in the rust struct", shader, block.name, member, field));
+        }
+        if offset != shader_offset {
+            errors.push(format!(
+                "{}: {}.{} is at offset {} in the shader but {} in the rust struct",
+                shader, block.name, member, shader_offset, offset
+            ));
+        }
+        if size != shader_size {
+            errors.push(format!(
+                "{}: {}.{} is {} bytes in the shader but {} in the rust struct",
+                shader, block.name, member, shader_size, size
+            ));
+        }
+    }
+    let block_size = (block.size() + 15) / 16 * 16;
+    if block_size as usize != std140_size {
+        errors.push(format!(
+            "{}: uniform block {} is {} bytes but the rust struct is {}",
+            shader, block.name, block_size, std140_size
+        ));
+    }
+    errors
+}
+
+// checks a uniform block of a compiled shader against the std140 struct of the rust struct with the
+// same name, and the uniform size the pass allocates per frame against both
+pub fn check(shader: &str, name: &str, fields: &[(String, u32, u32)], std140_size: usize, uniform_size: u64) -> Vec<String> {
+    let blocks = uniform_blocks(&crate::render::shader::spirv(shader));
+    let mut errors = match blocks.iter().find(|block| block.name == name) {
+        Some(block) => compare(shader, block, fields, std140_size),
+        None => vec![format!("{}: no uniform block {}", shader, name)],
+    };
+    if uniform_size != std140_size as u64 {
+        errors.push(format!("{}: the pass writes {} bytes of {} but its std140 struct is {}", shader, uniform_size, name, std140_size));
+    }
+    errors
+}
+
+// asserts a rust uniform struct lays out like the block of the same name in a shader, the fields are
+// read off the std140 struct glsl_layout derives for it so it's the layout that gets uploaded
+macro_rules! assert_uniform_layout {
+    ($ty:ident, $shader:expr, $uniform_size:expr, [$($field:ident),* $(,)?]) => {{
+        let value = <<$ty as glsl_layout::AsStd140>::Std140 as Default>::default();
+        let base = &value as *const _ as usize;
+        let fields = vec![$((
+            stringify!($field).to_string(),
+            (&value.$field as *const _ as usize - base) as u32,
+            std::mem::size_of_val(&value.$field) as u32,
+        )),*];
+        let errors = crate::render::shader_layout::check(
+            $shader,
+            stringify!($ty),
+            &fields,
+            std::mem::size_of_val(&value),
+            $uniform_size,
+        );
+        assert!(errors.is_empty(), "{}", errors.join("\n"));
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the block as glsl lays it out in std140
+    fn block() -> UniformBlock {
+        UniformBlock {
+            name: "ExampleUniformArgs".to_string(),
+            members: vec![("tint".to_string(), 0, 12), ("exposure".to_string(), 12, 4), ("view".to_string(), 16, 64)],
+        }
+    }
+
+    fn fields() -> Vec<(String, u32, u32)> {
+        block().members
+    }
+
+    // an instruction with its word count and opcode in the first word
+    fn instruction(opcode: u32, operands: &[u32]) -> Vec<u32> {
+        let mut words = vec![((operands.len() as u32 + 1) << 16) | opcode];
+        words.extend_from_slice(operands);
+        words
+    }
+
+    fn words(text: &str) -> Vec<u32> {
+        let mut bytes = text.as_bytes().to_vec();
+        bytes.resize(text.len() / 4 * 4 + 4, 0);
+        bytes.chunks(4).map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]])).collect()
+    }
+
+    #[test]
+    fn matching_struct_passes() {
+        assert_eq!(block().size(), 80);
+        assert_eq!(compare("example.frag", &block(), &fields(), 80), Vec::<String>::new());
+    }
+
+    #[test]
+    fn mismatched_struct_fails() {
+        // a vec3 padded out to 16 bytes pushes exposure along
+        let mut fields = fields();
+        fields[0].2 = 16;
+        fields[1].1 = 16;
+        fields[2].1 = 32;
+        let errors = compare("example.frag", &block(), &fields, 96);
+        assert!(errors.iter().any(|e| e.contains("tint is 12 bytes in the shader but 16")), "{:?}", errors);
+        assert!(errors.iter().any(|e| e.contains("exposure is at offset 12 in the shader but 16")), "{:?}", errors);
+        assert!(errors.iter().any(|e| e.contains("is 80 bytes but the rust struct is 96")), "{:?}", errors);
+    }
+
+    #[test]
+    fn missing_and_renamed_fields_fail() {
+        let mut fields = fields();
+        fields.pop();
+        let errors = compare("example.frag", &block(), &fields, 80);
+        assert!(errors[0].contains("has 3 members but the rust struct has 2 fields"), "{:?}", errors);
+
+        let mut fields = self::fields();
+        fields.swap(0, 1);
+        let errors = compare("example.frag", &block(), &fields, 80);
+        assert!(errors.iter().any(|e| e.contains("member tint is named exposure")), "{:?}", errors);
+    }
+
+    #[test]
+    fn reads_uniform_blocks_from_spirv() {
+        let (float, vec3, vec4, mat4, block_id, interface_id) = (1, 2, 3, 4, 7, 9);
+        let mut spirv = vec![0x0723_0203, 0x0001_0000, 0, 10, 0];
+        spirv.extend(instruction(5, &[&[block_id][..], &words("ExampleUniformArgs")].concat()));
+        spirv.extend(instruction(6, &[&[block_id, 0][..], &words("tint")].concat()));
+        spirv.extend(instruction(6, &[&[block_id, 1][..], &words("exposure")].concat()));
+        spirv.extend(instruction(6, &[&[block_id, 2][..], &words("view")].concat()));
+        spirv.extend(instruction(5, &[&[interface_id][..], &words("VertexData")].concat()));
+        spirv.extend(instruction(71, &[block_id, 2]));
+        spirv.extend(instruction(71, &[interface_id, 2]));
+        spirv.extend(instruction(72, &[block_id, 1, 35, 12]));
+        spirv.extend(instruction(72, &[block_id, 0, 35, 0]));
+        spirv.extend(instruction(72, &[block_id, 2, 35, 16]));
+        spirv.extend(instruction(72, &[block_id, 2, 7, 16]));
+        spirv.extend(instruction(22, &[float, 32]));
+        spirv.extend(instruction(23, &[vec3, float, 3]));
+        spirv.extend(instruction(23, &[vec4, float, 4]));
+        spirv.extend(instruction(24, &[mat4, vec4, 4]));
+        spirv.extend(instruction(30, &[block_id, vec3, float, mat4]));
+        spirv.extend(instruction(30, &[interface_id, vec3]));
+        let bytes = spirv.iter().flat_map(|w| w.to_le_bytes().to_vec()).collect::<Vec<_>>();
+
+        // the vertex interface block has no offsets, so only the uniform block is found
+        let blocks = uniform_blocks(&bytes);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].name, "ExampleUniformArgs");
+        assert_eq!(blocks[0].members, block().members);
+    }
+}
@@ -0,0 +1,109 @@
+// development only shader hot reloading, watches assets/shader and recompiles the glsl on save
+
+use amethyst::ecs::prelude::{System, Write};
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{mpsc::{channel, Receiver}, Mutex},
+    time::Duration,
+};
+use crate::render::shader::{validate, ShaderReloads};
+use crate::render::shader_compile::compile;
+
+lazy_static::lazy_static! {
+    // spir-v of the shaders that compiled since startup, by file name
+    static ref COMPILED: Mutex<HashMap<String, Vec<u8>>> = Mutex::new(HashMap::new());
+}
+
+pub fn compiled(name: &str) -> Option<Vec<u8>> {
+    COMPILED.lock().unwrap().get(name).cloned()
+}
+
+fn is_shader(path: &Path) -> bool {
+    matches!(path.extension().and_then(|e| e.to_str()), Some("vert") | Some("frag"))
+}
+
+// recompiles changed shaders and bumps ShaderReloads so the render graph picks them up, a thread
+// local system as the file watcher can't be shared between threads
+pub struct ShaderReloadSystem {
+    directory: PathBuf,
+    events: Receiver<DebouncedEvent>,
+    _watcher: Option<RecommendedWatcher>,
+}
+
+impl ShaderReloadSystem {
+    pub fn new(directory: PathBuf) -> Self {
+        let (sender, events) = channel();
+        let watcher = notify::watcher(sender, Duration::from_millis(200))
+            .and_then(|mut watcher| {
+                watcher.watch(&directory, RecursiveMode::Recursive)?;
+                Ok(watcher)
+            })
+            .map_err(|e| log::error!("Failed to watch {} for shader changes: {}", directory.display(), e))
+            .ok();
+        let system = Self { directory, events, _watcher:watcher };
+        // the shaders on disk may be newer than the built-in ones
+        system.compile_all();
+        system
+    }
+
+    fn compile_all(&self) -> bool {
+        let mut changed = false;
+        if let Ok(entries) = fs::read_dir(&self.directory) {
+            for path in entries.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| is_shader(p)) {
+                changed |= Self::compile_shader(&path);
+            }
+        }
+        changed
+    }
+
+    fn compile_shader(path: &Path) -> bool {
+        let name = match path.file_name().and_then(|n| n.to_str()) {
+            Some(name) => name.to_string(),
+            None => return false,
+        };
+        let compiled = compile(path, &|_| ())
+            .and_then(|spirv| validate(&name, &spirv).map(|_| spirv).map_err(|e| e.to_string()));
+        match compiled {
+            Ok(spirv) => {
+                log::info!("Compiled shader {}", name);
+                COMPILED.lock().unwrap().insert(name, spirv);
+                true
+            },
+            Err(error) => {
+                log::error!("Failed to compile shader {}:\n{}", name, error);
+                false
+            },
+        }
+    }
+}
+
+impl<'s> System<'s> for ShaderReloadSystem {
+    type SystemData = Write<'s, ShaderReloads>;
+
+    fn run(&mut self, mut reloads: Self::SystemData) {
+        let mut changed = false;
+        let mut header_changed = false;
+        for event in self.events.try_iter() {
+            if let DebouncedEvent::Write(path) | DebouncedEvent::Create(path) | DebouncedEvent::Rename(_, path) = event {
+                if !is_shader(&path) {
+                    continue;
+                }
+                // headers are included everywhere, so everything is recompiled
+                if path.parent() != Some(self.directory.as_path()) {
+                    header_changed = true;
+                } else {
+                    changed |= Self::compile_shader(&path);
+                }
+            }
+        }
+        if header_changed {
+            changed |= self.compile_all();
+        }
+        if changed {
+            reloads.count += 1;
+        }
+    }
+}
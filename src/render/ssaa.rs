@@ -0,0 +1,537 @@
+// supersampling resolve render pipeline, the scene is rendered at a multiple of the render size and this
+// averages each block of samples down to a pixel before tonemapping. it isn't multisampling because amethyst's
+// pbr and debug lines groups build their pipelines single sampled, so the main pass has to stay single sampled
+// based on tonepass pipeline from pbr-rendy: https://github.com/termhn/rendy-pbr/blob/master/src/node/pbr/tonemap.rs
+
+use amethyst::{
+    ecs::{World},
+    prelude::*,
+    window::ScreenDimensions,
+};
+use crate::render::debug_view::{DebugView, DebugViewSettings};
+use crate::render::quality::RenderQuality;
+use rendy::{
+    command::{QueueId, RenderPassEncoder },
+    hal::{
+        self, 
+        device::Device, pso::DescriptorPool,
+        format::Format, image::Filter::Nearest, image::WrapMode 
+    },
+    graph::{
+        render::{
+            PrepareResult,
+            SimpleGraphicsPipelineDesc,
+            SimpleGraphicsPipeline,
+            Layout, SetLayout
+        },
+        GraphContext, NodeBuffer, NodeImage, ImageAccess,
+    },
+    mesh::{
+        VertexFormat, AsVertex
+    },
+    memory,
+    resource::{ 
+        self,Escape,BufferInfo,Buffer,
+        Handle as RendyHandle,DescriptorSetLayout,
+        ImageViewInfo,SamplerInfo,ImageView,Sampler,
+    },
+    factory::{Factory},
+};
+use glsl_layout::*;
+use serde::{Deserialize, Serialize};
+use std::mem::size_of;
+
+// anti-aliasing mode, ssaa modes render the scene at several times the pixels and resolve it before tonemapping,
+// taa accumulates jittered frames before tonemapping
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum AntiAliasing {
+    Off,
+    Fxaa,
+    Ssaa2,
+    Ssaa4,
+    Ssaa8,
+    // 4x ssaa with fxaa on top for the edges it leaves, like thin alpha blended ones
+    SsaaFxaa,
+    // temporal anti-aliasing, jittered single sample frames accumulated over time
+    Taa,
+}
+
+impl Default for AntiAliasing {
+    fn default() -> Self {
+        AntiAliasing::Fxaa
+    }
+}
+
+impl AntiAliasing {
+    pub fn samples(self) -> u8 {
+        match self {
+            AntiAliasing::Off | AntiAliasing::Fxaa | AntiAliasing::Taa => 1,
+            AntiAliasing::Ssaa2 => 2,
+            AntiAliasing::Ssaa4 | AntiAliasing::SsaaFxaa => 4,
+            AntiAliasing::Ssaa8 => 8,
+        }
+    }
+
+    pub fn fxaa(self) -> bool {
+        match self {
+            AntiAliasing::Fxaa | AntiAliasing::SsaaFxaa => true,
+            _ => false,
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            AntiAliasing::Off => AntiAliasing::Fxaa,
+            AntiAliasing::Fxaa => AntiAliasing::Ssaa2,
+            AntiAliasing::Ssaa2 => AntiAliasing::Ssaa4,
+            AntiAliasing::Ssaa4 => AntiAliasing::Ssaa8,
+            AntiAliasing::Ssaa8 => AntiAliasing::SsaaFxaa,
+            AntiAliasing::SsaaFxaa => AntiAliasing::Taa,
+            AntiAliasing::Taa => AntiAliasing::Off,
+        }
+    }
+
+    pub fn previous(self) -> Self {
+        match self {
+            AntiAliasing::Off => AntiAliasing::Taa,
+            AntiAliasing::Fxaa => AntiAliasing::Off,
+            AntiAliasing::Ssaa2 => AntiAliasing::Fxaa,
+            AntiAliasing::Ssaa4 => AntiAliasing::Ssaa2,
+            AntiAliasing::Ssaa8 => AntiAliasing::Ssaa4,
+            AntiAliasing::SsaaFxaa => AntiAliasing::Ssaa8,
+            AntiAliasing::Taa => AntiAliasing::SsaaFxaa,
+        }
+    }
+}
+
+// resource with the active anti-aliasing mode
+#[derive(Default)]
+pub struct AntiAliasingSettings {
+    pub mode: AntiAliasing,
+}
+
+// samples per pixel the scene is rendered with, the requested count is lowered until the supersampled
+// images fit in the largest image the device supports
+pub fn sample_count<B: hal::Backend>(factory: &Factory<B>, world: &World) -> u8 {
+    // the debug views show the scene images as they are, without resolving them, so they show a
+    // single sampled scene rather than the supersampled one the game renders
+    if world.try_fetch::<DebugViewSettings>().map_or(false, |s| s.view != DebugView::Off) {
+        return 1;
+    }
+    let requested = world.try_fetch::<AntiAliasingSettings>().map_or(1, |s| s.mode.samples());
+    let render_size = match world.try_fetch::<ScreenDimensions>() {
+        Some(dimensions) => world.try_fetch::<RenderQuality>()
+            .map(|quality| quality.clone())
+            .unwrap_or_default()
+            .render_size(dimensions.width(), dimensions.height()),
+        None => return 1,
+    };
+    let limits = hal::adapter::PhysicalDevice::limits(factory.physical());
+    supported_samples(requested, render_size, limits.max_image_2d_size)
+}
+
+// highest sample count up to the requested one where the supersampled scene fits in max_size
+pub fn supported_samples(requested: u8, render_size: (u32, u32), max_size: u32) -> u8 {
+    let mut samples = requested.max(1);
+    loop {
+        let (width, height) = supersampled_size(render_size, samples);
+        if samples == 1 || (width <= max_size && height <= max_size) {
+            return samples;
+        }
+        samples /= 2;
+    }
+}
+
+// samples along x and y for a sample count, wider than tall when they don't split evenly
+pub fn sample_grid(samples: u8) -> (u32, u32) {
+    match samples {
+        0 | 1 => (1, 1),
+        2 => (2, 1),
+        3 | 4 => (2, 2),
+        _ => (4, 2),
+    }
+}
+
+// size of the scene images for a render size and sample count
+pub fn supersampled_size(render_size: (u32, u32), samples: u8) -> (u32, u32) {
+    let (x, y) = sample_grid(samples);
+    (render_size.0 * x, render_size.1 * y)
+}
+
+// uniform arguments
+/// layout(std140, set = 0, binding = 0) uniform ResolveUniformArgs {
+///    uniform ivec2 grid;
+/// };
+#[derive(Clone, Copy, Debug, AsStd140)]
+#[repr(C, align(4))]
+pub struct ResolveUniformArgs {
+    pub grid: ivec2,
+}
+
+/// Vertex Arguments to pass into shader.
+/// layout(location = 0) out VertexData {
+///    vec2 position;
+///    vec2 tex_coord;
+/// } vertex;
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, AsStd140)]
+#[repr(C, align(4))]
+pub struct ResolveVertexArgs {
+    pub position: vec2,
+    pub tex_coord: vec2,
+}
+
+/// Required to send data into the shader.
+/// These names must match the shader.
+impl AsVertex for ResolveVertexArgs {
+    fn vertex() -> VertexFormat {
+        VertexFormat::new((
+            (Format::Rg32Sfloat, "position"),
+            (Format::Rg32Sfloat, "tex_coord"),
+        ))
+    }
+}
+
+
+// the pipeline itself
+#[derive(Debug, Default)]
+pub struct PipelineDesc;
+
+#[derive(Debug)]
+pub struct Pipeline<B: hal::Backend> {
+    buffer: Escape<Buffer<B>>,
+    sets: Vec<B::DescriptorSet>,
+    descriptor_pool: B::DescriptorPool,
+    image_sampler: Escape<Sampler<B>>,
+    image_view: Escape<ImageView<B>>,
+    vertex_buffer: Escape<Buffer<B>>,
+    settings: Settings,
+    grid: (u32, u32),
+}
+
+// utility to calculte the uniform size and offset including alignment
+#[derive(Debug, PartialEq, Eq)]
+struct Settings {
+    align: u64
+}
+
+impl Settings {
+    const UNIFORM_SIZE:u64 = size_of::<<ResolveUniformArgs as AsStd140>::Std140>() as u64;
+
+    #[inline]
+    fn buffer_frame_size(&self) -> u64 {
+        ((Self::UNIFORM_SIZE - 1) / self.align + 1) * self.align
+    }
+
+    #[inline]
+    fn uniform_offset(&self, index: u64) -> u64 {
+        self.buffer_frame_size() * index as u64
+    }
+}
+
+impl<B> SimpleGraphicsPipelineDesc<B, World> for PipelineDesc
+where B: hal::Backend {
+    type Pipeline = Pipeline<B>;
+
+    fn images(&self) -> Vec<ImageAccess> {
+        vec![ImageAccess {
+            access: hal::image::Access::SHADER_READ,
+            usage: hal::image::Usage::SAMPLED,
+            layout: hal::image::Layout::ShaderReadOnlyOptimal,
+            stages: hal::pso::PipelineStage::FRAGMENT_SHADER,
+        }]
+    }
+
+    fn depth_stencil(&self) -> Option<hal::pso::DepthStencilDesc> {
+        None
+    }
+
+    fn vertices(
+        &self,
+    ) -> Vec<(
+        Vec<hal::pso::Element<hal::format::Format>>,
+        hal::pso::ElemStride,
+        hal::pso::VertexInputRate,
+    )> {
+        vec![
+            ResolveVertexArgs::vertex().gfx_vertex_input_desc(hal::pso::VertexInputRate::Vertex),
+        ]
+    }
+
+    fn load_shader_set(
+        &self,
+        factory: &mut Factory<B>,
+        _world: &World,
+    ) -> rendy::shader::ShaderSet<B> {
+        crate::render::shader::shader_set(factory, "fsquad.vert", "resolve.frag")
+    }
+
+    fn layout(&self) -> Layout {
+        Layout {
+            sets: vec![SetLayout {
+                bindings: vec![
+                    hal::pso::DescriptorSetLayoutBinding {
+                        binding: 0,
+                        ty: hal::pso::DescriptorType::UniformBuffer,
+                        count: 1,
+                        stage_flags: hal::pso::ShaderStageFlags::FRAGMENT,
+                        immutable_samplers: false,
+                    },
+                    hal::pso::DescriptorSetLayoutBinding {
+                        binding: 1,
+                        ty: hal::pso::DescriptorType::CombinedImageSampler,
+                        count: 1,
+                        stage_flags: hal::pso::ShaderStageFlags::FRAGMENT,
+                        immutable_samplers: false,
+                    },
+                ],
+            }],
+            push_constants: Vec::new(),
+        }
+    }
+
+    fn build<'a>(
+        self,
+        ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        _queue: QueueId,
+        world: &World,
+        buffers: Vec<NodeBuffer>,
+        images: Vec<NodeImage>,
+        set_layouts: &[RendyHandle<DescriptorSetLayout<B>>],
+    ) -> Result<Pipeline<B>, failure::Error> {
+        assert!(buffers.is_empty());
+        assert!(images.len() == 1);
+        assert!(set_layouts.len() == 1);
+
+        let align_limit = hal::adapter::PhysicalDevice::limits(factory.physical()).min_uniform_buffer_offset_alignment;
+        let settings = Settings { align:align_limit };
+        let frames = 3;
+
+        let mut descriptor_pool = unsafe {
+            factory.create_descriptor_pool(
+                frames,
+                vec![
+                    hal::pso::DescriptorRangeDesc {
+                        ty: hal::pso::DescriptorType::UniformBuffer,
+                        count: frames,
+                    },
+                    hal::pso::DescriptorRangeDesc {
+                        ty: hal::pso::DescriptorType::CombinedImageSampler,
+                        count: frames,
+                    },
+                ],
+                hal::pso::DescriptorPoolCreateFlags::empty(),
+            )?
+        };
+
+        let image_sampler = factory
+            .create_sampler(SamplerInfo {
+                min_filter:Nearest,
+                mag_filter:Nearest,
+                mip_filter:Nearest,
+                wrap_mode:(WrapMode::Clamp,WrapMode::Clamp,WrapMode::Clamp),
+                lod_bias:hal::image::Lod::ZERO,
+                lod_range:hal::image::Lod::ZERO .. hal::image::Lod::MAX,
+                comparison:None,
+                border:[0.0,0.0,0.0,0.0].into(),
+                normalized:true,
+                anisotropic:hal::image::Anisotropic::Off
+            })
+            .unwrap();
+
+        let image_handle = ctx
+            .get_image(images[0].id)
+            .expect("Input image missing");
+
+        let image_view = factory
+            .create_image_view(
+                image_handle.clone(),
+                ImageViewInfo {
+                    view_kind: resource::ViewKind::D2,
+                    format: image_handle.format(),
+                    swizzle: hal::format::Swizzle::NO,
+                    range: images[0].range.clone(),
+                },
+            )
+            .expect("Could not create input image view");
+
+        let buffer = factory
+            .create_buffer(
+                BufferInfo {
+                    size: settings.buffer_frame_size() * frames as u64,
+                    usage: hal::buffer::Usage::UNIFORM,
+                },
+                rendy::memory::MemoryUsageValue::Dynamic,
+            )
+            .unwrap();
+
+        let mut sets = Vec::with_capacity(frames);
+        for index in 0..frames {
+            unsafe {
+                let set = descriptor_pool.allocate_set(&set_layouts[0].raw()).unwrap();
+                factory.write_descriptor_sets(vec![
+                    hal::pso::DescriptorSetWrite {
+                        set: &set,
+                        binding: 0,
+                        array_offset: 0,
+                        descriptors: Some(hal::pso::Descriptor::Buffer(
+                            buffer.raw(),
+                            Some(settings.uniform_offset(index as u64))
+                            ..Some(
+                                settings.uniform_offset(index as u64) + Settings::UNIFORM_SIZE,
+                            ),
+                        )),
+                    },
+                    hal::pso::DescriptorSetWrite {
+                        set: &set,
+                        binding: 1,
+                        array_offset: 0,
+                        descriptors: Some(hal::pso::Descriptor::CombinedImageSampler(
+                            image_view.raw(),
+                            hal::image::Layout::ShaderReadOnlyOptimal,
+                            image_sampler.raw()
+                        )),
+                    }
+                ]);
+                sets.push(set);
+            }
+        }
+
+        // create a static vertex buffer
+        let vbuf_size = ResolveVertexArgs::vertex().stride as u64 * 6;
+        let mut vertex_buffer = factory.create_buffer(
+            BufferInfo {
+                size: vbuf_size,
+                usage: hal::buffer::Usage::VERTEX
+            },
+            memory::Dynamic,
+        ).unwrap();
+        unsafe {
+            factory
+                .upload_visible_buffer(
+                    &mut vertex_buffer,
+                    0,
+                    &[
+                        ResolveVertexArgs { position:[-1f32,1f32].into(), tex_coord:[0f32,1f32].into() },
+                        ResolveVertexArgs { position:[1f32,-1f32].into(), tex_coord:[1f32,0f32].into() },
+                        ResolveVertexArgs { position:[-1f32,-1f32].into(), tex_coord:[0f32,0f32].into() },
+                        ResolveVertexArgs { position:[1f32,-1f32].into(), tex_coord:[1f32,0f32].into() },
+                        ResolveVertexArgs { position:[-1f32,1f32].into(), tex_coord:[0f32,1f32].into() },
+                        ResolveVertexArgs { position:[1f32,1f32].into(), tex_coord:[1f32,1f32].into() },
+                    ],
+                )
+                .unwrap();
+        }
+
+        Ok(Pipeline {
+            grid: sample_grid(sample_count(factory, world)),
+            buffer,
+            sets,
+            image_view,
+            image_sampler,
+            descriptor_pool,
+            settings,
+            vertex_buffer,
+        })
+    }
+}
+
+impl<B> SimpleGraphicsPipeline<B, World> for Pipeline<B>
+where
+    B: hal::Backend,
+{
+    type Desc = PipelineDesc;
+
+    fn prepare(
+        &mut self,
+        factory: &Factory<B>,
+        _queue: QueueId,
+        _set_layouts: &[RendyHandle<DescriptorSetLayout<B>>],
+        index: usize,
+        _world: &World,
+    ) -> PrepareResult {
+        // write to the uniform
+        unsafe {
+            factory
+                .upload_visible_buffer(
+                    &mut self.buffer,
+                    self.settings.uniform_offset(index as u64),
+                    &[ResolveUniformArgs {
+                        grid: [self.grid.0 as i32, self.grid.1 as i32].into(),
+                    }.std140()],
+                )
+                .unwrap()
+        };
+
+        PrepareResult::DrawRecord
+    }
+
+    fn draw(
+        &mut self,
+        layout: &B::PipelineLayout,
+        mut encoder: RenderPassEncoder<'_, B>,
+        index: usize,
+        _world: &World,
+    ) {
+        unsafe {
+            encoder.bind_graphics_descriptor_sets(
+                layout,
+                0,
+                Some(&self.sets[index]),
+                std::iter::empty(),
+            );
+
+            encoder.bind_vertex_buffers(0, Some((self.vertex_buffer.raw(), 0)));
+
+            encoder.draw(0..6, 0..1);
+        }
+    }
+
+    fn dispose(mut self, factory: &mut Factory<B>, _world: &World) {
+        unsafe {
+            self.descriptor_pool.reset();
+            factory.destroy_descriptor_pool(self.descriptor_pool);
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_grid_has_the_sample_count() {
+        for samples in [1u8, 2, 4, 8].iter() {
+            let (x, y) = sample_grid(*samples);
+            assert_eq!(x * y, *samples as u32);
+        }
+    }
+
+    #[test]
+    fn default_renders_one_sample() {
+        assert_eq!(AntiAliasing::default(), AntiAliasing::Fxaa);
+        assert_eq!(AntiAliasing::default().samples(), 1);
+    }
+
+    #[test]
+    fn previous_undoes_next() {
+        let mut mode = AntiAliasing::Off;
+        for _ in 0..7 {
+            assert_eq!(mode.next().previous(), mode);
+            mode = mode.next();
+        }
+        assert_eq!(mode, AntiAliasing::Off);
+    }
+
+    #[test]
+    fn supported_samples_fit_the_largest_image() {
+        assert_eq!(supported_samples(8, (1920, 1080), 16384), 8);
+        assert_eq!(supported_samples(8, (3840, 2160), 8192), 4);
+        assert_eq!(supported_samples(4, (3840, 2160), 4096), 1);
+        assert_eq!(supported_samples(0, (1920, 1080), 16384), 1);
+    }
+
+    #[test]
+    fn uniform_matches_the_shader() {
+        assert_uniform_layout!(ResolveUniformArgs, "resolve.frag", Settings::UNIFORM_SIZE, [grid]);
+    }
+}
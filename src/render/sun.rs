@@ -15,13 +15,14 @@ use amethyst::core::{
     transform::components::Parent,
 };
 use amethyst::{
-    ecs::{NullStorage, World},
+    ecs::{NullStorage, World, WorldExt},
     ecs::prelude::{ Join, Component, SystemData, ReadStorage, Read },
     assets::{AssetStorage, Handle},
     error::Error,
     utils::tag::{Tag},
 };
 use derivative::Derivative;
+use crate::render::depth;
 use rendy::{
     command::{QueueId, RenderPassEncoder},
     hal::{self, device::Device, pso, pso::ShaderStageFlags},
@@ -32,7 +33,6 @@ use rendy::{
     mesh::{
         VertexFormat, TexCoord, Tangent, Position, Normal, AsVertex
     },
-    shader::{Shader, SpirvShader},
 };
 
 #[derive(Clone, Default)]
@@ -72,21 +72,6 @@ impl<B: Backend> RenderPlugin<B> for RenderSun {
     }
 }
 
-// load our shader
-lazy_static::lazy_static! {
-    static ref VERTEX:SpirvShader = SpirvShader::from_bytes(
-        include_bytes!("../../assets/shader/sun.vert.spv"),
-        ShaderStageFlags::VERTEX,
-        "main",
-    ).unwrap();
-
-    static ref FRAGMENT:SpirvShader = SpirvShader::from_bytes(
-        include_bytes!("../../assets/shader/sun.frag.spv"),
-        ShaderStageFlags::FRAGMENT,
-        "main",
-    ).unwrap();
-}
-
 // plugin desc
 #[derive(Clone, PartialEq, Derivative)]
 #[derivative(Debug(bound = ""), Default(bound = ""))]
@@ -123,8 +108,22 @@ fn build_custom_pipeline<B: Backend>(
         .collect::<Vec<_>>();
 
     // get shaders
-    let shader_vertex = unsafe { VERTEX.module(factory).unwrap() };
-    let shader_fragment = unsafe { FRAGMENT.module(factory).unwrap() };
+    let shaders = crate::render::shader::module(factory, "sun.vert").and_then(|vertex| {
+        match crate::render::shader::module(factory, "sun.frag") {
+            Ok(fragment) => Ok((vertex, fragment)),
+            Err(e) => {
+                unsafe { factory.destroy_shader_module(vertex); }
+                Err(e)
+            },
+        }
+    });
+    let (shader_vertex, shader_fragment) = match shaders {
+        Ok(shaders) => shaders,
+        Err(e) => {
+            unsafe { factory.device().destroy_pipeline_layout(pipeline_layout); }
+            return Err(e);
+        },
+    };
 
     // build the pipeline
     let pipes = PipelinesBuilder::new()
@@ -140,7 +139,7 @@ fn build_custom_pipeline<B: Backend>(
                 .with_framebuffer_size(framebuffer_width, framebuffer_height)
                 .with_face_culling(pso::Face::BACK)
                 .with_depth_test(pso::DepthTest {
-                    fun: pso::Comparison::Less,
+                    fun: depth::COMPARISON,
                     write: true,
                 })
                 .with_blend_targets(vec![pso::ColorBlendDesc {
@@ -174,7 +173,7 @@ impl<B: Backend> RenderGroupDesc<B, World> for DrawSunDesc {
         _ctx: &GraphContext<B>,
         factory: &mut Factory<B>,
         _queue: QueueId,
-        _aux: &World,
+        aux: &World,
         framebuffer_width: u32,
         framebuffer_height: u32,
         subpass: hal::pass::Subpass<'_, B>,
@@ -0,0 +1,659 @@
+// temporal anti-aliasing render pipeline, blends the jittered scene with the reprojected history
+
+use amethyst::{
+    core::{
+        math::{Matrix4, Vector2, Vector3},
+        timing::Time,
+        transform::Transform,
+    },
+    derive::SystemDesc,
+    ecs::{World, Entities, Entity, Join, Read, ReadExpect, ReadStorage, System, SystemData, Write, WriteStorage},
+    prelude::*,
+    renderer::{camera::{Camera, Projection}, ActiveCamera},
+    window::ScreenDimensions,
+};
+use rendy::{
+    command::{QueueId, RenderPassEncoder },
+    hal::{
+        self, 
+        device::Device, pso::DescriptorPool,
+        format::Format, image::Filter::Linear, image::WrapMode 
+    },
+    graph::{
+        render::{
+            PrepareResult,
+            SimpleGraphicsPipelineDesc,
+            SimpleGraphicsPipeline,
+            Layout, SetLayout
+        },
+        GraphContext, NodeBuffer, NodeImage, ImageAccess,
+    },
+    mesh::{
+        VertexFormat, AsVertex
+    },
+    memory,
+    resource::{ 
+        self,Escape,BufferInfo,Buffer,
+        Handle as RendyHandle,DescriptorSetLayout,
+        ImageViewInfo,SamplerInfo,ImageView,Sampler,
+    },
+    factory::{Factory},
+};
+use glsl_layout::*;
+use std::mem::size_of;
+use crate::render::camera::active_camera;
+use crate::render::debug_view::{DebugView, DebugViewSettings};
+use crate::render::ssaa::{AntiAliasing, AntiAliasingSettings};
+use crate::origin::FloatingOrigin;
+use crate::render::quality::RenderQuality;
+
+// taa tuning
+pub struct TaaSettings {
+    // weight of the history, higher is smoother but ghosts longer
+    pub feedback: f32,
+    // number of jitter positions before the sequence repeats
+    pub jitter_samples: u32,
+}
+
+impl Default for TaaSettings {
+    fn default() -> Self {
+        Self { feedback:0.9, jitter_samples:8 }
+    }
+}
+
+// per frame taa state, written by the jitter system and read by the pass
+pub struct TaaFrame {
+    // projection jitter of this frame in normalized device coordinates
+    pub jitter: Vector2<f32>,
+    // maps this frame's unjittered clip space to the previous frame's
+    pub reprojection: Matrix4<f32>,
+}
+
+impl Default for TaaFrame {
+    fn default() -> Self {
+        Self { jitter:Vector2::zeros(), reprojection:Matrix4::identity() }
+    }
+}
+
+// element of the halton low discrepancy sequence, in 0..1
+pub fn halton(mut index: u32, base: u32) -> f32 {
+    let mut fraction = 1.0;
+    let mut result = 0.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+// sub-pixel projection offset for a frame in normalized device coordinates, halton(2, 3) centred on
+// the pixel so every position stays within half a pixel
+pub fn jitter(frame: u64, samples: u32, width: f32, height: f32) -> Vector2<f32> {
+    // halton starts at 1, index 0 would always be the pixel corner
+    let index = (frame % samples.max(1) as u64) as u32 + 1;
+    Vector2::new(
+        (halton(index, 2) - 0.5) * 2.0 / width,
+        (halton(index, 3) - 0.5) * 2.0 / height,
+    )
+}
+
+// projection with the jitter applied, a shear that offsets every projected point by the same ndc amount
+pub fn jittered_projection(projection: &Matrix4<f32>, jitter: &Vector2<f32>) -> Matrix4<f32> {
+    let mut jittered = *projection;
+    // clip w is -z, so offsetting x/w by the jitter means adding jitter * -z to x
+    jittered[(0, 2)] = -jitter.x;
+    jittered[(1, 2)] = -jitter.y;
+    jittered
+}
+
+// matrix taking this frame's clip space back to the previous frame's, origin_shift is how far the
+// floating origin moved since then, as positions are relative to it
+pub fn reprojection(
+    previous_view_projection: &Matrix4<f32>,
+    view_projection: &Matrix4<f32>,
+    origin_shift: &Vector3<f32>,
+) -> Matrix4<f32> {
+    let inverse = view_projection.try_inverse().unwrap_or_else(Matrix4::identity);
+    previous_view_projection * Matrix4::new_translation(origin_shift) * inverse
+}
+
+// jitters the camera projection while taa is active and tracks the previous view projection
+#[derive(SystemDesc)]
+#[system_desc(name(TaaJitterSystemDesc))]
+pub struct TaaJitterSystem {
+    // camera the history was rendered from, its view projection and the origin at the time
+    #[system_desc(skip)]
+    previous: Option<(Entity, Matrix4<f32>, Vector3<f64>)>,
+}
+
+impl TaaJitterSystem {
+    pub fn new() -> Self {
+        Self { previous:None }
+    }
+}
+
+impl<'s> System<'s> for TaaJitterSystem {
+    type SystemData = (
+        Read<'s, AntiAliasingSettings>,
+        Read<'s, DebugViewSettings>,
+        Read<'s, TaaSettings>,
+        Read<'s, Time>,
+        Read<'s, FloatingOrigin>,
+        Read<'s, RenderQuality>,
+        ReadExpect<'s, ScreenDimensions>,
+        Write<'s, TaaFrame>,
+        Read<'s, ActiveCamera>,
+        Entities<'s>,
+        ReadStorage<'s, Transform>,
+        WriteStorage<'s, Camera>,
+    );
+
+    fn run(&mut self, (anti_aliasing, debug_view, settings, time, origin, quality, dimensions, mut frame, active_camera_entity, entities, transforms, mut cameras) : Self::SystemData) {
+        // the debug views leave taa out of the graph, so they show the scene without jitter
+        let active = anti_aliasing.mode == AntiAliasing::Taa && debug_view.view == DebugView::Off;
+        let jitter = if active {
+            // a sub-pixel offset of the scaled scene images
+            let (width, height) = quality.render_size(dimensions.width(), dimensions.height());
+            jitter(time.frame_number(), settings.jitter_samples, width as f32, height as f32)
+        } else {
+            Vector2::zeros()
+        };
+
+        // only the camera that's rendered, the others keep whatever jitter they had
+        let rendered = active_camera(&active_camera_entity, &entities, &cameras);
+        for (entity, camera, transform) in (&entities, &mut cameras, &transforms).join() {
+            if Some(entity) != rendered {
+                continue;
+            }
+            if let Projection::Perspective(perspective) = camera.projection_mut() {
+                let projection = jittered_projection(perspective.as_matrix(), &Vector2::zeros());
+                *perspective.as_matrix_mut() = jittered_projection(&projection, &jitter);
+
+                let view = transform.global_matrix().try_inverse().unwrap_or_else(Matrix4::identity);
+                let view_projection = projection * view;
+                // starting over when the rendered camera switched
+                let (previous_view_projection, previous_origin) = match self.previous {
+                    Some((previous, view_projection, origin)) if previous == entity => (view_projection, origin),
+                    _ => (view_projection, origin.origin),
+                };
+                let origin_shift = (origin.origin - previous_origin).map(|v| v as f32);
+                frame.jitter = jitter;
+                frame.reprojection = reprojection(&previous_view_projection, &view_projection, &origin_shift);
+                self.previous = Some((entity, view_projection, origin.origin));
+            }
+        }
+    }
+}
+
+// uniform arguments
+/// layout(std140, set = 0, binding = 0) uniform TaaUniformArgs {
+///    uniform mat4 reprojection;
+///    uniform vec2 jitter;
+///    uniform float screen_width;
+///    uniform float screen_height;
+///    uniform float feedback;
+///    uniform bool reset;
+/// };
+#[derive(Clone, Copy, Debug, AsStd140)]
+#[repr(C, align(4))]
+pub struct TaaUniformArgs {
+    pub reprojection: mat4,
+    pub jitter: vec2,
+    pub screen_width: float,
+    pub screen_height: float,
+    pub feedback: float,
+    pub reset: boolean,
+}
+
+/// Vertex Arguments to pass into shader.
+/// layout(location = 0) out VertexData {
+///    vec2 position;
+///    vec2 tex_coord;
+/// } vertex;
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, AsStd140)]
+#[repr(C, align(4))]
+pub struct TaaVertexArgs {
+    pub position: vec2,
+    pub tex_coord: vec2,
+}
+
+/// Required to send data into the shader.
+/// These names must match the shader.
+impl AsVertex for TaaVertexArgs {
+    fn vertex() -> VertexFormat {
+        VertexFormat::new((
+            (Format::Rg32Sfloat, "position"),
+            (Format::Rg32Sfloat, "tex_coord"),
+        ))
+    }
+}
+
+
+// the pipeline itself
+#[derive(Debug, Default)]
+pub struct PipelineDesc;
+
+#[derive(Debug)]
+pub struct Pipeline<B: hal::Backend> {
+    buffer: Escape<Buffer<B>>,
+    sets: Vec<B::DescriptorSet>,
+    descriptor_pool: B::DescriptorPool,
+    image_sampler: Escape<Sampler<B>>,
+    image_views: Vec<Escape<ImageView<B>>>,
+    vertex_buffer: Escape<Buffer<B>>,
+    settings: Settings,
+    // the history is garbage until the first frame after a rebuild wrote it
+    history_valid: bool,
+}
+
+// utility to calculte the uniform size and offset including alignment
+#[derive(Debug, PartialEq, Eq)]
+struct Settings {
+    align: u64
+}
+
+impl Settings {
+    const UNIFORM_SIZE:u64 = size_of::<<TaaUniformArgs as AsStd140>::Std140>() as u64;
+
+    #[inline]
+    fn buffer_frame_size(&self) -> u64 {
+        ((Self::UNIFORM_SIZE - 1) / self.align + 1) * self.align
+    }
+
+    #[inline]
+    fn uniform_offset(&self, index: u64) -> u64 {
+        self.buffer_frame_size() * index as u64
+    }
+}
+
+impl<B> SimpleGraphicsPipelineDesc<B, World> for PipelineDesc
+where B: hal::Backend {
+    type Pipeline = Pipeline<B>;
+
+    // current hdr, depth and history images
+    fn images(&self) -> Vec<ImageAccess> {
+        vec![
+            ImageAccess {
+                access: hal::image::Access::SHADER_READ,
+                usage: hal::image::Usage::SAMPLED,
+                layout: hal::image::Layout::ShaderReadOnlyOptimal,
+                stages: hal::pso::PipelineStage::FRAGMENT_SHADER,
+            },
+            ImageAccess {
+                access: hal::image::Access::SHADER_READ,
+                usage: hal::image::Usage::SAMPLED,
+                layout: hal::image::Layout::DepthStencilReadOnlyOptimal,
+                stages: hal::pso::PipelineStage::FRAGMENT_SHADER,
+            },
+            ImageAccess {
+                access: hal::image::Access::SHADER_READ,
+                usage: hal::image::Usage::SAMPLED,
+                layout: hal::image::Layout::ShaderReadOnlyOptimal,
+                stages: hal::pso::PipelineStage::FRAGMENT_SHADER,
+            },
+        ]
+    }
+
+    fn depth_stencil(&self) -> Option<hal::pso::DepthStencilDesc> {
+        None
+    }
+
+    fn vertices(
+        &self,
+    ) -> Vec<(
+        Vec<hal::pso::Element<hal::format::Format>>,
+        hal::pso::ElemStride,
+        hal::pso::VertexInputRate,
+    )> {
+        vec![
+            TaaVertexArgs::vertex().gfx_vertex_input_desc(hal::pso::VertexInputRate::Vertex),
+        ]
+    }
+
+    fn load_shader_set(
+        &self,
+        factory: &mut Factory<B>,
+        _world: &World,
+    ) -> rendy::shader::ShaderSet<B> {
+        crate::render::shader::shader_set(factory, "fsquad.vert", "taa.frag")
+    }
+
+    fn layout(&self) -> Layout {
+        Layout {
+            sets: vec![SetLayout {
+                bindings: vec![
+                    hal::pso::DescriptorSetLayoutBinding {
+                        binding: 0,
+                        ty: hal::pso::DescriptorType::UniformBuffer,
+                        count: 1,
+                        stage_flags: hal::pso::ShaderStageFlags::FRAGMENT,
+                        immutable_samplers: false,
+                    },
+                    hal::pso::DescriptorSetLayoutBinding {
+                        binding: 1,
+                        ty: hal::pso::DescriptorType::CombinedImageSampler,
+                        count: 3,
+                        stage_flags: hal::pso::ShaderStageFlags::FRAGMENT,
+                        immutable_samplers: false,
+                    },
+                ],
+            }],
+            push_constants: Vec::new(),
+        }
+    }
+
+    fn build<'a>(
+        self,
+        ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        _queue: QueueId,
+        _world: &World,
+        buffers: Vec<NodeBuffer>,
+        images: Vec<NodeImage>,
+        set_layouts: &[RendyHandle<DescriptorSetLayout<B>>],
+    ) -> Result<Pipeline<B>, failure::Error> {
+        assert!(buffers.is_empty());
+        assert!(images.len() == 3);
+        assert!(set_layouts.len() == 1);
+
+        let align_limit = hal::adapter::PhysicalDevice::limits(factory.physical()).min_uniform_buffer_offset_alignment;
+        let settings = Settings { align:align_limit };
+        let frames = 3;
+
+        let mut descriptor_pool = unsafe {
+            factory.create_descriptor_pool(
+                frames,
+                vec![
+                    hal::pso::DescriptorRangeDesc {
+                        ty: hal::pso::DescriptorType::UniformBuffer,
+                        count: frames,
+                    },
+                    hal::pso::DescriptorRangeDesc {
+                        ty: hal::pso::DescriptorType::CombinedImageSampler,
+                        count: frames * 3,
+                    },
+                ],
+                hal::pso::DescriptorPoolCreateFlags::empty(),
+            )?
+        };
+
+        let image_sampler = factory
+            .create_sampler(SamplerInfo {
+                min_filter:Linear,
+                mag_filter:Linear,
+                mip_filter:Linear,
+                wrap_mode:(WrapMode::Clamp,WrapMode::Clamp,WrapMode::Clamp),
+                lod_bias:hal::image::Lod::ZERO,
+                lod_range:hal::image::Lod::ZERO .. hal::image::Lod::MAX,
+                comparison:None,
+                border:[0.0,0.0,0.0,0.0].into(),
+                normalized:true,
+                anisotropic:hal::image::Anisotropic::Off
+            })
+            .unwrap();
+
+        let image_views = images.iter().map(|image| {
+            let image_handle = ctx
+                .get_image(image.id)
+                .expect("Input image missing");
+            factory
+                .create_image_view(
+                    image_handle.clone(),
+                    ImageViewInfo {
+                        view_kind: resource::ViewKind::D2,
+                        format: image_handle.format(),
+                        swizzle: hal::format::Swizzle::NO,
+                        range: image.range.clone(),
+                    },
+                )
+                .expect("Could not create input image view")
+        }).collect::<Vec<_>>();
+
+        let buffer = factory
+            .create_buffer(
+                BufferInfo {
+                    size: settings.buffer_frame_size() * frames as u64,
+                    usage: hal::buffer::Usage::UNIFORM,
+                },
+                rendy::memory::MemoryUsageValue::Dynamic,
+            )
+            .unwrap();
+
+        let mut sets = Vec::with_capacity(frames);
+        for index in 0..frames {
+            unsafe {
+                let set = descriptor_pool.allocate_set(&set_layouts[0].raw()).unwrap();
+                factory.write_descriptor_sets(vec![
+                    hal::pso::DescriptorSetWrite {
+                        set: &set,
+                        binding: 0,
+                        array_offset: 0,
+                        descriptors: Some(hal::pso::Descriptor::Buffer(
+                            buffer.raw(),
+                            Some(settings.uniform_offset(index as u64))
+                            ..Some(
+                                settings.uniform_offset(index as u64) + Settings::UNIFORM_SIZE,
+                            ),
+                        )),
+                    },
+                    hal::pso::DescriptorSetWrite {
+                        set: &set,
+                        binding: 1,
+                        array_offset: 0,
+                        descriptors: image_views.iter().zip(images.iter()).map(|(view, image)| {
+                            hal::pso::Descriptor::CombinedImageSampler(
+                                view.raw(),
+                                image.layout,
+                                image_sampler.raw()
+                            )
+                        }).collect::<Vec<_>>(),
+                    }
+                ]);
+                sets.push(set);
+            }
+        }
+
+        // create a static vertex buffer
+        let vbuf_size = TaaVertexArgs::vertex().stride as u64 * 6;
+        let mut vertex_buffer = factory.create_buffer(
+            BufferInfo {
+                size: vbuf_size,
+                usage: hal::buffer::Usage::VERTEX
+            },
+            memory::Dynamic,
+        ).unwrap();
+        unsafe {
+            factory
+                .upload_visible_buffer(
+                    &mut vertex_buffer,
+                    0,
+                    &[
+                        TaaVertexArgs { position:[-1f32,1f32].into(), tex_coord:[0f32,1f32].into() },
+                        TaaVertexArgs { position:[1f32,-1f32].into(), tex_coord:[1f32,0f32].into() },
+                        TaaVertexArgs { position:[-1f32,-1f32].into(), tex_coord:[0f32,0f32].into() },
+                        TaaVertexArgs { position:[1f32,-1f32].into(), tex_coord:[1f32,0f32].into() },
+                        TaaVertexArgs { position:[-1f32,1f32].into(), tex_coord:[0f32,1f32].into() },
+                        TaaVertexArgs { position:[1f32,1f32].into(), tex_coord:[1f32,1f32].into() },
+                    ],
+                )
+                .unwrap();
+        }
+
+        Ok(Pipeline {
+            buffer,
+            sets,
+            image_views,
+            image_sampler,
+            descriptor_pool,
+            settings,
+            vertex_buffer,
+            history_valid: false,
+        })
+    }
+}
+
+impl<B> SimpleGraphicsPipeline<B, World> for Pipeline<B>
+where
+    B: hal::Backend,
+{
+    type Desc = PipelineDesc;
+
+    fn prepare(
+        &mut self,
+        factory: &Factory<B>,
+        _queue: QueueId,
+        _set_layouts: &[RendyHandle<DescriptorSetLayout<B>>],
+        index: usize,
+        world: &World,
+    ) -> PrepareResult {
+        let dimensions = world.read_resource::<ScreenDimensions>();
+        let (width, height) = world.read_resource::<RenderQuality>()
+            .render_size(dimensions.width(), dimensions.height());
+        let settings = world.read_resource::<TaaSettings>();
+        let frame = world.read_resource::<TaaFrame>();
+        let reprojection: [[f32; 4]; 4] = frame.reprojection.into();
+        let jitter: [f32; 2] = frame.jitter.into();
+
+        // write to the uniform
+        unsafe {
+            factory
+                .upload_visible_buffer(
+                    &mut self.buffer,
+                    self.settings.uniform_offset(index as u64),
+                    &[TaaUniformArgs {
+                        reprojection: reprojection.into(),
+                        jitter: jitter.into(),
+                        screen_width: width as f32,
+                        screen_height: height as f32,
+                        feedback: settings.feedback,
+                        reset: (!self.history_valid).into(),
+                    }.std140()],
+                )
+                .unwrap()
+        };
+        self.history_valid = true;
+
+        PrepareResult::DrawRecord
+    }
+
+    fn draw(
+        &mut self,
+        layout: &B::PipelineLayout,
+        mut encoder: RenderPassEncoder<'_, B>,
+        index: usize,
+        _world: &World,
+    ) {
+        unsafe {
+            encoder.bind_graphics_descriptor_sets(
+                layout,
+                0,
+                Some(&self.sets[index]),
+                std::iter::empty(),
+            );
+
+            encoder.bind_vertex_buffers(0, Some((self.vertex_buffer.raw(), 0)));
+
+            encoder.draw(0..6, 0..1);
+        }
+    }
+
+    fn dispose(mut self, factory: &mut Factory<B>, _world: &World) {
+        unsafe {
+            self.descriptor_pool.reset();
+            factory.destroy_descriptor_pool(self.descriptor_pool);
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst::core::math::{Isometry3, Perspective3, Point3, Vector4};
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+    }
+
+    fn view_projection() -> Matrix4<f32> {
+        let projection = Perspective3::new(16.0 / 9.0, 1.0, 0.1, 1000.0).to_homogeneous();
+        let view = Isometry3::look_at_rh(&Point3::new(1.0, 2.0, 5.0), &Point3::origin(), &Vector3::y());
+        projection * view.to_homogeneous()
+    }
+
+    #[test]
+    fn halton_2_3() {
+        let base2 = [0.5, 0.25, 0.75, 0.125, 0.625];
+        let base3 = [1.0 / 3.0, 2.0 / 3.0, 1.0 / 9.0, 4.0 / 9.0, 7.0 / 9.0];
+        for index in 0..5 {
+            assert_close(halton(index as u32 + 1, 2), base2[index]);
+            assert_close(halton(index as u32 + 1, 3), base3[index]);
+        }
+        assert_eq!(halton(0, 2), 0.0);
+    }
+
+    #[test]
+    fn jitter_stays_within_half_a_pixel() {
+        let (width, height) = (1280.0, 720.0);
+        for frame in 0..64 {
+            let jitter = jitter(frame, 8, width, height);
+            // ndc spans 2 over the width and height
+            let (x, y) = (jitter.x * width / 2.0, jitter.y * height / 2.0);
+            assert!(x.abs() <= 0.5 && y.abs() <= 0.5, "frame {} is {} {} pixels off", frame, x, y);
+        }
+        // the sequence repeats after the sample count
+        assert_eq!(jitter(3, 8, width, height), jitter(11, 8, width, height));
+    }
+
+    #[test]
+    fn jitter_only_changes_the_third_column() {
+        let projection = Perspective3::new(16.0 / 9.0, 1.0, 0.1, 1000.0).to_homogeneous();
+        let jitter = Vector2::new(0.001, -0.002);
+        let jittered = jittered_projection(&projection, &jitter);
+        for row in 0..4 {
+            for column in 0..4 {
+                if column != 2 {
+                    assert_eq!(jittered[(row, column)], projection[(row, column)]);
+                }
+            }
+        }
+        assert_ne!(jittered.column(2), projection.column(2));
+
+        // and every point lands the jitter away from where it was in ndc
+        let point = Vector4::new(0.3, -0.2, -4.0, 1.0);
+        let (clip, jittered_clip) = (projection * point, jittered * point);
+        assert_close(jittered_clip.x / jittered_clip.w - clip.x / clip.w, jitter.x);
+        assert_close(jittered_clip.y / jittered_clip.w - clip.y / clip.w, jitter.y);
+    }
+
+    #[test]
+    fn unchanged_view_projection_reprojects_to_the_same_uv() {
+        let view_projection = view_projection();
+        let reprojection = reprojection(&view_projection, &view_projection, &Vector3::zeros());
+        for (x, y, depth) in [(0.0, 0.0, 0.5), (-0.9, 0.7, 0.99), (0.25, -0.5, 0.1)].iter() {
+            let previous = reprojection * Vector4::new(*x, *y, *depth, 1.0);
+            let uv = (previous.x / previous.w * 0.5 + 0.5, previous.y / previous.w * 0.5 + 0.5);
+            assert_close(uv.0, x * 0.5 + 0.5);
+            assert_close(uv.1, y * 0.5 + 0.5);
+        }
+    }
+
+    #[test]
+    fn origin_shift_is_undone_by_the_reprojection() {
+        // the camera and point stay put in the world, only the floating origin moved between the frames
+        let view_projection = view_projection();
+        let shift = Vector3::new(10.0, 0.0, -3.0);
+        let previous_view_projection = view_projection * Matrix4::new_translation(&-shift);
+        let reprojection = reprojection(&previous_view_projection, &view_projection, &shift);
+        let point = view_projection * Vector4::new(0.5, 0.25, -1.0, 1.0);
+        let previous = reprojection * point;
+        assert_close(previous.x / previous.w, point.x / point.w);
+        assert_close(previous.y / previous.w, point.y / point.w);
+    }
+
+    #[test]
+    fn uniform_matches_the_shader() {
+        assert_uniform_layout!(TaaUniformArgs, "taa.frag", Settings::UNIFORM_SIZE, [
+            reprojection, jitter, screen_width, screen_height, feedback, reset
+        ]);
+    }
+}
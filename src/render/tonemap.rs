@@ -1,14 +1,15 @@
 // tonemapping render pipeline
 use amethyst::{
+    core::Time,
     ecs::{World},
     prelude::*,
-    window::ScreenDimensions,
+    utils::application_root_dir,
 };
 use rendy::{
     command::{QueueId, RenderPassEncoder },
     hal::{
         self, 
-        device::Device, pso::ShaderStageFlags, pso::DescriptorPool,
+        device::Device, pso::DescriptorPool,
         format::Format, image::Filter::Linear, image::WrapMode 
     },
     graph::{
@@ -23,50 +24,123 @@ use rendy::{
     mesh::{
         VertexFormat, AsVertex
     },
-    shader::{SpirvShader},
     memory,
     resource::{ 
         self,Escape,BufferInfo,Buffer,
         Handle as RendyHandle,DescriptorSetLayout,
         ImageViewInfo,SamplerInfo,ImageView,Sampler,
     },
-    factory::{Factory},
+    factory::{Factory, ImageState},
+    texture::{Texture, TextureBuilder},
 };
 use glsl_layout::*;
+use serde::{Deserialize, Serialize};
 use std::mem::size_of;
+use crate::render::cube::CubeLut;
 
-// tonemapping settings resource
-#[derive(Default)]
+// tonemapping settings resource, loaded from config/graphics.ron
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
 pub struct TonemapSettings {
     pub enabled: bool,
     pub exposure: f32,
 }
 
-// shader pair
-lazy_static::lazy_static! {
-    static ref VERTEX:SpirvShader = SpirvShader::from_bytes(
-        include_bytes!("../../assets/shader/fsquad.vert.spv"),
-        ShaderStageFlags::VERTEX,
-        "main",
-    ).unwrap();
-
-    static ref FRAGMENT:SpirvShader = SpirvShader::from_bytes(
-        include_bytes!("../../assets/shader/tonemap.frag.spv"),
-        ShaderStageFlags::FRAGMENT,
-        "main",
-    ).unwrap();
-
-    static ref SHADERS: rendy::shader::ShaderSetBuilder = rendy::shader::ShaderSetBuilder::default()
-        .with_vertex(&*VERTEX).unwrap()
-        .with_fragment(&*FRAGMENT).unwrap();
+impl Default for TonemapSettings {
+    fn default() -> Self {
+        Self { enabled:true, exposure:1.0 }
+    }
+}
+
+// colour grading settings resource, applied as part of the tonemapping pass so the grade works on
+// the full precision image before it's written to 8 bits
+pub struct ColorGradingSettings {
+    pub enabled: bool,
+    // .cube file in the assets folder, changing it rebuilds the render graph
+    pub lut: Option<String>,
+    pub lut_strength: f32,
+    // negative is cooler, positive is warmer
+    pub temperature: f32,
+    // negative is greener, positive is more magenta
+    pub tint: f32,
+    pub contrast: f32,
+    pub saturation: f32,
+    pub lift: [f32; 3],
+    pub gamma: [f32; 3],
+    pub gain: [f32; 3],
+    pub dither: bool,
 }
 
+impl Default for ColorGradingSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            lut: None,
+            lut_strength: 1.0,
+            temperature: 0.0,
+            tint: 0.0,
+            contrast: 1.0,
+            saturation: 1.0,
+            lift: [0.0; 3],
+            gamma: [1.0; 3],
+            gain: [1.0; 3],
+            dither: true,
+        }
+    }
+}
+
+impl ColorGradingSettings {
+    // per channel multipliers for the temperature and tint, normalised to keep the luminance
+    pub fn white_balance(&self) -> [f32; 3] {
+        let r = 1.0 + 0.2 * self.temperature;
+        let g = 1.0 - 0.2 * self.tint;
+        let b = 1.0 - 0.2 * self.temperature;
+        let luma = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        [r / luma, g / luma, b / luma]
+    }
+
+    // load the configured lut, falls back to an identity lut when there's none or it fails to load
+    pub fn load_lut(&self) -> CubeLut {
+        let path = match self.lut {
+            Some(ref path) => path,
+            None => return CubeLut::identity(2),
+        };
+        let lut = application_root_dir()
+            .map_err(|e| e.to_string())
+            .and_then(|root| {
+                std::fs::read_to_string(root.join("assets").join(path)).map_err(|e| e.to_string())
+            })
+            .and_then(|source| CubeLut::parse(&source).map_err(|e| e.to_string()));
+        match lut {
+            Ok(lut) => lut,
+            Err(e) => {
+                log::warn!("Failed to load colour grading lut {}: {}", path, e);
+                CubeLut::identity(2)
+            },
+        }
+    }
+}
+
+
 // uniform args
 #[derive(Clone, Copy, Debug, AsStd140)]
 #[repr(C, align(4))]
 pub struct TonemapUniformArgs {
     pub enabled: boolean,
     pub exposure: float,
+    pub grading: boolean,
+    pub lut_strength: float,
+    pub lut_domain_min: vec3,
+    pub lut_size: float,
+    pub lut_domain_max: vec3,
+    pub contrast: float,
+    pub white_balance: vec3,
+    pub saturation: float,
+    pub lift: vec3,
+    pub dither: boolean,
+    pub gamma: vec3,
+    pub frame: float,
+    pub gain: vec3,
 }
 
 // vertex args
@@ -100,6 +174,9 @@ pub struct Pipeline<B: hal::Backend> {
     image_sampler: Escape<Sampler<B>>,
     image_view: Escape<ImageView<B>>,
     vertex_buffer: Escape<Buffer<B>>,
+    lut: Texture<B>,
+    lut_size: f32,
+    lut_domain: ([f32; 3], [f32; 3]),
     settings: Settings,
 }
 
@@ -110,7 +187,7 @@ struct Settings {
 }
 
 impl Settings {
-    const UNIFORM_SIZE:u64 = size_of::<TonemapUniformArgs>() as u64;
+    const UNIFORM_SIZE:u64 = size_of::<<TonemapUniformArgs as AsStd140>::Std140>() as u64;
 
     #[inline]
     fn buffer_frame_size(&self) -> u64 {
@@ -157,7 +234,7 @@ where B: hal::Backend {
         factory: &mut Factory<B>,
         _world: &World,
     ) -> rendy::shader::ShaderSet<B> {
-        SHADERS.build(factory, Default::default()).unwrap()
+        crate::render::shader::shader_set(factory, "fsquad.vert", "tonemap.frag")
     }
 
     fn layout(&self) -> Layout {
@@ -178,6 +255,13 @@ where B: hal::Backend {
                         stage_flags: hal::pso::ShaderStageFlags::FRAGMENT,
                         immutable_samplers: false,
                     },
+                    hal::pso::DescriptorSetLayoutBinding {
+                        binding: 2,
+                        ty: hal::pso::DescriptorType::CombinedImageSampler,
+                        count: 1,
+                        stage_flags: hal::pso::ShaderStageFlags::FRAGMENT,
+                        immutable_samplers: false,
+                    },
                 ],
             }],
             push_constants: Vec::new(),
@@ -188,8 +272,8 @@ where B: hal::Backend {
         self,
         ctx: &GraphContext<B>,
         factory: &mut Factory<B>,
-        _queue: QueueId,
-        _world: &World,
+        queue: QueueId,
+        world: &World,
         buffers: Vec<NodeBuffer>,
         images: Vec<NodeImage>,
         set_layouts: &[RendyHandle<DescriptorSetLayout<B>>],
@@ -212,7 +296,7 @@ where B: hal::Backend {
                     },
                     hal::pso::DescriptorRangeDesc {
                         ty: hal::pso::DescriptorType::CombinedImageSampler,
-                        count: frames,
+                        count: frames * 2,
                     },
                 ],
                 hal::pso::DescriptorPoolCreateFlags::empty(),
@@ -243,13 +327,41 @@ where B: hal::Backend {
                 image_handle.clone(),
                 ImageViewInfo {
                     view_kind: resource::ViewKind::D2,
-                    format: hal::format::Format::Rgba32Sfloat, //Rgba8Unorm,
+                    format: image_handle.format(),
                     swizzle: hal::format::Swizzle::NO,
                     range: images[0].range.clone(),
                 },
             )
             .expect("Could not create input image view");
 
+        // upload the colour grading lut as a 3D texture
+        let cube = world.read_resource::<ColorGradingSettings>().load_lut();
+        let lut_size = cube.size as u32;
+        let lut_data = cube.to_rgba();
+        let lut_bytes = unsafe {
+            std::slice::from_raw_parts(
+                lut_data.as_ptr() as *const u8,
+                lut_data.len() * size_of::<[f32; 4]>(),
+            )
+        };
+        let lut = TextureBuilder::new()
+            .with_raw_data(lut_bytes, hal::format::Format::Rgba32Sfloat)
+            .with_data_width(lut_size)
+            .with_data_height(lut_size)
+            .with_kind(hal::image::Kind::D3(lut_size, lut_size, lut_size))
+            .with_view_kind(resource::ViewKind::D3)
+            .with_sampler_info(SamplerInfo::new(Linear, WrapMode::Clamp))
+            .build(
+                ImageState {
+                    queue,
+                    stage: hal::pso::PipelineStage::FRAGMENT_SHADER,
+                    access: hal::image::Access::SHADER_READ,
+                    layout: hal::image::Layout::ShaderReadOnlyOptimal,
+                },
+                factory,
+            )
+            .map_err(|e| failure::format_err!("Failed to build the colour grading lut: {:?}", e))?;
+
         let buffer = factory
             .create_buffer(
                 BufferInfo {
@@ -286,6 +398,16 @@ where B: hal::Backend {
                             hal::image::Layout::ShaderReadOnlyOptimal,
                             image_sampler.raw()
                         )),
+                    },
+                    hal::pso::DescriptorSetWrite {
+                        set: &set,
+                        binding: 2,
+                        array_offset: 0,
+                        descriptors: Some(hal::pso::Descriptor::CombinedImageSampler(
+                            lut.view().raw(),
+                            hal::image::Layout::ShaderReadOnlyOptimal,
+                            lut.sampler().raw()
+                        )),
                     }
                 ]);
                 sets.push(set);
@@ -326,6 +448,9 @@ where B: hal::Backend {
             descriptor_pool,
             settings,
             vertex_buffer,
+            lut,
+            lut_size: lut_size as f32,
+            lut_domain: (cube.domain_min, cube.domain_max),
         })
     }
 }
@@ -344,8 +469,9 @@ where
         index: usize,
         world: &World,
     ) -> PrepareResult {
-        let dimensions = world.read_resource::<ScreenDimensions>();
         let tonemap_settings = world.read_resource::<TonemapSettings>();
+        let grading = world.read_resource::<ColorGradingSettings>();
+        let time = world.read_resource::<Time>();
 
         // write to the uniform
         unsafe {
@@ -356,6 +482,20 @@ where
                     &[TonemapUniformArgs {
                         enabled: tonemap_settings.enabled.into(),
                         exposure: tonemap_settings.exposure.into(),
+                        grading: grading.enabled.into(),
+                        lut_strength: grading.lut_strength.into(),
+                        lut_domain_min: self.lut_domain.0.into(),
+                        lut_size: self.lut_size.into(),
+                        lut_domain_max: self.lut_domain.1.into(),
+                        contrast: grading.contrast.into(),
+                        white_balance: grading.white_balance().into(),
+                        saturation: grading.saturation.into(),
+                        lift: grading.lift.into(),
+                        dither: grading.dither.into(),
+                        gamma: grading.gamma.into(),
+                        // wrapped to keep the noise seed precise
+                        frame: ((time.frame_number() % 64) as f32).into(),
+                        gain: grading.gain.into(),
                     }.std140()],
                 )
                 .unwrap()
@@ -391,4 +531,16 @@ where
             factory.destroy_descriptor_pool(self.descriptor_pool);
         }
     }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uniform_matches_the_shader() {
+        assert_uniform_layout!(TonemapUniformArgs, "tonemap.frag", Settings::UNIFORM_SIZE, [
+            enabled, exposure, grading, lut_strength, lut_domain_min, lut_size, lut_domain_max, contrast,
+            white_balance, saturation, lift, dither, gamma, frame, gain
+        ]);
+    }
 }
\ No newline at end of file
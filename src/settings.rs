@@ -0,0 +1,286 @@
+use amethyst::{
+    config::Config,
+    controls::HideCursor,
+    core::{
+        frame_limiter::{FrameLimiter, FrameRateLimitStrategy},
+        shrev::{EventChannel, ReaderId},
+        HiddenPropagate,
+    },
+    derive::SystemDesc,
+    ecs::prelude::{ Read, ReadExpect, ReadStorage, System, SystemData, Write, WriteStorage },
+    input::{InputEvent, StringBindings},
+    ui::{UiEvent, UiEventType, UiFinder, UiText, UiTransform},
+    window::{DisplayConfig, MonitorIdent, Window},
+    winit::dpi::LogicalSize,
+};
+use serde::{Deserialize, Serialize};
+use std::{path::PathBuf, time::Duration};
+use crate::render::fxaa::FxaaSettings;
+use crate::render::ssaa::{AntiAliasing, AntiAliasingSettings};
+use crate::render::tonemap::TonemapSettings;
+
+// graphics settings resource, loaded from and saved to config/graphics.ron
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct GraphicsSettings {
+    pub anti_aliasing: AntiAliasing,
+    pub tonemap: TonemapSettings,
+    // frames per second, 0 is uncapped
+    pub frame_cap: u32,
+}
+
+impl Default for GraphicsSettings {
+    fn default() -> Self {
+        Self { anti_aliasing:AntiAliasing::default(), tonemap:TonemapSettings::default(), frame_cap:144 }
+    }
+}
+
+impl GraphicsSettings {
+    pub fn frame_limit(&self) -> (FrameRateLimitStrategy, u32) {
+        if self.frame_cap == 0 {
+            // this eats all available cpu cycles
+            (FrameRateLimitStrategy::Unlimited, 9999)
+        } else {
+            // sleeping the whole frame overshoots, so the last 2ms are yielded
+            (FrameRateLimitStrategy::SleepAndYield(Duration::from_millis(2)), self.frame_cap)
+        }
+    }
+}
+
+const RESOLUTIONS: [(u32, u32); 6] = [(800, 600), (1280, 720), (1600, 900), (1920, 1080), (2560, 1440), (3840, 2160)];
+const FRAME_CAPS: [u32; 6] = [30, 60, 120, 144, 240, 0];
+const MIN_EXPOSURE: f32 = 0.1;
+const MAX_EXPOSURE: f32 = 8.0;
+
+// rows of the settings menu, their ui ids are prefixed with settings_
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Setting {
+    AntiAliasing,
+    Tonemapping,
+    Exposure,
+    Vsync,
+    Resolution,
+    Fullscreen,
+    FrameCap,
+}
+
+const SETTINGS: [(Setting, &str); 7] = [
+    (Setting::AntiAliasing, "settings_anti_aliasing"),
+    (Setting::Tonemapping, "settings_tonemapping"),
+    (Setting::Exposure, "settings_exposure"),
+    (Setting::Vsync, "settings_vsync"),
+    (Setting::Resolution, "settings_resolution"),
+    (Setting::Fullscreen, "settings_fullscreen"),
+    (Setting::FrameCap, "settings_frame_cap"),
+];
+
+// ui id prefixes of the rows, top to bottom
+pub fn rows() -> impl Iterator<Item = &'static str> {
+    SETTINGS.iter().map(|(_, prefix)| *prefix)
+}
+
+// settings menu, applies changes live and writes them back to the config files
+#[derive(SystemDesc)]
+#[system_desc(name(SettingsSystemDesc))]
+pub struct SettingsSystem {
+    config_dir: PathBuf,
+    #[system_desc(event_channel_reader)]
+    input_reader: ReaderId<InputEvent<StringBindings>>,
+    #[system_desc(event_channel_reader)]
+    ui_reader: ReaderId<UiEvent>,
+    // what's in config/graphics.ron, the hotkeys change the settings too so this is compared every frame
+    #[system_desc(skip)]
+    saved: Option<GraphicsSettings>,
+    // the value labels need updating
+    #[system_desc(skip)]
+    dirty: bool,
+}
+
+impl SettingsSystem {
+    pub fn new(
+        config_dir: PathBuf,
+        input_reader: ReaderId<InputEvent<StringBindings>>,
+        ui_reader: ReaderId<UiEvent>,
+    ) -> Self {
+        Self { config_dir, input_reader, ui_reader, saved:None, dirty:true }
+    }
+}
+
+impl<'s> System<'s> for SettingsSystem {
+    type SystemData = (
+        Read<'s, EventChannel<InputEvent<StringBindings>>>,
+        Read<'s, EventChannel<UiEvent>>,
+        ReadStorage<'s, UiTransform>,
+        UiFinder<'s>,
+        WriteStorage<'s, UiText>,
+        WriteStorage<'s, HiddenPropagate>,
+        Write<'s, HideCursor>,
+        Write<'s, AntiAliasingSettings>,
+        Write<'s, FxaaSettings>,
+        Write<'s, TonemapSettings>,
+        Write<'s, GraphicsSettings>,
+        Write<'s, DisplayConfig>,
+        Write<'s, FrameLimiter>,
+        ReadExpect<'s, Window>,
+    );
+
+    fn run(&mut self, (input_events, ui_events, transforms, ui_finder, mut ui_texts, mut hidden, mut hide_cursor, mut anti_aliasing, mut fxaa_settings, mut tonemap_settings, mut graphics, mut display, mut frame_limiter, window): Self::SystemData) {
+        // open and close with the settings action, the cursor is released while it's open
+        let container = ui_finder.find("settings_container");
+        let mut toggle = input_events
+            .read(&mut self.input_reader)
+            .filter(|event| matches!(event, InputEvent::ActionPressed(action) if action == "settings"))
+            .count() % 2 == 1;
+
+        let mut display_changed = false;
+        for event in ui_events.read(&mut self.ui_reader) {
+            if event.event_type != UiEventType::Click {
+                continue;
+            }
+            let id = match transforms.get(event.target) {
+                Some(transform) => transform.id.as_str(),
+                None => continue,
+            };
+            if id == "settings_close" {
+                toggle = !toggle;
+                continue;
+            }
+            // every row has a previous and next button around its value
+            let (row, direction) = if id.ends_with("_prev") {
+                (&id[..id.len() - "_prev".len()], -1)
+            } else if id.ends_with("_next") {
+                (&id[..id.len() - "_next".len()], 1)
+            } else {
+                continue;
+            };
+            let setting = match SETTINGS.iter().find(|(_, prefix)| *prefix == row) {
+                Some((setting, _)) => *setting,
+                None => continue,
+            };
+
+            match setting {
+                Setting::AntiAliasing => {
+                    anti_aliasing.mode = if direction > 0 { anti_aliasing.mode.next() } else { anti_aliasing.mode.previous() };
+                    fxaa_settings.enabled = anti_aliasing.mode.fxaa();
+                },
+                Setting::Tonemapping => {
+                    tonemap_settings.enabled = !tonemap_settings.enabled;
+                },
+                Setting::Exposure => {
+                    let exposure = tonemap_settings.exposure + 0.1 * direction as f32;
+                    tonemap_settings.exposure = exposure.max(MIN_EXPOSURE).min(MAX_EXPOSURE);
+                },
+                Setting::Vsync => {
+                    // the swapchain present mode is picked when the window is created
+                    display.vsync = !display.vsync;
+                    display_changed = true;
+                },
+                Setting::Resolution => {
+                    let (width, height) = step_resolution(display.dimensions.unwrap_or(RESOLUTIONS[1]), direction);
+                    window.set_inner_size(LogicalSize::new(width as f64, height as f64));
+                    display.dimensions = Some((width, height));
+                    display_changed = true;
+                },
+                Setting::Fullscreen => {
+                    display.fullscreen = match display.fullscreen {
+                        Some(_) => None,
+                        None => Some(MonitorIdent::from_primary(&*window)),
+                    };
+                    window.set_fullscreen(display.fullscreen.as_ref().map(|monitor| monitor.monitor_id(&*window)));
+                    display_changed = true;
+                },
+                Setting::FrameCap => {
+                    let index = FRAME_CAPS.iter().position(|cap| *cap == graphics.frame_cap).unwrap_or(3) as i32;
+                    graphics.frame_cap = FRAME_CAPS[(index + direction).max(0).min(FRAME_CAPS.len() as i32 - 1) as usize];
+                    let (strategy, fps) = graphics.frame_limit();
+                    frame_limiter.set_rate(strategy, fps);
+                },
+            }
+            self.dirty = true;
+        }
+
+        if toggle {
+            if let Some(entity) = container {
+                let open = match hidden.get(entity) {
+                    Some(_) => { hidden.remove(entity); true },
+                    None => { hidden.insert(entity, HiddenPropagate::new()).expect("Failed to create HiddenPropagate component"); false },
+                };
+                hide_cursor.hide = !open;
+            }
+        }
+
+        // persist whatever changed, from this menu or from the hotkeys
+        graphics.anti_aliasing = anti_aliasing.mode;
+        graphics.tonemap = tonemap_settings.clone();
+        match &self.saved {
+            None => self.saved = Some(graphics.clone()),
+            Some(saved) if *saved != *graphics => {
+                if let Err(error) = graphics.write(self.config_dir.join("graphics.ron")) {
+                    log::error!("Failed to save the graphics settings: {}", error);
+                }
+                self.saved = Some(graphics.clone());
+                self.dirty = true;
+            },
+            _ => (),
+        }
+        if display_changed {
+            if let Err(error) = display.write(self.config_dir.join("display.ron")) {
+                log::error!("Failed to save the display settings: {}", error);
+            }
+        }
+
+        if self.dirty {
+            for (setting, prefix) in SETTINGS.iter() {
+                let value = match setting {
+                    Setting::AntiAliasing => anti_aliasing_name(anti_aliasing.mode).to_string(),
+                    Setting::Tonemapping => on_off(tonemap_settings.enabled).to_string(),
+                    Setting::Exposure => format!("{:.1}", tonemap_settings.exposure),
+                    Setting::Vsync => on_off(display.vsync).to_string(),
+                    Setting::Resolution => match display.dimensions {
+                        Some((width, height)) => format!("{}x{}", width, height),
+                        None => "Default".to_string(),
+                    },
+                    Setting::Fullscreen => on_off(display.fullscreen.is_some()).to_string(),
+                    Setting::FrameCap => match graphics.frame_cap {
+                        0 => "Unlimited".to_string(),
+                        cap => cap.to_string(),
+                    },
+                };
+                let text = ui_finder.find(&format!("{}_value", prefix)).and_then(|entity| ui_texts.get_mut(entity));
+                match text {
+                    Some(text) => text.text = value,
+                    // the ui hasn't loaded yet, try again next frame
+                    None => return,
+                }
+            }
+            self.dirty = false;
+        }
+    }
+}
+
+fn anti_aliasing_name(mode: AntiAliasing) -> &'static str {
+    match mode {
+        AntiAliasing::Off => "Off",
+        AntiAliasing::Fxaa => "FXAA",
+        AntiAliasing::Ssaa2 => "SSAA 2x",
+        AntiAliasing::Ssaa4 => "SSAA 4x",
+        AntiAliasing::Ssaa8 => "SSAA 8x",
+        AntiAliasing::SsaaFxaa => "SSAA 4x + FXAA",
+        AntiAliasing::Taa => "TAA",
+    }
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value { "On" } else { "Off" }
+}
+
+// next resolution preset up or down from the current size, which doesn't have to be a preset
+fn step_resolution(current: (u32, u32), direction: i32) -> (u32, u32) {
+    let pixels = |(width, height): (u32, u32)| width * height;
+    let preset = if direction > 0 {
+        RESOLUTIONS.iter().find(|r| pixels(**r) > pixels(current))
+    } else {
+        RESOLUTIONS.iter().rev().find(|r| pixels(**r) < pixels(current))
+    };
+    preset.copied().unwrap_or(current)
+}